@@ -0,0 +1,778 @@
+use crate::queue::Retention;
+use crate::sensors::{Calibration, DriverInfo};
+use piweather_common::errors::PiWeatherError;
+use piweather_common::UnitSystem;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::Table;
+use url::Url;
+
+pub const DEFAULT_STATION_NAME: &str = "piweather";
+pub const DEFAULT_BACKLOG: usize = 16;
+pub const DEFAULT_BUS_NAME: &str = "default";
+pub const DEFAULT_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_METRICS_PATH: &str = "/metrics";
+pub const DEFAULT_BUFFER_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Highest address of the 7 bits I2C addressing
+const I2C_MAX_ADDRESS: u16 = 0x7F;
+
+/// Identity of the weather station, attached to the readouts it pushes
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StationConfig {
+    #[serde(default = "StationConfig::default_name")]
+    pub name: String,
+
+    /// Decimal degrees
+    #[serde(default)]
+    pub latitude: Option<f64>,
+
+    /// Decimal degrees
+    #[serde(default)]
+    pub longitude: Option<f64>,
+
+    /// Meters above sea level
+    #[serde(default)]
+    pub altitude: Option<f32>,
+}
+
+impl StationConfig {
+    fn default_name() -> String {
+        DEFAULT_STATION_NAME.to_string()
+    }
+}
+
+impl Default for StationConfig {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            latitude: None,
+            longitude: None,
+            altitude: None,
+        }
+    }
+}
+
+/// I2C bus sensors are attached to
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BusConfig {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SensorConfig {
+    /// Name of the driver, as registered in the `SensorRegistry`
+    pub driver: String,
+
+    /// Name identifying the sensor within the station, the driver's name if not specified
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Name of the bus the sensor is attached to, optional if a single bus is declared
+    #[serde(default)]
+    pub bus: Option<String>,
+
+    /// Address of the sensor on the bus, the driver's default one if not specified
+    #[serde(default)]
+    pub address: Option<u16>,
+
+    /// Number of seconds between two readouts
+    #[serde(default = "SensorConfig::default_interval")]
+    pub interval: u64,
+
+    #[serde(default)]
+    pub calibration: Calibration,
+
+    /// Name of the sensor of the station measuring the ambient conditions (temperature and
+    /// humidity, or pressure) the readouts are compensated for, if the driver supports it
+    #[serde(default)]
+    pub compensation: Option<String>,
+
+    /// JSON file where the state learned by the sensor is kept across restarts,
+    /// if the driver has any
+    #[serde(default)]
+    pub state: Option<PathBuf>,
+
+    /// Driver specific settings, checked when the sensor is opened
+    #[serde(default)]
+    pub settings: Table,
+}
+
+impl SensorConfig {
+    fn default_interval() -> u64 {
+        DEFAULT_INTERVAL_SECS
+    }
+
+    /// Sensor handled by `driver` at its default address, on the only bus of the station
+    pub fn new<S: Into<String>>(driver: S) -> Self {
+        Self {
+            driver: driver.into(),
+            name: None,
+            bus: None,
+            address: None,
+            interval: DEFAULT_INTERVAL_SECS,
+            calibration: Calibration::default(),
+            compensation: None,
+            state: None,
+            settings: Table::new(),
+        }
+    }
+
+    /// Name identifying the sensor within the station
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.driver)
+    }
+}
+
+/// On-disk queue keeping the payloads a sink failed to forward
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BufferConfig {
+    /// Directory holding the queue, dedicated to the sink
+    pub path: PathBuf,
+
+    /// Maximum number of bytes of buffered payloads, the oldest ones being discarded first
+    #[serde(default = "BufferConfig::default_max_size")]
+    pub max_size: u64,
+
+    /// Number of seconds after which buffered payloads are discarded
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+impl BufferConfig {
+    fn default_max_size() -> u64 {
+        DEFAULT_BUFFER_MAX_SIZE
+    }
+
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_size: self.max_size,
+            max_age: self.max_age.map(Duration::from_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkConfig {
+    /// URI where to push the readouts
+    pub destination: String,
+
+    /// Persist the payloads the sink fails to forward, replaying them once it recovers
+    #[serde(default)]
+    pub buffer: Option<BufferConfig>,
+}
+
+impl SinkConfig {
+    pub fn new<S: Into<String>>(destination: S) -> Self {
+        Self {
+            destination: destination.into(),
+            buffer: None,
+        }
+    }
+}
+
+/// Prometheus exporter serving the latest readouts and the health of the agent
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address the HTTP endpoint listens on
+    pub listen: SocketAddr,
+
+    /// Path of the HTTP endpoint
+    #[serde(default = "MetricsConfig::default_path")]
+    pub path: String,
+}
+
+impl MetricsConfig {
+    fn default_path() -> String {
+        DEFAULT_METRICS_PATH.to_string()
+    }
+
+    pub fn new(listen: SocketAddr) -> Self {
+        Self {
+            listen,
+            path: Self::default_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub station: StationConfig,
+
+    /// Maximum number of queued readouts before blocking
+    #[serde(default = "Config::default_backlog")]
+    pub backlog: usize,
+
+    /// Units readouts are converted to before reaching the sinks, as reported by the sensors if not specified
+    #[serde(default)]
+    pub units: Option<UnitSystem>,
+
+    #[serde(default)]
+    pub buses: Vec<BusConfig>,
+
+    #[serde(default)]
+    pub sensors: Vec<SensorConfig>,
+
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+}
+
+impl Config {
+    fn default_backlog() -> usize {
+        DEFAULT_BACKLOG
+    }
+
+    /// Read the configuration from a TOML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, PiWeatherError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|err| {
+            PiWeatherError::Io(format!("Failed to read {}: {}", path.display(), err))
+        })?;
+
+        content.parse().map_err(|err| match err {
+            PiWeatherError::InvalidConfiguration(msg) => {
+                PiWeatherError::InvalidConfiguration(format!("{}: {}", path.display(), msg))
+            }
+            err => err,
+        })
+    }
+
+    /// Bus the sensor is attached to, if it can be resolved
+    pub fn bus_of(&self, sensor: &SensorConfig) -> Option<&BusConfig> {
+        match &sensor.bus {
+            Some(name) => self.buses.iter().find(|bus| &bus.name == name),
+            None if self.buses.len() == 1 => self.buses.first(),
+            None => None,
+        }
+    }
+
+    /// Check the configuration is consistent, `drivers` being the names of the available
+    /// sensor drivers. The error points at the first offending key.
+    pub fn validate(&self, drivers: &[DriverInfo]) -> Result<(), PiWeatherError> {
+        let invalid = |key: String, reason: String| {
+            Err(PiWeatherError::InvalidConfiguration(format!(
+                "{}: {}",
+                key, reason
+            )))
+        };
+
+        if self.station.name.trim().is_empty() {
+            return invalid("station.name".into(), "must not be empty".into());
+        }
+
+        if let Some(latitude) = self.station.latitude {
+            if !(-90.0..=90.0).contains(&latitude) {
+                return invalid(
+                    "station.latitude".into(),
+                    format!("{} is not within [-90, 90]", latitude),
+                );
+            }
+        }
+
+        if let Some(longitude) = self.station.longitude {
+            if !(-180.0..=180.0).contains(&longitude) {
+                return invalid(
+                    "station.longitude".into(),
+                    format!("{} is not within [-180, 180]", longitude),
+                );
+            }
+        }
+
+        if self.backlog == 0 {
+            return invalid("backlog".into(), "must be greater than 0".into());
+        }
+
+        let mut buses = HashSet::with_capacity(self.buses.len());
+        for (index, bus) in self.buses.iter().enumerate() {
+            if !buses.insert(bus.name.as_str()) {
+                return invalid(
+                    format!("buses[{}].name", index),
+                    format!("bus \"{}\" is declared more than once", bus.name),
+                );
+            }
+        }
+
+        if self.sensors.is_empty() {
+            return invalid("sensors".into(), "at least one sensor is required".into());
+        }
+
+        let mut names = HashSet::with_capacity(self.sensors.len());
+        let mut states = HashSet::with_capacity(self.sensors.len());
+        for (index, sensor) in self.sensors.iter().enumerate() {
+            if !names.insert(sensor.name()) {
+                return invalid(
+                    format!("sensors[{}].name", index),
+                    format!("sensor \"{}\" is declared more than once", sensor.name()),
+                );
+            }
+
+            let Some(driver) = drivers.iter().find(|driver| driver.name == sensor.driver) else {
+                let available = drivers.iter().map(|driver| driver.name);
+                return invalid(
+                    format!("sensors[{}].driver", index),
+                    format!(
+                        "unknown driver \"{}\" (available: {})",
+                        sensor.driver,
+                        available.collect::<Vec<_>>().join(", ")
+                    ),
+                );
+            };
+
+            if self.bus_of(sensor).is_none() {
+                let reason = match &sensor.bus {
+                    Some(name) => format!("unknown bus \"{}\"", name),
+                    None if self.buses.is_empty() => "no bus is declared".to_string(),
+                    None => "must be specified when several buses are declared".to_string(),
+                };
+                return invalid(format!("sensors[{}].bus", index), reason);
+            }
+
+            if let Some(address) = sensor.address.filter(|address| *address > I2C_MAX_ADDRESS) {
+                return invalid(
+                    format!("sensors[{}].address", index),
+                    format!("0x{:X} is not a 7 bits I2C address", address),
+                );
+            }
+
+            if sensor.interval == 0 {
+                return invalid(
+                    format!("sensors[{}].interval", index),
+                    "must be greater than 0".into(),
+                );
+            }
+
+            let calibration = &sensor.calibration;
+            for (modality, adjustment) in [
+                ("temperature", &calibration.temperature),
+                ("humidity", &calibration.humidity),
+                ("pressure", &calibration.pressure),
+            ] {
+                if !adjustment.offset.is_finite() || !adjustment.scale.is_normal() {
+                    return invalid(
+                        format!("sensors[{}].calibration.{}", index, modality),
+                        "offset must be finite and scale non-zero".into(),
+                    );
+                }
+            }
+
+            if let Some(source) = &sensor.compensation {
+                if source == sensor.name() {
+                    return invalid(
+                        format!("sensors[{}].compensation", index),
+                        "must not refer to the sensor itself".into(),
+                    );
+                }
+
+                let Some(other) = self.sensors.iter().find(|other| other.name() == source) else {
+                    return invalid(
+                        format!("sensors[{}].compensation", index),
+                        format!("unknown sensor \"{}\"", source),
+                    );
+                };
+
+                if driver.compensated_for.is_empty() {
+                    return invalid(
+                        format!("sensors[{}].compensation", index),
+                        format!("{} readouts can't be compensated", driver.name),
+                    );
+                }
+
+                // Unknown drivers are reported along with their own sensor
+                let measures = drivers
+                    .iter()
+                    .find(|driver| driver.name == other.driver)
+                    .map_or(&[][..], |driver| driver.measures);
+                if let Some(missing) = driver
+                    .compensated_for
+                    .iter()
+                    .find(|ambient| !measures.contains(ambient))
+                {
+                    return invalid(
+                        format!("sensors[{}].compensation", index),
+                        format!(
+                            "\"{}\" doesn't measure the {} {} is compensated for",
+                            source,
+                            missing.as_str(),
+                            driver.name
+                        ),
+                    );
+                }
+            }
+
+            if let Some(state) = &sensor.state {
+                if !states.insert(state.as_path()) {
+                    return invalid(
+                        format!("sensors[{}].state", index),
+                        format!("{} is already used by another sensor", state.display()),
+                    );
+                }
+            }
+        }
+
+        if self.sinks.is_empty() && self.metrics.is_none() {
+            return invalid(
+                "sinks".into(),
+                "at least one sink is required unless metrics are exported".into(),
+            );
+        }
+
+        let mut buffers = HashSet::with_capacity(self.sinks.len());
+        for (index, sink) in self.sinks.iter().enumerate() {
+            if let Err(err) = Url::parse(&sink.destination) {
+                return invalid(
+                    format!("sinks[{}].destination", index),
+                    format!("not a valid URI: {}", err),
+                );
+            }
+
+            if let Some(buffer) = &sink.buffer {
+                if !buffers.insert(buffer.path.as_path()) {
+                    return invalid(
+                        format!("sinks[{}].buffer.path", index),
+                        format!("{} is already used by another sink", buffer.path.display()),
+                    );
+                }
+
+                if buffer.max_size == 0 {
+                    return invalid(
+                        format!("sinks[{}].buffer.max_size", index),
+                        "must be greater than 0".into(),
+                    );
+                }
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            if !metrics.path.starts_with('/') {
+                return invalid(
+                    "metrics.path".into(),
+                    format!("\"{}\" must start with a /", metrics.path),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            station: StationConfig::default(),
+            backlog: DEFAULT_BACKLOG,
+            units: None,
+            buses: Vec::new(),
+            sensors: Vec::new(),
+            sinks: Vec::new(),
+            metrics: None,
+        }
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = PiWeatherError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        toml::from_str(content).map_err(|err| PiWeatherError::InvalidConfiguration(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        BufferConfig, Config, MetricsConfig, DEFAULT_BACKLOG, DEFAULT_BUFFER_MAX_SIZE,
+        DEFAULT_INTERVAL_SECS, DEFAULT_METRICS_PATH,
+    };
+    use crate::sensors::{Ambient, DriverInfo};
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::UnitSystem;
+    use std::path::Path;
+    use std::time::Duration;
+
+    const DRIVERS: [DriverInfo; 5] = [
+        DriverInfo {
+            name: "am2315",
+            measures: &[Ambient::Temperature, Ambient::Humidity],
+            compensated_for: &[],
+        },
+        DriverInfo {
+            name: "bme280",
+            measures: &[Ambient::Temperature, Ambient::Humidity, Ambient::Pressure],
+            compensated_for: &[],
+        },
+        DriverInfo {
+            name: "bmp280",
+            measures: &[Ambient::Temperature, Ambient::Pressure],
+            compensated_for: &[],
+        },
+        DriverInfo {
+            name: "pmsa003",
+            measures: &[],
+            compensated_for: &[],
+        },
+        DriverInfo {
+            name: "sgp41",
+            measures: &[],
+            compensated_for: &[Ambient::Humidity, Ambient::Temperature],
+        },
+    ];
+
+    fn assert_invalid(config: &Config, key: &str) {
+        match config.validate(&DRIVERS) {
+            Err(PiWeatherError::InvalidConfiguration(msg)) => {
+                assert!(msg.starts_with(key), "{} doesn't point at {}", msg, key)
+            }
+            other => panic!("Expected an invalid configuration, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_example() {
+        let config: Config = include_str!("../piweather.example.toml").parse().unwrap();
+        assert!(config.validate(&DRIVERS).is_ok());
+
+        assert_eq!(config.station.name, "garden");
+        assert_eq!(config.backlog, 32);
+        assert_eq!(config.units, Some(UnitSystem::Metric));
+        assert_eq!(config.buses.len(), 1);
+        assert_eq!(config.buses[0].path, Path::new("/dev/i2c-1"));
+
+        assert_eq!(config.sensors.len(), 4);
+        assert_eq!(config.sensors[0].driver, "am2315");
+        assert_eq!(config.sensors[0].name(), "outdoor");
+        assert_eq!(config.sensors[0].address, Some(0x5C));
+        assert_eq!(config.sensors[0].calibration.temperature.offset, -0.4);
+        assert_eq!(config.sensors[1].driver, "bme280");
+        assert_eq!(config.sensors[1].name(), "shelter");
+        assert_eq!(config.sensors[1].address, Some(0x76));
+        assert_eq!(config.sensors[1].settings["filter"].as_str(), Some("x4"));
+        assert_eq!(config.sensors[2].driver, "pmsa003");
+        assert_eq!(config.sensors[2].name(), "pmsa003");
+        assert_eq!(config.sensors[2].interval, 30);
+        assert!(config.sensors[2].calibration.is_identity());
+        assert!(config.sensors[2].settings.is_empty());
+        assert_eq!(config.sensors[3].driver, "sgp41");
+        assert_eq!(config.sensors[3].compensation.as_deref(), Some("shelter"));
+        assert_eq!(
+            config.sensors[3].state.as_deref(),
+            Some(Path::new("/var/lib/piweather/sgp41.json"))
+        );
+
+        assert_eq!(config.sinks.len(), 2);
+        assert_eq!(config.sinks[0].buffer, None);
+        let buffer = config.sinks[1].buffer.as_ref().unwrap();
+        assert_eq!(buffer.path, Path::new("/var/lib/piweather/mqtt"));
+        assert_eq!(buffer.max_size, DEFAULT_BUFFER_MAX_SIZE);
+        assert_eq!(
+            buffer.retention().max_age,
+            Some(Duration::from_secs(604800))
+        );
+        assert_eq!(config.metrics, None);
+        assert_eq!(
+            config
+                .bus_of(&config.sensors[1])
+                .map(|bus| bus.name.as_str()),
+            Some("main")
+        );
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let config: Config = r#"
+            [[buses]]
+            name = "main"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            driver = "am2315"
+
+            [[sinks]]
+            destination = "stdout:"
+        "#
+        .parse()
+        .unwrap();
+
+        assert!(config.validate(&DRIVERS).is_ok());
+        assert_eq!(config.station.name, "piweather");
+        assert_eq!(config.backlog, DEFAULT_BACKLOG);
+        assert_eq!(config.units, None);
+        assert_eq!(config.sensors[0].interval, DEFAULT_INTERVAL_SECS);
+        assert_eq!(config.sensors[0].address, None);
+    }
+
+    #[test]
+    fn parse_metrics_exporter() {
+        let config: Config = r#"
+            [[buses]]
+            name = "main"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            driver = "am2315"
+
+            [metrics]
+            listen = "0.0.0.0:9100"
+        "#
+        .parse()
+        .unwrap();
+
+        assert!(config.validate(&DRIVERS).is_ok());
+        let metrics = config.metrics.unwrap();
+        assert_eq!(metrics.listen.port(), 9100);
+        assert_eq!(metrics.path, DEFAULT_METRICS_PATH);
+    }
+
+    #[test]
+    fn parse_unknown_key() {
+        let err = r#"
+            [[sensors]]
+            driver = "am2315"
+            adress = 0x5C
+        "#
+        .parse::<Config>()
+        .unwrap_err();
+
+        match err {
+            PiWeatherError::InvalidConfiguration(msg) => assert!(msg.contains("adress")),
+            err => panic!("Unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_invalid_address() {
+        assert!(r#"
+            [[sensors]]
+            driver = "am2315"
+            address = 70000
+        "#
+        .parse::<Config>()
+        .is_err());
+    }
+
+    #[test]
+    fn validate_errors() {
+        let valid: Config = r#"
+            [[buses]]
+            name = "main"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            driver = "am2315"
+
+            [[sinks]]
+            destination = "stdout:"
+        "#
+        .parse()
+        .unwrap();
+
+        let mut config = valid.clone();
+        config.sensors[0].driver = "dht22".into();
+        assert_invalid(&config, "sensors[0].driver");
+
+        let mut config = valid.clone();
+        config.sensors[0].bus = Some("aux".into());
+        assert_invalid(&config, "sensors[0].bus");
+
+        let mut config = valid.clone();
+        config.buses.push(config.buses[0].clone());
+        assert_invalid(&config, "buses[1].name");
+
+        let mut config = valid.clone();
+        config.buses[0].name = "aux".into();
+        config.buses.push(valid.buses[0].clone());
+        assert_invalid(&config, "sensors[0].bus");
+
+        let mut config = valid.clone();
+        config.sensors.push(config.sensors[0].clone());
+        assert_invalid(&config, "sensors[1].name");
+
+        let mut config = valid.clone();
+        config.sensors[0].address = Some(0x80);
+        assert_invalid(&config, "sensors[0].address");
+
+        config.sensors[0].address = Some(0x7F);
+        assert!(config.validate(&DRIVERS).is_ok());
+
+        let mut config = valid.clone();
+        config.sensors[0].interval = 0;
+        assert_invalid(&config, "sensors[0].interval");
+
+        let mut config = valid.clone();
+        config.sensors[0].calibration.humidity.scale = 0.0;
+        assert_invalid(&config, "sensors[0].calibration.humidity");
+
+        let mut config = valid.clone();
+        config.sensors[0].compensation = Some("am2315".into());
+        assert_invalid(&config, "sensors[0].compensation");
+
+        config.sensors[0].compensation = Some("shelter".into());
+        assert_invalid(&config, "sensors[0].compensation");
+
+        let mut config = valid.clone();
+        config.sensors[0].state = Some("/var/lib/piweather/am2315.json".into());
+        config.sensors.push(config.sensors[0].clone());
+        config.sensors[1].name = Some("shelter".into());
+        assert_invalid(&config, "sensors[1].state");
+
+        config.sensors[1].state = None;
+        config.sensors[1].compensation = Some("am2315".into());
+        assert_invalid(&config, "sensors[1].compensation");
+
+        config.sensors[1].driver = "sgp41".into();
+        assert!(config.validate(&DRIVERS).is_ok());
+
+        config.sensors[0].driver = "pmsa003".into();
+        assert_invalid(&config, "sensors[1].compensation");
+
+        config.sensors[0].driver = "bmp280".into();
+        assert_invalid(&config, "sensors[1].compensation");
+
+        let mut config = valid.clone();
+        config.sinks.clear();
+        assert_invalid(&config, "sinks");
+
+        let mut config = valid.clone();
+        config.sinks[0].destination = "localhost".into();
+        assert_invalid(&config, "sinks[0].destination");
+
+        let mut config = valid.clone();
+        config.sinks[0].buffer = Some(BufferConfig {
+            path: "/var/lib/piweather/stdout".into(),
+            max_size: 0,
+            max_age: None,
+        });
+        assert_invalid(&config, "sinks[0].buffer.max_size");
+
+        config.sinks[0].buffer.as_mut().unwrap().max_size = 1024;
+        config.sinks.push(config.sinks[0].clone());
+        assert_invalid(&config, "sinks[1].buffer.path");
+
+        let mut config = valid.clone();
+        config.sinks.clear();
+        config.metrics = Some(MetricsConfig::new("0.0.0.0:9100".parse().unwrap()));
+        assert!(config.validate(&DRIVERS).is_ok());
+
+        config.metrics.as_mut().unwrap().path = "metrics".into();
+        assert_invalid(&config, "metrics.path");
+
+        let mut config = valid;
+        config.station.latitude = Some(123.0);
+        assert_invalid(&config, "station.latitude");
+    }
+}
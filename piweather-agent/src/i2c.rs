@@ -2,6 +2,8 @@ use crate::i2c::linux::LinuxI2CDeviceFactory;
 use i2cdev::core::I2CDevice;
 use piweather_common::errors::PiWeatherError;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::task::spawn_blocking;
 
 pub trait I2CDeviceFactory {
     type Device: I2CDevice + Sized;
@@ -11,6 +13,50 @@ pub trait I2CDeviceFactory {
     fn open(&self, address: u16) -> Result<Self::Device, PiWeatherError>;
 }
 
+/// Allow multiple sensors to be opened from the same bus
+impl<F: I2CDeviceFactory> I2CDeviceFactory for &F {
+    type Device = F::Device;
+
+    #[inline]
+    fn open(&self, address: u16) -> Result<Self::Device, PiWeatherError> {
+        (*self).open(address)
+    }
+}
+
+/// Run the transactions of a blocking `I2CDevice` on the blocking thread-pool,
+/// so the executor can make progress on other tasks while the bus is busy.
+pub struct AsyncI2CDevice<D> {
+    device: Arc<Mutex<D>>,
+}
+
+impl<D> AsyncI2CDevice<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    pub fn new(device: D) -> Self {
+        Self {
+            device: Arc::new(Mutex::new(device)),
+        }
+    }
+
+    /// Execute `transaction` with exclusive access to the underlying device
+    pub async fn transaction<F, R>(&self, transaction: F) -> Result<R, PiWeatherError>
+    where
+        F: FnOnce(&mut D) -> Result<R, PiWeatherError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let device = Arc::clone(&self.device);
+        spawn_blocking(move || {
+            let mut device = device
+                .lock()
+                .map_err(|_| PiWeatherError::I2CError("I2C device lock is poisoned".into()))?;
+            transaction(&mut device)
+        })
+        .await
+        .map_err(|err| PiWeatherError::I2CError(format!("I2C transaction aborted: {}", err)))?
+    }
+}
+
 #[cfg(target_os = "linux")]
 pub fn get_os_i2c_factory<P: AsRef<Path>>(
     fd: P,
@@ -47,8 +93,37 @@ pub mod linux {
 
         #[inline]
         fn open(&self, address: u16) -> Result<Self::Device, PiWeatherError> {
-            LinuxI2CDevice::new(&self.fd.as_ref(), address)
+            LinuxI2CDevice::new(self.fd.as_ref(), address)
                 .map_err(|err| PiWeatherError::I2CError(err.to_string()))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::i2c::AsyncI2CDevice;
+    use i2cdev::core::I2CDevice;
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+
+    #[tokio::test]
+    async fn async_transaction() {
+        let mut mock = MockI2CDevice::new();
+        mock.regmap.write_regs(0x10, &[0xCA, 0xFE]);
+
+        let device = AsyncI2CDevice::new(mock);
+        let data = device
+            .transaction(|device| {
+                let mut data = [0u8; 2];
+                device
+                    .write(&[0x10])
+                    .and_then(|_| device.read(&mut data))
+                    .map_err(|err| PiWeatherError::I2CError(err.to_string()))?;
+                Ok(data)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(data, [0xCA, 0xFE]);
+    }
+}
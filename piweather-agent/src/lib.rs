@@ -1,2 +1,7 @@
+pub mod config;
 pub mod i2c;
+pub mod metrics;
+pub mod polling;
+pub mod queue;
 pub mod sensors;
+pub mod sinks;
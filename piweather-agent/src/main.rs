@@ -1,35 +1,180 @@
 use clap::Parser;
+use piweather_agent::config::{
+    BusConfig, Config, MetricsConfig, SensorConfig, SinkConfig, DEFAULT_BUS_NAME,
+};
 use piweather_agent::i2c::get_os_i2c_factory;
-use piweather_agent::sensors::{Am2315, Sensor};
+use piweather_agent::metrics::{serve_metrics, Metrics};
+use piweather_agent::polling::poll_sensor;
+use piweather_agent::queue::DiskQueue;
+use piweather_agent::sensors::{Calibrated, Compensated, Persisted, SensorRegistry, Shared};
+use piweather_agent::sinks::{self, Buffered, Sink};
 use piweather_common::errors::PiWeatherError;
-use piweather_common::Payload;
+use piweather_common::{Payload, UnitSystem};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{channel, Receiver};
-use tracing::{debug, error, info};
-use tracing_subscriber;
+use tokio::sync::watch;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+const DEFAULT_SENSOR_DRIVER: &str = "am2315";
+
+/// Period of the ticks letting sinks deliver payloads held back when no new one comes
+const SINK_TICK_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[arg(
+        short,
+        long,
+        help = "TOML file describing the station, command line flags override its values"
+    )]
+    config: Option<PathBuf>,
+
     #[arg(
         long,
-        default_value = "16",
-        help = "Maximum number of queued readouts before blocking"
+        help = "Maximum number of queued readouts before blocking [default: 16]"
     )]
-    backlog: usize,
+    backlog: Option<usize>,
 
     #[arg(short, long, help = "The I2C device to use")]
-    bus: PathBuf,
+    bus: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "Number of seconds between two sensor readouts [default: 10]"
+    )]
+    interval: Option<u64>,
+
+    #[arg(
+        short,
+        long = "sensor",
+        help = "Driver of a sensor to poll on the I2C bus, can be repeated [default: am2315]"
+    )]
+    sensors: Vec<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Address where to serve Prometheus metrics, e.g. 0.0.0.0:9100"
+    )]
+    metrics: Option<SocketAddr>,
+
+    #[arg(
+        short,
+        long,
+        help = "Units to convert the readouts to before forwarding them: metric or imperial"
+    )]
+    units: Option<UnitSystem>,
+
+    #[arg(help = "URI where to push the readouts, replacing the configured sinks")]
+    destination: Option<String>,
+}
+
+impl Args {
+    /// Build the effective configuration, command line flags taking precedence over the file
+    fn configuration(&self) -> Result<Config, PiWeatherError> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+
+        if let Some(backlog) = self.backlog {
+            config.backlog = backlog;
+        }
+
+        if let Some(units) = self.units {
+            config.units = Some(units);
+        }
+
+        if let Some(path) = &self.bus {
+            match config.buses.as_mut_slice() {
+                [] => config.buses.push(BusConfig {
+                    name: DEFAULT_BUS_NAME.to_string(),
+                    path: path.clone(),
+                }),
+                [bus] => bus.path = path.clone(),
+                buses => {
+                    return Err(PiWeatherError::InvalidConfiguration(format!(
+                        "--bus: ambiguous as {} buses are configured",
+                        buses.len()
+                    )))
+                }
+            }
+        }
+
+        if !self.sensors.is_empty() {
+            config.sensors = self.sensors.iter().map(SensorConfig::new).collect();
+        } else if self.config.is_none() {
+            config.sensors = vec![SensorConfig::new(DEFAULT_SENSOR_DRIVER)];
+        }
+
+        if let Some(interval) = self.interval {
+            for sensor in config.sensors.iter_mut() {
+                sensor.interval = interval;
+            }
+        }
+
+        if let Some(destination) = &self.destination {
+            config.sinks = vec![SinkConfig::new(destination)];
+        }
+
+        if let Some(listen) = self.metrics {
+            match config.metrics.as_mut() {
+                Some(metrics) => metrics.listen = listen,
+                None => config.metrics = Some(MetricsConfig::new(listen)),
+            }
+        }
 
-    #[arg(required = true, help = "URI where to push the readouts")]
-    destination: String,
+        Ok(config)
+    }
 }
 
-async fn weather_readouts_scheduler(mut readouts: Receiver<Payload<3>>) {
+async fn weather_readouts_scheduler(
+    mut readouts: Receiver<Payload>,
+    mut sinks: Vec<Box<dyn Sink>>,
+    metrics: Arc<Metrics>,
+    units: Option<UnitSystem>,
+) {
+    let mut ticks = interval(SINK_TICK_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
     loop {
-        match readouts.recv().await {
-            Some(payload) => {
+        let readout = tokio::select! {
+            readout = readouts.recv() => readout,
+            _ = ticks.tick() => {
+                for sink in sinks.iter_mut() {
+                    if let Err(err) = sink.tick().await {
+                        warn!("{}", err);
+                    }
+                }
+                continue;
+            }
+        };
+
+        match readout {
+            Some(mut payload) => {
                 debug!("Received payload: {:?}", payload);
+                if let Some(units) = units {
+                    for readout in payload.readouts_mut() {
+                        *readout = units.present(*readout);
+                    }
+                }
+
+                metrics.record_payload(&payload);
+                for sink in sinks.iter_mut() {
+                    if let Err(err) = sink.send(&payload).await {
+                        error!("Failed to forward payload: {}", err);
+                        metrics.record_dropped();
+                    }
+                }
             }
             None => {
                 debug!("Received termination from the channel");
@@ -38,33 +183,170 @@ async fn weather_readouts_scheduler(mut readouts: Receiver<Payload<3>>) {
         }
     }
 
+    for sink in sinks.iter_mut() {
+        if let Err(err) = sink.close().await {
+            error!("Failed to close sink: {}", err);
+        }
+    }
+
     info!("Scheduler exiting");
 }
 
+/// Wait until the process receives either SIGINT or SIGTERM
+async fn wait_for_termination() -> Result<(), PiWeatherError> {
+    let mut sigterm = signal(SignalKind::terminate())
+        .map_err(|err| PiWeatherError::Io(format!("Failed to install SIGTERM handler: {}", err)))?;
+
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => {
+            res.map_err(|err| PiWeatherError::Io(format!("Failed to listen for SIGINT: {}", err)))?;
+            info!("Received SIGINT");
+        }
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+    }
+
+    Ok(())
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), PiWeatherError> {
     tracing_subscriber::fmt::init();
 
-    // Parse arguments
+    // Parse arguments and resolve the configuration
     let args = Args::parse();
+    let config = args.configuration()?;
 
-    // Create the I2C bus from the provided file address
-    let factory = get_os_i2c_factory(&args.bus)?;
-    info!("Opening I2C bus {}", &args.bus.display());
+    let registry = SensorRegistry::new();
+    config.validate(&registry.infos().collect::<Vec<_>>())?;
+    info!("Starting station {}", &config.station.name);
 
-    // Initiate sensors
-    let mut am2315 = Am2315::with_i2c_factory(factory)?;
-    let readouts = am2315.read()?;
+    // Create the I2C buses from the provided file addresses
+    let mut factories = HashMap::with_capacity(config.buses.len());
+    for bus in &config.buses {
+        info!("Opening I2C bus {} ({})", &bus.name, &bus.path.display());
+        factories.insert(bus.name.as_str(), get_os_i2c_factory(bus.path.clone())?);
+    }
 
-    info!("Am2315 read: {:?}", readouts);
+    // Open the destinations before acquiring anything
+    let mut sinks = Vec::with_capacity(config.sinks.len());
+    for sink in &config.sinks {
+        let mut opened = sinks::open(&sink.destination, &config.station.name).await?;
+        if let Some(buffer) = &sink.buffer {
+            let queue = DiskQueue::open(&buffer.path, buffer.retention()).await?;
+            info!(
+                "Buffering undelivered readouts in {}",
+                buffer.path.display()
+            );
+            opened = Buffered::wrap(opened, queue);
+        }
+
+        sinks.push(opened);
+        info!(
+            "Forwarding readouts to {}",
+            sinks::redact(&sink.destination)
+        );
+    }
 
     // Start the looper
-    let (_sender, receiver) = channel(args.backlog);
+    let (sender, receiver) = channel(config.backlog);
+    let (shutdown, on_shutdown) = watch::channel(false);
+    let metrics = Arc::new(Metrics::new(&config.station.name));
+    let mut handles = Vec::with_capacity(config.sensors.len() + 2);
+
+    // Expose the latest readouts to Prometheus
+    if let Some(exporter) = &config.metrics {
+        let listener = TcpListener::bind(exporter.listen).await.map_err(|err| {
+            PiWeatherError::Io(format!("Failed to listen on {}: {}", exporter.listen, err))
+        })?;
+
+        info!(
+            "Serving metrics on http://{}{}",
+            exporter.listen, exporter.path
+        );
+        handles.push(tokio::spawn(serve_metrics(
+            listener,
+            exporter.path.clone(),
+            Arc::clone(&metrics),
+            on_shutdown.clone(),
+        )));
+    }
+
+    // Sensors used to compensate others publish their latest readouts
+    let mut ambients = HashMap::new();
+    for source in config
+        .sensors
+        .iter()
+        .filter_map(|s| s.compensation.as_deref())
+    {
+        ambients
+            .entry(source)
+            .or_insert_with(|| watch::channel(None));
+    }
+
+    // Initiate sensors, each of them being polled by its own task
+    for sensor in &config.sensors {
+        // Validation ensures every sensor is attached to a declared bus
+        let bus = config
+            .bus_of(sensor)
+            .expect("Sensor attached to an unknown bus");
+        let factory = &factories[bus.name.as_str()];
+
+        let driver = registry.open(&sensor.driver, factory, sensor.address, &sensor.settings)?;
+        let mut driver = Calibrated::wrap(driver, sensor.calibration);
+        if let Some(path) = &sensor.state {
+            driver = Persisted::wrap(driver, path).await;
+        }
+
+        // Validation ensures the compensation refers to a declared sensor
+        if let Some(source) = &sensor.compensation {
+            let (_, ambient) = &ambients[source.as_str()];
+            driver = Compensated::wrap(driver, ambient.clone());
+        }
+
+        if let Some((publisher, _)) = ambients.get(sensor.name()) {
+            driver = Shared::wrap(driver, publisher.clone());
+        }
+
+        info!(
+            "Polling {} ({}) on {} every {}s",
+            sensor.name(),
+            &sensor.driver,
+            &bus.name,
+            sensor.interval
+        );
+
+        let period = Duration::from_secs(sensor.interval);
+        let (sender, on_shutdown) = (sender.clone(), on_shutdown.clone());
+        handles.push(tokio::spawn(poll_sensor(
+            Arc::from(sensor.name()),
+            driver,
+            period,
+            sender,
+            Arc::clone(&metrics),
+            on_shutdown,
+        )));
+    }
+
+    // Only the polling tasks should keep the channel open
+    drop(sender);
+
+    handles.push(tokio::spawn(weather_readouts_scheduler(
+        receiver,
+        sinks,
+        metrics,
+        config.units,
+    )));
+
+    // Run until we are asked to stop, then let the pipeline drain
+    let termination = wait_for_termination().await;
+    let _ = shutdown.send(true);
 
     // Display the epilogue if any
-    if let Err(ref err) = tokio::spawn(weather_readouts_scheduler(receiver)).await {
-        error!("Got an error when terminating the application {}", err);
+    for handle in handles {
+        if let Err(ref err) = handle.await {
+            error!("Got an error when terminating the application {}", err);
+        }
     }
 
-    Ok(())
+    termination
 }
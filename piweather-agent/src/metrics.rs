@@ -0,0 +1,582 @@
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{AirQuality, Lightning, Modality, Payload, Quantity};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Time a client has to send its request, so it can't hold the connection indefinitely
+const METRICS_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request or header line accepted
+const METRICS_MAX_LINE_LENGTH: u64 = 8192;
+
+/// Name and description of an exported metric
+struct Metric {
+    name: &'static str,
+    help: &'static str,
+}
+
+const TEMPERATURE: Metric = Metric {
+    name: "piweather_temperature",
+    help: "Latest temperature readout",
+};
+const HUMIDITY: Metric = Metric {
+    name: "piweather_humidity_percent",
+    help: "Latest relative humidity readout",
+};
+const PRESSURE: Metric = Metric {
+    name: "piweather_pressure_hpa",
+    help: "Latest atmospheric pressure readout",
+};
+const WIND_SPEED: Metric = Metric {
+    name: "piweather_wind_speed",
+    help: "Latest wind speed readout",
+};
+const WIND_GUST: Metric = Metric {
+    name: "piweather_wind_gust",
+    help: "Latest wind gust readout",
+};
+const WIND_DIRECTION: Metric = Metric {
+    name: "piweather_wind_direction_degrees",
+    help: "Latest direction the wind blows from, clockwise from the north",
+};
+const PARTICLE_CONCENTRATION: Metric = Metric {
+    name: "piweather_particle_concentration_ugm3",
+    help: "Latest particulate matter concentration readout",
+};
+const PARTICLE_COUNT: Metric = Metric {
+    name: "piweather_particle_count",
+    help: "Latest number of particles per 0.1L of air",
+};
+const RAIN: Metric = Metric {
+    name: "piweather_rain",
+    help: "Rain accumulated since the sensor started counting",
+};
+const RAIN_RATE: Metric = Metric {
+    name: "piweather_rain_rate",
+    help: "Latest rain intensity readout",
+};
+const ILLUMINANCE: Metric = Metric {
+    name: "piweather_illuminance_lux",
+    help: "Latest illuminance readout",
+};
+const UV_INDEX: Metric = Metric {
+    name: "piweather_uv_index",
+    help: "Latest UV index readout",
+};
+const CARBON_DIOXIDE: Metric = Metric {
+    name: "piweather_co2_ppm",
+    help: "Latest carbon dioxide concentration readout",
+};
+const GAS_INDEX: Metric = Metric {
+    name: "piweather_gas_index",
+    help: "Latest VOC or NOx index readout",
+};
+const LIGHTNING_DISTANCE: Metric = Metric {
+    name: "piweather_lightning_distance_km",
+    help: "Estimated distance to the latest lightning storm front",
+};
+const LIGHTNING_STRIKES: Metric = Metric {
+    name: "piweather_lightning_strikes",
+    help: "Lightning strikes detected since the sensor started counting",
+};
+const LAST_READOUT: Metric = Metric {
+    name: "piweather_last_readout_timestamp_seconds",
+    help: "Time at which the latest readouts were acquired",
+};
+const PAYLOADS: Metric = Metric {
+    name: "piweather_payloads_total",
+    help: "Payloads acquired from the sensor",
+};
+const I2C_ERRORS: Metric = Metric {
+    name: "piweather_i2c_errors_total",
+    help: "Failed I2C transactions with the sensor",
+};
+const CHECKSUM_FAILURES: Metric = Metric {
+    name: "piweather_checksum_failures_total",
+    help: "Frames received from the sensor with an invalid checksum",
+};
+const READ_ERRORS: Metric = Metric {
+    name: "piweather_read_errors_total",
+    help: "Other failures to acquire readouts from the sensor",
+};
+const DROPPED_PAYLOADS: Metric = Metric {
+    name: "piweather_dropped_payloads_total",
+    help: "Payloads a sink failed to forward",
+};
+
+/// Escape backslashes, double quotes and line feeds of a label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Accessor of one of the counters of a sensor
+type CounterOf = fn(&SensorCounters) -> u64;
+
+#[derive(Debug, Default)]
+struct SensorCounters {
+    payloads: u64,
+    i2c_errors: u64,
+    checksum_failures: u64,
+    read_errors: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Latest value of every gauge, indexed by metric name, description and rendered labels
+    gauges: BTreeMap<(&'static str, &'static str, String), f64>,
+    counters: BTreeMap<String, SensorCounters>,
+}
+
+/// Latest readouts and health counters of the agent, rendered in the Prometheus text format
+#[derive(Debug)]
+pub struct Metrics {
+    station: String,
+    state: Mutex<State>,
+    dropped_payloads: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new<S: Into<String>>(station: S) -> Self {
+        Self {
+            station: station.into(),
+            state: Mutex::new(State::default()),
+            dropped_payloads: AtomicU64::new(0),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // Every update leaves the state consistent, a panicking holder doesn't invalidate it
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Update the gauges with the readouts of `payload`
+    pub fn record_payload(&self, payload: &Payload) {
+        let sensor = escape_label(payload.sensor().unwrap_or_default());
+        let labels = format!(
+            "station=\"{}\",sensor=\"{}\"",
+            escape_label(&self.station),
+            sensor
+        );
+
+        let mut state = self.state();
+        state.counters.entry(sensor).or_default().payloads += 1;
+        state.gauges.insert(
+            (LAST_READOUT.name, LAST_READOUT.help, labels.clone()),
+            payload.when().timestamp_millis() as f64 / 1000.0,
+        );
+
+        for modality in payload.readouts() {
+            let (metric, extra, value) = match *modality {
+                Modality::Temperature(t) => (
+                    &TEMPERATURE,
+                    format!(",unit=\"{}\"", t.unit().as_str()),
+                    f64::from(t.value()),
+                ),
+                Modality::Humidity(h) => (&HUMIDITY, String::new(), f64::from(h.value())),
+                Modality::Pressure(p) => (
+                    &PRESSURE,
+                    String::new(),
+                    f64::from(p.to_hectopascal().value()),
+                ),
+                Modality::Wind(w) => (
+                    &WIND_SPEED,
+                    format!(",unit=\"{}\"", w.unit().as_str()),
+                    f64::from(w.value()),
+                ),
+                Modality::WindGust(w) => (
+                    &WIND_GUST,
+                    format!(",unit=\"{}\"", w.unit().as_str()),
+                    f64::from(w.value()),
+                ),
+                Modality::WindDirection(d) => {
+                    (&WIND_DIRECTION, String::new(), f64::from(d.degrees()))
+                }
+                Modality::AirQuality(AirQuality::Concentration(particle, unit, value)) => (
+                    &PARTICLE_CONCENTRATION,
+                    format!(
+                        ",particle=\"{}\",unit=\"{}\"",
+                        particle.as_str(),
+                        unit.as_str()
+                    ),
+                    f64::from(value),
+                ),
+                Modality::AirQuality(AirQuality::Count(particle, value)) => (
+                    &PARTICLE_COUNT,
+                    format!(",particle=\"{}\"", particle.as_str()),
+                    f64::from(value),
+                ),
+                Modality::Rain(r) => (
+                    &RAIN,
+                    format!(",unit=\"{}\"", r.unit().as_str()),
+                    f64::from(r.value()),
+                ),
+                Modality::RainRate(r) => (
+                    &RAIN_RATE,
+                    format!(",unit=\"{}\"", r.unit().as_str()),
+                    f64::from(r.value()),
+                ),
+                Modality::Illuminance(lux) => (&ILLUMINANCE, String::new(), f64::from(lux)),
+                Modality::UvIndex(index) => (&UV_INDEX, String::new(), f64::from(index)),
+                Modality::CarbonDioxide(ppm) => (&CARBON_DIOXIDE, String::new(), f64::from(ppm)),
+                Modality::GasIndex(gas, index) => (
+                    &GAS_INDEX,
+                    format!(",gas=\"{}\"", gas.as_str()),
+                    f64::from(index),
+                ),
+                Modality::Lightning(Lightning::Distance(km)) => {
+                    (&LIGHTNING_DISTANCE, String::new(), f64::from(km))
+                }
+                Modality::Lightning(Lightning::Strikes(count)) => {
+                    (&LIGHTNING_STRIKES, String::new(), f64::from(count))
+                }
+            };
+
+            state.gauges.insert(
+                (metric.name, metric.help, format!("{}{}", labels, extra)),
+                value,
+            );
+        }
+    }
+
+    /// Account for a failure to acquire readouts from `sensor`
+    pub fn record_error(&self, sensor: &str, error: &PiWeatherError) {
+        let mut state = self.state();
+        let counters = state.counters.entry(escape_label(sensor)).or_default();
+        match error {
+            PiWeatherError::I2CError(_) => counters.i2c_errors += 1,
+            PiWeatherError::ChecksumMismatch { .. } => counters.checksum_failures += 1,
+            _ => counters.read_errors += 1,
+        }
+    }
+
+    /// Account for a payload a sink failed to forward
+    pub fn record_dropped(&self) {
+        self.dropped_payloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let state = self.state();
+        let station = escape_label(&self.station);
+        let mut output = String::new();
+
+        // Writing into a String never fails
+        let mut previous = None;
+        for ((name, help, labels), value) in &state.gauges {
+            if previous != Some(*name) {
+                let _ = writeln!(output, "# HELP {} {}", name, help);
+                let _ = writeln!(output, "# TYPE {} gauge", name);
+                previous = Some(*name);
+            }
+            let _ = writeln!(output, "{}{{{}}} {}", name, labels, value);
+        }
+
+        let counters: [(&Metric, CounterOf); 4] = [
+            (&PAYLOADS, |c| c.payloads),
+            (&I2C_ERRORS, |c| c.i2c_errors),
+            (&CHECKSUM_FAILURES, |c| c.checksum_failures),
+            (&READ_ERRORS, |c| c.read_errors),
+        ];
+
+        for (metric, value) in counters {
+            let _ = writeln!(output, "# HELP {} {}", metric.name, metric.help);
+            let _ = writeln!(output, "# TYPE {} counter", metric.name);
+            for (sensor, counters) in &state.counters {
+                let _ = writeln!(
+                    output,
+                    "{}{{station=\"{}\",sensor=\"{}\"}} {}",
+                    metric.name,
+                    station,
+                    sensor,
+                    value(counters)
+                );
+            }
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP {} {}",
+            DROPPED_PAYLOADS.name, DROPPED_PAYLOADS.help
+        );
+        let _ = writeln!(output, "# TYPE {} counter", DROPPED_PAYLOADS.name);
+        let _ = writeln!(
+            output,
+            "{}{{station=\"{}\"}} {}",
+            DROPPED_PAYLOADS.name,
+            station,
+            self.dropped_payloads.load(Ordering::Relaxed)
+        );
+
+        output
+    }
+}
+
+/// Read a single line of the request, failing if it exceeds `METRICS_MAX_LINE_LENGTH`
+async fn read_line(stream: &mut BufReader<TcpStream>) -> std::io::Result<String> {
+    let mut line = String::new();
+    let read = (&mut *stream)
+        .take(METRICS_MAX_LINE_LENGTH)
+        .read_line(&mut line)
+        .await?;
+    if read as u64 == METRICS_MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "request line is too long",
+        ));
+    }
+
+    Ok(line)
+}
+
+/// Read the request line, skipping the headers: the request never has a body we care about
+async fn read_request(stream: &mut BufReader<TcpStream>) -> std::io::Result<String> {
+    let request = read_line(stream).await?;
+    loop {
+        let header = read_line(stream).await?;
+        if header.trim_end().is_empty() {
+            return Ok(request);
+        }
+    }
+}
+
+/// Answer a single HTTP request, serving the metrics on `GET <path>`
+async fn handle(stream: TcpStream, path: &str, metrics: &Metrics) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let request = timeout(METRICS_REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "request wasn't received in time",
+            )
+        })??;
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) if target == path => ("200 OK", metrics.render()),
+        (Some("GET"), Some(_)) => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        METRICS_CONTENT_TYPE,
+        body.len(),
+        body
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serve `metrics` on `path` to every client of `listener` until `shutdown` is notified
+pub async fn serve_metrics(
+    listener: TcpListener,
+    path: String,
+    metrics: Arc<Metrics>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => {
+                debug!("Metrics exporter received shutdown notification");
+                break;
+            }
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    debug!("Serving metrics to {}", peer);
+                    let (path, metrics) = (path.clone(), Arc::clone(&metrics));
+                    tokio::spawn(async move {
+                        if let Err(err) = handle(stream, &path, &metrics).await {
+                            warn!("Failed to serve metrics to {}: {}", peer, err);
+                        }
+                    });
+                }
+                Err(err) => warn!("Failed to accept metrics client: {}", err),
+            }
+        }
+    }
+
+    info!("Metrics exporter exiting");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{handle, serve_metrics, Metrics};
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{
+        AirQuality, ConcentrationUnit, Direction, Gas, Humidity, Modality, Particle, Payload, Rain,
+        Temperature, Wind,
+    };
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::watch;
+
+    fn metrics() -> Metrics {
+        let metrics = Metrics::new("garden");
+        metrics.record_payload(
+            &Payload::now([
+                Modality::Temperature(Temperature::Celsius(21.5)),
+                Modality::Humidity(Humidity::Relative(45.25)),
+                Modality::WindGust(Wind::Kph(32.5)),
+                Modality::WindDirection(Direction::Degrees(-45.0)),
+            ])
+            .with_sensor("outdoor"),
+        );
+        metrics.record_payload(
+            &Payload::now([
+                Modality::AirQuality(AirQuality::Concentration(
+                    Particle::PM2_5,
+                    ConcentrationUnit::Environmental,
+                    12,
+                )),
+                Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 1032)),
+            ])
+            .with_sensor("pmsa003"),
+        );
+        metrics.record_payload(
+            &Payload::now([
+                Modality::Rain(Rain::Millimeters(2.4)),
+                Modality::CarbonDioxide(612),
+                Modality::GasIndex(Gas::Voc, 104),
+            ])
+            .with_sensor("indoor"),
+        );
+
+        metrics.record_error("outdoor", &PiWeatherError::I2CError("NACK".into()));
+        metrics.record_error(
+            "pmsa003",
+            &PiWeatherError::ChecksumMismatch {
+                sensor: "pmsa003",
+                expected: 0,
+                computed: 1,
+            },
+        );
+        metrics.record_dropped();
+        metrics
+    }
+
+    #[test]
+    fn render_metrics() {
+        let output = metrics().render();
+
+        for line in [
+            "# TYPE piweather_temperature gauge",
+            "piweather_temperature{station=\"garden\",sensor=\"outdoor\",unit=\"celsius\"} 21.5",
+            "piweather_humidity_percent{station=\"garden\",sensor=\"outdoor\"} 45.25",
+            "piweather_wind_gust{station=\"garden\",sensor=\"outdoor\",unit=\"kph\"} 32.5",
+            "piweather_wind_direction_degrees{station=\"garden\",sensor=\"outdoor\"} 315",
+            "piweather_particle_concentration_ugm3{station=\"garden\",sensor=\"pmsa003\",particle=\"pm2_5\",unit=\"environmental\"} 12",
+            "piweather_particle_count{station=\"garden\",sensor=\"pmsa003\",particle=\"pm0_3\"} 1032",
+            "piweather_rain{station=\"garden\",sensor=\"indoor\",unit=\"mm\"} 2.4000000953674316",
+            "piweather_co2_ppm{station=\"garden\",sensor=\"indoor\"} 612",
+            "piweather_gas_index{station=\"garden\",sensor=\"indoor\",gas=\"voc\"} 104",
+            "# TYPE piweather_i2c_errors_total counter",
+            "piweather_i2c_errors_total{station=\"garden\",sensor=\"outdoor\"} 1",
+            "piweather_i2c_errors_total{station=\"garden\",sensor=\"pmsa003\"} 0",
+            "piweather_checksum_failures_total{station=\"garden\",sensor=\"pmsa003\"} 1",
+            "piweather_payloads_total{station=\"garden\",sensor=\"outdoor\"} 1",
+            "piweather_dropped_payloads_total{station=\"garden\"} 1",
+        ] {
+            assert!(
+                output.lines().any(|l| l == line),
+                "Missing {} in\n{}",
+                line,
+                output
+            );
+        }
+
+        // Metadata is only emitted once per metric
+        assert_eq!(
+            output
+                .lines()
+                .filter(|l| *l == "# TYPE piweather_temperature gauge")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn latest_value_wins() {
+        let metrics = metrics();
+        metrics.record_payload(
+            &Payload::now([Modality::Temperature(Temperature::Celsius(19.0))])
+                .with_sensor("outdoor"),
+        );
+
+        let output = metrics.render();
+        assert!(output.contains("sensor=\"outdoor\",unit=\"celsius\"} 19\n"));
+        assert!(
+            output.contains("piweather_payloads_total{station=\"garden\",sensor=\"outdoor\"} 2")
+        );
+    }
+
+    async fn get(port: u16, target: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nhost: localhost\r\n\r\n", target).as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serve_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (shutdown, on_shutdown) = watch::channel(false);
+        let task = tokio::spawn(serve_metrics(
+            listener,
+            "/metrics".into(),
+            Arc::new(metrics()),
+            on_shutdown,
+        ));
+
+        let response = get(port, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("piweather_dropped_payloads_total{station=\"garden\"} 1"));
+
+        let response = get(port, "/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        shutdown.send(true).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reject_oversized_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let _ = stream.write_all(&[b'A'; 10000]).await;
+            stream
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let error = handle(stream, "/metrics", &metrics()).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        client.await.unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use crate::metrics::Metrics;
+use crate::sensors::Sensor;
+use piweather_common::Payload;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info};
+
+/// Periodically acquire readouts from `sensor` and push them to `readouts`.
+/// Each payload is tagged with the name of the sensor and a sequence number,
+/// increasing by one for every readout. Acquisition failures are accounted in `metrics`.
+///
+/// The task stops when `shutdown` is notified (or its sender is dropped) or when the
+/// receiving end of `readouts` is closed. Dropping the sender on exit lets the consumer
+/// drain the remaining payloads and terminate.
+pub async fn poll_sensor(
+    name: Arc<str>,
+    mut sensor: Box<dyn Sensor>,
+    period: Duration,
+    readouts: Sender<Payload>,
+    metrics: Arc<Metrics>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    sensor.set_polling_period(period);
+
+    let mut sequence = 0u64;
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => {
+                debug!("Polling task received shutdown notification");
+                break;
+            }
+            _ = ticker.tick() => {
+                match sensor.payload().await {
+                    Ok(Some(payload)) => {
+                        let payload = payload
+                            .with_sensor(Arc::clone(&name))
+                            .with_sequence(sequence);
+                        sequence += 1;
+
+                        if readouts.send(payload).await.is_err() {
+                            debug!("Readouts channel closed");
+                            break;
+                        }
+                    }
+                    Ok(None) => debug!("{} returned no readouts", name),
+                    Err(err) => {
+                        error!("Failed to acquire readouts from {}: {}", name, err);
+                        metrics.record_error(&name, &err);
+                    }
+                }
+            }
+        }
+    }
+
+    info!("Polling task for {} ({}) exiting", name, sensor.driver());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::Metrics;
+    use crate::polling::poll_sensor;
+    use crate::sensors::Sensor;
+    use async_trait::async_trait;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Modality, Payload, Pressure};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::{mpsc, watch};
+
+    struct CountingSensor {
+        count: u16,
+    }
+
+    #[async_trait]
+    impl Sensor for CountingSensor {
+        fn driver(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+            self.count += 1;
+            Ok(Some(Payload::now([Modality::Pressure(
+                Pressure::Hectopascal(f32::from(self.count)),
+            )])))
+        }
+    }
+
+    #[tokio::test]
+    async fn poll_sensor_until_shutdown() {
+        let (sender, mut receiver) = mpsc::channel(4);
+        let (shutdown, on_shutdown) = watch::channel(false);
+
+        let sensor = Box::new(CountingSensor { count: 0 });
+        let task = tokio::spawn(poll_sensor(
+            "counter".into(),
+            sensor,
+            Duration::from_millis(5),
+            sender,
+            Arc::new(Metrics::new("test")),
+            on_shutdown,
+        ));
+
+        for expected in 1..=3u16 {
+            let payload = receiver.recv().await.expect("Missing payload");
+            assert_eq!(payload.sequence(), Some(u64::from(expected - 1)));
+            assert_eq!(payload.sensor(), Some("counter"));
+            match payload.readouts() {
+                [Modality::Pressure(count)] => {
+                    assert_eq!(*count, Pressure::Hectopascal(f32::from(expected)))
+                }
+                readouts => panic!("Unexpected readouts {:?}", readouts),
+            }
+        }
+
+        shutdown.send(true).unwrap();
+        task.await.unwrap();
+
+        // Every sender is gone, the channel is closed once drained
+        while receiver.recv().await.is_some() {}
+    }
+}
@@ -0,0 +1,517 @@
+use crate::sinks::Sink;
+use chrono::Utc;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+const QUEUE_SEGMENT_EXTENSION: &str = "wal";
+const QUEUE_CURSOR_FILE: &str = "cursor";
+const QUEUE_MAX_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+fn io_error(path: &Path, action: &str, err: std::io::Error) -> PiWeatherError {
+    PiWeatherError::Io(format!("Failed to {} {}: {}", action, path.display(), err))
+}
+
+/// Limits on what the queue keeps on disk
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Retention {
+    /// Maximum number of bytes of pending payloads, the oldest ones being discarded first
+    pub max_size: u64,
+
+    /// Pending payloads older than this are discarded instead of being replayed
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    id: u64,
+    size: u64,
+}
+
+/// Write-ahead queue persisting payloads as JSON lines in a directory.
+///
+/// Payloads are appended to segment files `<id>.wal`, rotated once they exceed a fraction
+/// of the retention size. The position of the oldest pending payload is tracked in a
+/// `cursor` file, so replaying resumes where it stopped after a restart. Fully replayed
+/// segments are deleted.
+pub struct DiskQueue {
+    directory: PathBuf,
+    retention: Retention,
+    segment_size: u64,
+    segments: VecDeque<Segment>,
+    writer: Option<File>,
+
+    /// Offset of the oldest pending payload in the first segment
+    cursor: u64,
+}
+
+impl DiskQueue {
+    /// Open the queue stored in `directory`, creating it if needed
+    pub async fn open<P: Into<PathBuf>>(
+        directory: P,
+        retention: Retention,
+    ) -> Result<Self, PiWeatherError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)
+            .await
+            .map_err(|err| io_error(&directory, "create", err))?;
+
+        let mut segments = Vec::new();
+        let mut entries = fs::read_dir(&directory)
+            .await
+            .map_err(|err| io_error(&directory, "list", err))?;
+
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|err| io_error(&directory, "list", err))?
+        {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(QUEUE_SEGMENT_EXTENSION) {
+                continue;
+            }
+
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                let size = entry
+                    .metadata()
+                    .await
+                    .map_err(|err| io_error(&path, "inspect", err))?
+                    .len();
+                segments.push(Segment { id, size });
+            }
+        }
+        segments.sort_by_key(|segment| segment.id);
+
+        let mut queue = Self {
+            segment_size: (retention.max_size / 4).clamp(1, QUEUE_MAX_SEGMENT_SIZE),
+            directory,
+            retention,
+            segments: segments.into(),
+            writer: None,
+            cursor: 0,
+        };
+
+        // Segments before the cursor were fully replayed but not deleted yet
+        if let Some((id, offset)) = queue.read_cursor().await {
+            while queue
+                .segments
+                .front()
+                .is_some_and(|segment| segment.id < id)
+            {
+                queue.pop_segment().await?;
+            }
+
+            if let Some(head) = queue.segments.front().filter(|segment| segment.id == id) {
+                queue.cursor = offset.min(head.size);
+            }
+        }
+
+        queue.repair_tail().await?;
+        debug!(
+            "Opened queue {} with {} pending bytes",
+            queue.directory.display(),
+            queue.pending_size()
+        );
+
+        Ok(queue)
+    }
+
+    fn segment_path(&self, id: u64) -> PathBuf {
+        self.directory
+            .join(format!("{:020}.{}", id, QUEUE_SEGMENT_EXTENSION))
+    }
+
+    async fn read_cursor(&self) -> Option<(u64, u64)> {
+        let content = fs::read_to_string(self.directory.join(QUEUE_CURSOR_FILE))
+            .await
+            .ok()?;
+        let (id, offset) = content.trim().split_once(' ')?;
+        Some((id.parse().ok()?, offset.parse().ok()?))
+    }
+
+    /// Atomically persist the position of the oldest pending payload
+    async fn write_cursor(&self) -> Result<(), PiWeatherError> {
+        let id = self.segments.front().map_or(0, |segment| segment.id);
+        let path = self.directory.join(QUEUE_CURSOR_FILE);
+        let staging = path.with_extension("tmp");
+
+        fs::write(&staging, format!("{} {}", id, self.cursor))
+            .await
+            .map_err(|err| io_error(&staging, "write", err))?;
+        fs::rename(&staging, &path)
+            .await
+            .map_err(|err| io_error(&path, "write", err))
+    }
+
+    /// Drop a payload partially written when the agent was interrupted
+    async fn repair_tail(&mut self) -> Result<(), PiWeatherError> {
+        let Some(tail) = self.segments.back() else {
+            return Ok(());
+        };
+
+        let path = self.segment_path(tail.id);
+        let content = fs::read(&path)
+            .await
+            .map_err(|err| io_error(&path, "read", err))?;
+        let complete = content
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |position| position + 1) as u64;
+
+        if complete < tail.size {
+            warn!(
+                "Discarding truncated payload at the end of {}",
+                path.display()
+            );
+            let file = OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .await
+                .map_err(|err| io_error(&path, "open", err))?;
+            file.set_len(complete)
+                .await
+                .map_err(|err| io_error(&path, "truncate", err))?;
+
+            if let Some(tail) = self.segments.back_mut() {
+                tail.size = complete;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete the oldest segment, whether it was replayed or not
+    async fn pop_segment(&mut self) -> Result<(), PiWeatherError> {
+        if let Some(segment) = self.segments.pop_front() {
+            if self.segments.is_empty() {
+                self.writer = None;
+            }
+
+            let path = self.segment_path(segment.id);
+            fs::remove_file(&path)
+                .await
+                .map_err(|err| io_error(&path, "remove", err))?;
+        }
+
+        self.cursor = 0;
+        Ok(())
+    }
+
+    /// Number of bytes of pending payloads
+    pub fn pending_size(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.size)
+            .sum::<u64>()
+            - self.cursor
+    }
+
+    /// Whether every payload was replayed
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pending_size() == 0
+    }
+
+    /// Append `payload` at the end of the queue, discarding the oldest segments if the
+    /// retention size is exceeded
+    pub async fn push(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        let mut line = serde_json::to_vec(payload)
+            .map_err(|err| PiWeatherError::Io(format!("Failed to encode payload: {}", err)))?;
+        line.push(b'\n');
+
+        let rotate = match self.segments.back() {
+            Some(tail) => tail.size > 0 && tail.size + line.len() as u64 > self.segment_size,
+            None => true,
+        };
+
+        if rotate {
+            let id = self.segments.back().map_or(0, |tail| tail.id + 1);
+            self.segments.push_back(Segment { id, size: 0 });
+            self.writer = None;
+        }
+
+        let tail = self.segments.back().map_or(0, |tail| tail.id);
+        let path = self.segment_path(tail);
+        if self.writer.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .map_err(|err| io_error(&path, "open", err))?;
+            self.writer = Some(file);
+        }
+
+        if let Some(writer) = self.writer.as_mut() {
+            writer
+                .write_all(&line)
+                .await
+                .map_err(|err| io_error(&path, "write", err))?;
+            writer
+                .sync_data()
+                .await
+                .map_err(|err| io_error(&path, "sync", err))?;
+        }
+
+        if let Some(tail) = self.segments.back_mut() {
+            tail.size += line.len() as u64;
+        }
+
+        while self.pending_size() > self.retention.max_size && self.segments.len() > 1 {
+            warn!(
+                "Queue {} exceeds {} bytes, discarding its oldest payloads",
+                self.directory.display(),
+                self.retention.max_size
+            );
+            self.pop_segment().await?;
+            self.write_cursor().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Forward pending payloads to `sink` in order, until the queue is empty or the sink
+    /// fails. Payloads are only removed once the sink flushed them. Returns the number of
+    /// payloads forwarded.
+    pub async fn replay(&mut self, sink: &mut dyn Sink) -> Result<usize, PiWeatherError> {
+        let mut forwarded = 0;
+
+        while let Some(head) = self.segments.front() {
+            let (id, size) = (head.id, head.size);
+            let path = self.segment_path(id);
+            let content = fs::read(&path)
+                .await
+                .map_err(|err| io_error(&path, "read", err))?;
+
+            let mut lines = content
+                .get(self.cursor as usize..size as usize)
+                .unwrap_or_default()
+                .split_inclusive(|byte| *byte == b'\n');
+
+            let (mut offset, mut sent) = (self.cursor, 0);
+            let outcome = loop {
+                let Some(line) = lines.next() else {
+                    break Ok(());
+                };
+
+                match serde_json::from_slice::<Payload>(line) {
+                    Ok(payload) if self.is_expired(&payload) => {
+                        debug!("Discarding expired payload from {}", payload.when());
+                    }
+                    Ok(payload) => {
+                        if let Err(err) = sink.send(&payload).await {
+                            break Err(err);
+                        }
+                        sent += 1;
+                    }
+                    Err(err) => warn!(
+                        "Discarding corrupted payload in {}: {}",
+                        path.display(),
+                        err
+                    ),
+                }
+
+                offset += line.len() as u64;
+            };
+
+            // Payloads held back by the sink are replayed again unless it delivers them
+            let outcome = match sink.flush().await {
+                Ok(()) => {
+                    self.cursor = offset;
+                    forwarded += sent;
+                    outcome
+                }
+                Err(err) => Err(err),
+            };
+
+            // Keep writing to the tail segment, unless everything was replayed
+            let exhausted = self.cursor >= size;
+            if exhausted && (self.segments.len() > 1 || outcome.is_ok()) {
+                self.pop_segment().await?;
+            }
+
+            self.write_cursor().await?;
+            outcome?;
+        }
+
+        Ok(forwarded)
+    }
+
+    fn is_expired(&self, payload: &Payload) -> bool {
+        self.retention.max_age.is_some_and(|max_age| {
+            (Utc::now() - payload.when())
+                .to_std()
+                .is_ok_and(|age| age > max_age)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::queue::{DiskQueue, Retention};
+    use crate::sinks::Sink;
+    use async_trait::async_trait;
+    use chrono::{Duration as Delta, SubsecRound, Utc};
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Modality, Payload, Pressure};
+    use std::time::Duration;
+
+    /// Sink recording the sequence of the payloads, failing once `capacity` is reached
+    struct RecordingSink {
+        sequences: Vec<u64>,
+        capacity: usize,
+    }
+
+    #[async_trait]
+    impl Sink for RecordingSink {
+        async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+            if self.sequences.len() >= self.capacity {
+                return Err(PiWeatherError::SinkError("unavailable".into()));
+            }
+
+            self.sequences.push(payload.sequence().unwrap());
+            Ok(())
+        }
+    }
+
+    /// Payloads of identical sizes, provided sequences have the same number of digits
+    fn payload(sequence: u64) -> Payload {
+        Payload::new(
+            Utc::now().trunc_subsecs(0),
+            [Modality::Pressure(Pressure::Hectopascal(1013.0))],
+        )
+        .with_sequence(sequence)
+    }
+
+    const RETENTION: Retention = Retention {
+        max_size: 1024 * 1024,
+        max_age: None,
+    };
+
+    #[tokio::test]
+    async fn replay_in_order() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut queue = DiskQueue::open(directory.path(), RETENTION).await.unwrap();
+        assert!(queue.is_empty());
+
+        for sequence in 0..5 {
+            queue.push(&payload(sequence)).await.unwrap();
+        }
+
+        // The sink goes down after the third payload
+        let mut sink = RecordingSink {
+            sequences: Vec::new(),
+            capacity: 3,
+        };
+        assert!(queue.replay(&mut sink).await.is_err());
+        assert_eq!(sink.sequences, [0, 1, 2]);
+        assert!(!queue.is_empty());
+
+        sink.capacity = usize::MAX;
+        assert_eq!(queue.replay(&mut sink).await.unwrap(), 2);
+        assert_eq!(sink.sequences, [0, 1, 2, 3, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resume_after_restart() {
+        let directory = tempfile::tempdir().unwrap();
+        {
+            let mut queue = DiskQueue::open(directory.path(), RETENTION).await.unwrap();
+            for sequence in 0..4 {
+                queue.push(&payload(sequence)).await.unwrap();
+            }
+
+            let mut sink = RecordingSink {
+                sequences: Vec::new(),
+                capacity: 1,
+            };
+            assert!(queue.replay(&mut sink).await.is_err());
+        }
+
+        let mut queue = DiskQueue::open(directory.path(), RETENTION).await.unwrap();
+        queue.push(&payload(4)).await.unwrap();
+
+        let mut sink = RecordingSink {
+            sequences: Vec::new(),
+            capacity: usize::MAX,
+        };
+        assert_eq!(queue.replay(&mut sink).await.unwrap(), 4);
+        assert_eq!(sink.sequences, [1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn discard_truncated_payload() {
+        let directory = tempfile::tempdir().unwrap();
+        {
+            let mut queue = DiskQueue::open(directory.path(), RETENTION).await.unwrap();
+            queue.push(&payload(0)).await.unwrap();
+        }
+
+        // Simulate the agent being killed while appending a payload
+        let segment = directory.path().join(format!("{:020}.wal", 0));
+        let mut content = std::fs::read(&segment).unwrap();
+        content.extend_from_slice(b"{\"when\":");
+        std::fs::write(&segment, content).unwrap();
+
+        let mut queue = DiskQueue::open(directory.path(), RETENTION).await.unwrap();
+        queue.push(&payload(1)).await.unwrap();
+
+        let mut sink = RecordingSink {
+            sequences: Vec::new(),
+            capacity: usize::MAX,
+        };
+        assert_eq!(queue.replay(&mut sink).await.unwrap(), 2);
+        assert_eq!(sink.sequences, [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn enforce_retention() {
+        let directory = tempfile::tempdir().unwrap();
+        let line = serde_json::to_vec(&payload(100)).unwrap().len() as u64 + 1;
+
+        // Room for 8 payloads, segments holding 2 of them
+        let retention = Retention {
+            max_size: 8 * line,
+            max_age: None,
+        };
+        let mut queue = DiskQueue::open(directory.path(), retention).await.unwrap();
+        for sequence in 100..120 {
+            queue.push(&payload(sequence)).await.unwrap();
+            assert!(queue.pending_size() <= retention.max_size);
+        }
+
+        let mut sink = RecordingSink {
+            sequences: Vec::new(),
+            capacity: usize::MAX,
+        };
+        queue.replay(&mut sink).await.unwrap();
+        assert_eq!(sink.sequences, (112..120).collect::<Vec<_>>());
+
+        // Expired payloads are not replayed
+        let retention = Retention {
+            max_size: 8 * line,
+            max_age: Some(Duration::from_secs(3600)),
+        };
+        let mut queue = DiskQueue::open(directory.path(), retention).await.unwrap();
+        let expired = Payload::new(
+            Utc::now() - Delta::hours(2),
+            [Modality::Pressure(Pressure::Hectopascal(1013.0))],
+        );
+        queue.push(&expired.with_sequence(120)).await.unwrap();
+        queue.push(&payload(121)).await.unwrap();
+
+        sink.sequences.clear();
+        assert_eq!(queue.replay(&mut sink).await.unwrap(), 1);
+        assert_eq!(sink.sequences, [121]);
+    }
+}
@@ -1,12 +1,14 @@
-use crate::i2c::I2CDeviceFactory;
-use crate::sensors::Sensor;
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::{Ambient, I2CSensor, NoSettings, Sensor};
+use async_trait::async_trait;
 use i2cdev::core::I2CDevice;
 use piweather_common::errors::PiWeatherError;
-use piweather_common::{Modality, Payload, Temperature};
-use std::thread::sleep;
+use piweather_common::{Humidity, Modality, Payload, Temperature};
 use std::time::{Duration, Instant};
+use tokio::time::sleep;
 use tracing::debug;
 
+const AM2315_DRIVER: &str = "am2315";
 const AM2315_I2C_SLAVE_ADDRESS: u16 = 0x5C;
 const AM2315_I2C_READ_FUNC_CODE: u8 = 0x03;
 const AM2315_I2C_READ_CALL: [u8; 3] = [AM2315_I2C_READ_FUNC_CODE, 0x0, 0x4];
@@ -23,7 +25,7 @@ impl From<Am2315Readout> for Modality {
     fn from(value: Am2315Readout) -> Self {
         match value {
             Am2315Readout::Temperature(t) => Modality::Temperature(Temperature::Celsius(t)),
-            Am2315Readout::Humidity(h) => Modality::Humidity(h),
+            Am2315Readout::Humidity(h) => Modality::Humidity(Humidity::Relative(h)),
         }
     }
 }
@@ -31,31 +33,41 @@ impl From<Am2315Readout> for Modality {
 pub struct Am2315<T: I2CDevice + Sized> {
     last_read: Option<Instant>,
     last_readouts: Option<[Am2315Readout; 2]>,
-    device: T,
+    device: AsyncI2CDevice<T>,
 }
 
 impl<T> Am2315<T>
 where
-    T: I2CDevice + Sized,
+    T: I2CDevice + Send + 'static,
 {
     pub fn new(device: T) -> Self {
         Self {
             last_read: None,
             last_readouts: None,
-            device,
+            device: AsyncI2CDevice::new(device),
         }
     }
 
-    fn wake(&mut self) -> Result<(), PiWeatherError> {
-        let _ = self.device.write(&[0x0]);
-        sleep(AM2315_WAKEUP_TIME_MS);
+    async fn wake(&mut self) -> Result<(), PiWeatherError> {
+        // The sensor doesn't acknowledge the wake-up call, failure is expected here
+        let _ = self
+            .device
+            .transaction(|device| {
+                let _ = device.write(&[0x0]);
+                Ok(())
+            })
+            .await;
 
-        // Create the buffers to send & store the request and response content
-        self.device.write(&AM2315_I2C_READ_CALL).map_err(|e| {
-            PiWeatherError::I2CError(format!("Failed to write read op to AM2315: {}", e))
-        })?;
+        sleep(AM2315_WAKEUP_TIME_MS).await;
 
-        Ok(())
+        // Create the buffers to send & store the request and response content
+        self.device
+            .transaction(|device| {
+                device.write(&AM2315_I2C_READ_CALL).map_err(|e| {
+                    PiWeatherError::I2CError(format!("Failed to write read op to AM2315: {}", e))
+                })
+            })
+            .await
     }
 
     fn temperature_from_le_bytes(low: u8, high: u8) -> f32 {
@@ -75,12 +87,33 @@ where
         h as f32 / 10.0
     }
 
-    pub fn read_temperature_and_humidity(&mut self) -> Result<[Am2315Readout; 2], PiWeatherError> {
-        let mut data = [0u8; 6];
+    /// Modbus CRC16 (polynomial 0xA001, initial value 0xFFFF) protecting AM2315 frames
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0xFFFFu16, |crc, byte| {
+            (0..8).fold(crc ^ (*byte as u16), |crc, _| {
+                if crc & 0x1 == 1 {
+                    (crc >> 1) ^ 0xA001
+                } else {
+                    crc >> 1
+                }
+            })
+        })
+    }
 
-        self.device.read(&mut data).map_err(|e| {
-            PiWeatherError::I2CError(format!("Failed to read data from AM2315: {}", e))
-        })?;
+    pub async fn read_temperature_and_humidity(
+        &mut self,
+    ) -> Result<[Am2315Readout; 2], PiWeatherError> {
+        // Function code, number of bytes, 4 bytes of data and the CRC16 (low byte first)
+        let data = self
+            .device
+            .transaction(|device| {
+                let mut data = [0u8; 8];
+                device.read(&mut data).map_err(|e| {
+                    PiWeatherError::I2CError(format!("Failed to read data from AM2315: {}", e))
+                })?;
+                Ok(data)
+            })
+            .await?;
 
         // Update last time we read the sensor
         self.last_read = Some(Instant::now());
@@ -94,6 +127,16 @@ where
             return Err(PiWeatherError::Io("Mismatched number of bytes read".into()));
         }
 
+        let checksum = u16::from_le_bytes([data[6], data[7]]);
+        let crc = Self::crc16(&data[..6]);
+        if crc != checksum {
+            return Err(PiWeatherError::ChecksumMismatch {
+                sensor: "AM2315",
+                expected: checksum,
+                computed: crc,
+            });
+        }
+
         // Convert to meaningful values
         let humidity = Self::humidity_from_le_bytes(data[2], data[3]);
         let temperature = Self::temperature_from_le_bytes(data[4], data[5]);
@@ -106,7 +149,7 @@ where
         Ok(readouts)
     }
 
-    pub fn read(&mut self) -> Result<Option<[Am2315Readout; 2]>, PiWeatherError> {
+    pub async fn read(&mut self) -> Result<Option<[Am2315Readout; 2]>, PiWeatherError> {
         if let Some(last_read) = self.last_read {
             let since_last_read = Instant::now() - last_read;
             if since_last_read < AM2315_READ_INTERVAL_SEC {
@@ -119,27 +162,47 @@ where
         }
 
         // Wake up the sensor (sleeping to avoid self-heating)
-        self.wake()?;
-        let readouts = self.read_temperature_and_humidity()?;
+        self.wake().await?;
+        let readouts = self.read_temperature_and_humidity().await?;
 
         self.last_readouts = Some(readouts);
         Ok(self.last_readouts)
     }
 }
 
-impl<F, D> Sensor<F, D, 2> for Am2315<D>
+impl<D> I2CSensor<D> for Am2315<D>
 where
-    F: I2CDeviceFactory<Device = D>,
-    D: I2CDevice + Sized,
-    Self: Sized,
+    D: I2CDevice + Send + 'static,
 {
-    fn with_i2c_factory(factory: F) -> Result<Self, PiWeatherError> {
-        let device = factory.open(AM2315_I2C_SLAVE_ADDRESS)?;
+    const DRIVER: &'static str = AM2315_DRIVER;
+    const DEFAULT_ADDRESS: u16 = AM2315_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] = &[Ambient::Temperature, Ambient::Humidity];
+    type Settings = NoSettings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        _settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
         Ok(Am2315::new(device))
     }
+}
 
-    fn payload(&mut self) -> Result<Option<Payload<2>>, PiWeatherError> {
-        if let Some(readouts) = self.read()? {
+#[async_trait]
+impl<D> Sensor for Am2315<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        AM2315_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        if let Some(readouts) = self.read().await? {
             let modalities = [readouts[0].into(), readouts[1].into()];
             return Ok(Some(Payload::now(modalities)));
         }
@@ -153,6 +216,13 @@ mod tests {
     use crate::sensors::am2315::Am2315;
     use crate::sensors::Am2315Readout;
     use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+
+    #[test]
+    fn crc16() {
+        let crc = Am2315::<MockI2CDevice>::crc16(&[0x03, 0x04, 0x03, 0x39, 0x01, 0x15]);
+        assert_eq!(crc, 0xFEE1);
+    }
 
     #[test]
     fn read_humidity() {
@@ -166,18 +236,18 @@ mod tests {
         assert_eq!(temperature, 27.7);
     }
 
-    #[test]
-    fn amd2315_read() {
+    #[tokio::test]
+    async fn amd2315_read() {
         const REGISTER: [u8; 8] = [0x03, 0x04, 0x03, 0x39, 0x01, 0x15, 0xE1, 0xFE];
 
         // Write dummy data to the register
         let mut device = MockI2CDevice::new();
-        (&mut device.regmap).write_regs(0x0, &REGISTER);
+        device.regmap.write_regs(0x0, &REGISTER);
 
         let mut am2315 = Am2315::new(device);
 
         // Handle read
-        let readouts = am2315.read_temperature_and_humidity();
+        let readouts = am2315.read_temperature_and_humidity().await;
         assert!(readouts.is_ok(), "Error while reading from the sensor");
 
         let readouts = readouts.unwrap();
@@ -191,4 +261,24 @@ mod tests {
 
         // assert!(readouts.is_some(), "Am2315 returned no data");
     }
+
+    #[tokio::test]
+    async fn amd2315_read_corrupted() {
+        // Humidity LSB got flipped on the bus, CRC16 is the one of the original frame
+        const REGISTER: [u8; 8] = [0x03, 0x04, 0x03, 0x38, 0x01, 0x15, 0xE1, 0xFE];
+
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0x0, &REGISTER);
+
+        let mut am2315 = Am2315::new(device);
+        match am2315.read_temperature_and_humidity().await {
+            Err(PiWeatherError::ChecksumMismatch {
+                expected, computed, ..
+            }) => {
+                assert_eq!(expected, 0xFEE1);
+                assert_ne!(computed, expected);
+            }
+            other => panic!("Corrupted frame should be rejected, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,695 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::registers::{read_registers, write_registers};
+use crate::sensors::{Ambient, I2CSensor, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Humidity, Modality, Payload, Pressure, Temperature};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::debug;
+
+const BME280_DRIVER: &str = "bme280";
+const BMP280_DRIVER: &str = "bmp280";
+const BME280_NAME: &str = "BME280";
+
+/// Address with SDO pulled to ground, 0x77 when pulled to VDDIO
+const BME280_I2C_SLAVE_ADDRESS: u16 = 0x76;
+
+const BME280_CHIP_ID: u8 = 0x60;
+const BMP280_CHIP_IDS: [u8; 3] = [0x56, 0x57, 0x58];
+
+const BME280_REGISTER_CALIBRATION_TP: u8 = 0x88;
+const BME280_REGISTER_CHIP_ID: u8 = 0xD0;
+const BME280_REGISTER_RESET: u8 = 0xE0;
+const BME280_REGISTER_CALIBRATION_H: u8 = 0xE1;
+const BME280_REGISTER_CTRL_HUM: u8 = 0xF2;
+const BME280_REGISTER_STATUS: u8 = 0xF3;
+const BME280_REGISTER_CTRL_MEAS: u8 = 0xF4;
+const BME280_REGISTER_CONFIG: u8 = 0xF5;
+const BME280_REGISTER_DATA: u8 = 0xF7;
+
+const BME280_RESET_WORD: u8 = 0xB6;
+const BME280_STATUS_MEASURING: u8 = 0x08;
+const BME280_STATUS_IM_UPDATE: u8 = 0x01;
+
+/// Value of the ADC when the measurement is skipped
+const BME280_SKIPPED_20BITS: i32 = 0x80000;
+const BME280_SKIPPED_16BITS: i32 = 0x8000;
+
+const BME280_STARTUP_TIME: Duration = Duration::from_millis(2);
+const BME280_STATUS_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const BME280_STATUS_POLL_ATTEMPTS: usize = 50;
+
+/// Number of samples averaged for a single measurement, reducing the noise
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Oversampling {
+    /// The measurement is not performed
+    Skipped = 0,
+    #[default]
+    X1 = 1,
+    X2 = 2,
+    X4 = 3,
+    X8 = 4,
+    X16 = 5,
+}
+
+impl Oversampling {
+    fn samples(&self) -> f32 {
+        match self {
+            Oversampling::Skipped => 0.0,
+            Oversampling::X1 => 1.0,
+            Oversampling::X2 => 2.0,
+            Oversampling::X4 => 4.0,
+            Oversampling::X8 => 8.0,
+            Oversampling::X16 => 16.0,
+        }
+    }
+}
+
+/// Coefficient of the IIR filter smoothing pressure and temperature over consecutive measurements
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    #[default]
+    Off = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+    X16 = 4,
+}
+
+/// Inactive period between two measurements in normal mode
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Standby {
+    Ms0_5 = 0,
+    Ms62_5 = 1,
+    Ms125 = 2,
+    Ms250 = 3,
+    Ms500 = 4,
+    #[default]
+    Ms1000 = 5,
+
+    /// 2000ms on BMP280
+    Ms10 = 6,
+
+    /// 4000ms on BMP280
+    Ms20 = 7,
+}
+
+/// Configured as `"forced"` or `{ normal = "ms1000" }`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Perform a single measurement when polled, sleeping in between
+    #[default]
+    Forced,
+
+    /// Measure continuously, polling returns the latest measurement
+    Normal(Standby),
+}
+
+/// Acquisition settings, the defaults being the ones recommended for weather monitoring
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bme280Settings {
+    pub temperature: Oversampling,
+    pub pressure: Oversampling,
+
+    /// Ignored by BMP280
+    pub humidity: Oversampling,
+    pub filter: Filter,
+    pub mode: Mode,
+}
+
+impl Bme280Settings {
+    /// Pressure and humidity are compensated with the temperature, which can't be skipped
+    fn validate(&self, driver: &str) -> Result<(), PiWeatherError> {
+        if self.temperature == Oversampling::Skipped {
+            return Err(PiWeatherError::InvalidConfiguration(format!(
+                "invalid {} settings: temperature oversampling can't be skipped",
+                driver
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn ctrl_meas(&self, mode: u8) -> u8 {
+        ((self.temperature as u8) << 5) | ((self.pressure as u8) << 2) | mode
+    }
+
+    fn config(&self) -> u8 {
+        // The standby period is irrelevant in forced mode
+        let standby = match self.mode {
+            Mode::Forced => Standby::Ms0_5,
+            Mode::Normal(standby) => standby,
+        };
+        ((standby as u8) << 5) | ((self.filter as u8) << 2)
+    }
+
+    /// Longest time a measurement can take with these settings
+    fn measurement_time(&self) -> Duration {
+        let optional = |oversampling: Oversampling| match oversampling {
+            Oversampling::Skipped => 0.0,
+            oversampling => 2.3 * oversampling.samples() + 0.575,
+        };
+
+        let ms = 1.25
+            + 2.3 * self.temperature.samples()
+            + optional(self.pressure)
+            + optional(self.humidity);
+        Duration::from_micros((ms * 1000.0).ceil() as u64)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Bme280Variant {
+    Bme280,
+
+    /// Same as BME280, without humidity
+    Bmp280,
+}
+
+/// Factory trimming parameters, burnt in the non-volatile memory of each sensor
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Bme280Coefficients {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+impl Bme280Coefficients {
+    /// Decode the coefficients stored from 0x88 to 0xA1 and, for BME280, from 0xE1 to 0xE7
+    pub fn from_registers(tp: &[u8; 26], h: Option<&[u8; 7]>) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([tp[offset], tp[offset + 1]]);
+        let i16_at = |offset: usize| i16::from_le_bytes([tp[offset], tp[offset + 1]]);
+
+        let mut coefficients = Self {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p2: i16_at(8),
+            p3: i16_at(10),
+            p4: i16_at(12),
+            p5: i16_at(14),
+            p6: i16_at(16),
+            p7: i16_at(18),
+            p8: i16_at(20),
+            p9: i16_at(22),
+            h1: tp[25],
+            ..Self::default()
+        };
+
+        // H4 and H5 are 12 bits signed values sharing 0xE5
+        if let Some(h) = h {
+            coefficients.h2 = i16::from_le_bytes([h[0], h[1]]);
+            coefficients.h3 = h[2];
+            coefficients.h4 = ((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16;
+            coefficients.h5 = ((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16;
+            coefficients.h6 = h[6] as i8;
+        }
+
+        coefficients
+    }
+
+    /// Fine temperature shared with the pressure and humidity compensation, and the
+    /// temperature in 0.01°C. Integer implementation from the datasheet.
+    fn compensate_temperature(&self, adc: i32) -> (i32, i32) {
+        let (t1, t2, t3) = (self.t1 as i32, self.t2 as i32, self.t3 as i32);
+
+        let var1 = (((adc >> 3) - (t1 << 1)) * t2) >> 11;
+        let var2 = (((((adc >> 4) - t1) * ((adc >> 4) - t1)) >> 12) * t3) >> 14;
+        let t_fine = var1 + var2;
+        (t_fine, (t_fine * 5 + 128) >> 8)
+    }
+
+    /// Pressure in Pa as an unsigned Q24.8. Integer implementation from the datasheet.
+    fn compensate_pressure(&self, adc: i32, t_fine: i32) -> u32 {
+        let mut var1 = t_fine as i64 - 128000;
+        let mut var2 = var1 * var1 * self.p6 as i64;
+        var2 += (var1 * self.p5 as i64) << 17;
+        var2 += (self.p4 as i64) << 35;
+        var1 = ((var1 * var1 * self.p3 as i64) >> 8) + ((var1 * self.p2 as i64) << 12);
+        var1 = (((1i64 << 47) + var1) * self.p1 as i64) >> 33;
+
+        // Avoid a division by zero on a blank calibration
+        if var1 == 0 {
+            return 0;
+        }
+
+        let mut p = 1048576 - adc as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        let var1 = (self.p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+        let var2 = (self.p8 as i64 * p) >> 19;
+        (((p + var1 + var2) >> 8) + ((self.p7 as i64) << 4)) as u32
+    }
+
+    /// Relative humidity as an unsigned Q22.10. Integer implementation from the datasheet.
+    fn compensate_humidity(&self, adc: i32, t_fine: i32) -> u32 {
+        let (h1, h2, h3) = (self.h1 as i32, self.h2 as i32, self.h3 as i32);
+        let (h4, h5, h6) = (self.h4 as i32, self.h5 as i32, self.h6 as i32);
+
+        let mut v = t_fine - 76800;
+        v = ((((adc << 14) - (h4 << 20) - (h5 * v)) + 16384) >> 15)
+            * (((((((v * h6) >> 10) * (((v * h3) >> 11) + 32768)) >> 10) + 2097152) * h2 + 8192)
+                >> 14);
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * h1) >> 4;
+        (v.clamp(0, 419430400) >> 12) as u32
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Bme280Readout {
+    Temperature(f32),
+    Humidity(f32),
+
+    /// Expressed in hPa
+    Pressure(f32),
+}
+
+impl From<Bme280Readout> for Modality {
+    fn from(value: Bme280Readout) -> Self {
+        match value {
+            Bme280Readout::Temperature(t) => Modality::Temperature(Temperature::Celsius(t)),
+            Bme280Readout::Humidity(h) => Modality::Humidity(Humidity::Relative(h)),
+            Bme280Readout::Pressure(p) => Modality::Pressure(Pressure::Hectopascal(p)),
+        }
+    }
+}
+
+pub struct Bme280<T: I2CDevice + Sized> {
+    settings: Bme280Settings,
+    chip: Option<(Bme280Variant, Bme280Coefficients)>,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Bme280<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Bme280Settings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Bme280Settings) -> Self {
+        Self {
+            settings,
+            chip: None,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    async fn read_registers<const N: usize>(
+        &self,
+        register: u8,
+    ) -> Result<[u8; N], PiWeatherError> {
+        read_registers(&self.device, BME280_NAME, register).await
+    }
+
+    async fn write_register(&self, register: u8, value: u8) -> Result<(), PiWeatherError> {
+        write_registers(&self.device, BME280_NAME, register, &[value]).await
+    }
+
+    /// Wait until the bits of `mask` are cleared in the status register
+    async fn wait_status(&self, mask: u8) -> Result<(), PiWeatherError> {
+        for _ in 0..BME280_STATUS_POLL_ATTEMPTS {
+            let [status] = self.read_registers::<1>(BME280_REGISTER_STATUS).await?;
+            if status & mask == 0 {
+                return Ok(());
+            }
+
+            sleep(BME280_STATUS_POLL_INTERVAL).await;
+        }
+
+        Err(PiWeatherError::I2CError(
+            "BME280 didn't complete in time".into(),
+        ))
+    }
+
+    /// Identify the chip, load its coefficients and apply the settings
+    pub async fn initialize(
+        &mut self,
+    ) -> Result<(Bme280Variant, Bme280Coefficients), PiWeatherError> {
+        let [chip_id] = self.read_registers::<1>(BME280_REGISTER_CHIP_ID).await?;
+        let variant = match chip_id {
+            BME280_CHIP_ID => Bme280Variant::Bme280,
+            id if BMP280_CHIP_IDS.contains(&id) => Bme280Variant::Bmp280,
+            id => {
+                return Err(PiWeatherError::I2CError(format!(
+                    "Unexpected chip id 0x{:02X} for BME280",
+                    id
+                )))
+            }
+        };
+
+        // Start from a known state, the sensor being asleep after a reset
+        self.write_register(BME280_REGISTER_RESET, BME280_RESET_WORD)
+            .await?;
+        sleep(BME280_STARTUP_TIME).await;
+        self.wait_status(BME280_STATUS_IM_UPDATE).await?;
+
+        let tp = self
+            .read_registers::<26>(BME280_REGISTER_CALIBRATION_TP)
+            .await?;
+        let h = match variant {
+            Bme280Variant::Bme280 => Some(
+                self.read_registers::<7>(BME280_REGISTER_CALIBRATION_H)
+                    .await?,
+            ),
+            Bme280Variant::Bmp280 => None,
+        };
+        let coefficients = Bme280Coefficients::from_registers(&tp, h.as_ref());
+
+        // ctrl_hum only applies once ctrl_meas is written, config only while sleeping
+        if variant == Bme280Variant::Bme280 {
+            self.write_register(BME280_REGISTER_CTRL_HUM, self.settings.humidity as u8)
+                .await?;
+        }
+        self.write_register(BME280_REGISTER_CONFIG, self.settings.config())
+            .await?;
+        if let Mode::Normal(_) = self.settings.mode {
+            self.write_register(BME280_REGISTER_CTRL_MEAS, self.settings.ctrl_meas(0b11))
+                .await?;
+        }
+
+        debug!("Initialized {:?} with {:?}", variant, coefficients);
+        self.chip = Some((variant, coefficients));
+        Ok((variant, coefficients))
+    }
+
+    /// Acquire and compensate a measurement, triggering it first in forced mode.
+    /// Skipped measurements are not reported.
+    pub async fn read(&mut self) -> Result<Vec<Bme280Readout>, PiWeatherError> {
+        let (variant, coefficients) = match self.chip {
+            Some(chip) => chip,
+            None => self.initialize().await?,
+        };
+
+        if self.settings.mode == Mode::Forced {
+            self.write_register(BME280_REGISTER_CTRL_MEAS, self.settings.ctrl_meas(0b01))
+                .await?;
+            sleep(self.settings.measurement_time()).await;
+            self.wait_status(BME280_STATUS_MEASURING).await?;
+        }
+
+        // Burst read, so every value comes from the same measurement
+        let data = self.read_registers::<8>(BME280_REGISTER_DATA).await?;
+        let adc_20bits = |msb: u8, lsb: u8, xlsb: u8| {
+            ((msb as i32) << 12) | ((lsb as i32) << 4) | ((xlsb as i32) >> 4)
+        };
+        let adc_p = adc_20bits(data[0], data[1], data[2]);
+        let adc_t = adc_20bits(data[3], data[4], data[5]);
+        let adc_h = ((data[6] as i32) << 8) | data[7] as i32;
+
+        // Pressure and humidity compensation depend on the temperature
+        let mut readouts = Vec::with_capacity(3);
+        if adc_t == BME280_SKIPPED_20BITS {
+            return Ok(readouts);
+        }
+
+        let (t_fine, temperature) = coefficients.compensate_temperature(adc_t);
+        readouts.push(Bme280Readout::Temperature(temperature as f32 / 100.0));
+
+        if variant == Bme280Variant::Bme280 && adc_h != BME280_SKIPPED_16BITS {
+            let humidity = coefficients.compensate_humidity(adc_h, t_fine);
+            readouts.push(Bme280Readout::Humidity(humidity as f32 / 1024.0));
+        }
+
+        if adc_p != BME280_SKIPPED_20BITS {
+            let pressure = coefficients.compensate_pressure(adc_p, t_fine);
+            readouts.push(Bme280Readout::Pressure(pressure as f32 / 25600.0));
+        }
+
+        Ok(readouts)
+    }
+}
+
+impl<D> I2CSensor<D> for Bme280<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = BME280_DRIVER;
+    const DEFAULT_ADDRESS: u16 = BME280_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] =
+        &[Ambient::Temperature, Ambient::Humidity, Ambient::Pressure];
+    type Settings = Bme280Settings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        settings.validate(BME280_DRIVER)?;
+        let device = factory.open(address)?;
+        Ok(Bme280::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Bme280<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        BME280_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let readouts = self.read().await?;
+        if readouts.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Payload::now(readouts.into_iter().map(Modality::from))))
+    }
+}
+
+/// BMP280 is handled by the BME280 driver, registered under its own name for clarity
+pub struct Bmp280<T: I2CDevice + Sized>(Bme280<T>);
+
+impl<T> Bmp280<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self(Bme280::new(device))
+    }
+
+    pub fn with_settings(device: T, settings: Bme280Settings) -> Self {
+        Self(Bme280::with_settings(device, settings))
+    }
+}
+
+impl<D> I2CSensor<D> for Bmp280<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = BMP280_DRIVER;
+    const DEFAULT_ADDRESS: u16 = BME280_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] = &[Ambient::Temperature, Ambient::Pressure];
+    type Settings = Bme280Settings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        settings.validate(BMP280_DRIVER)?;
+        let device = factory.open(address)?;
+        Ok(Bmp280::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Bmp280<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        BMP280_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        self.0.payload().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::bme280::{
+        Bme280, Bme280Coefficients, Bme280Readout, Bme280Settings, Bme280Variant, Filter, Mode,
+        Oversampling, Standby,
+    };
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+
+    /// Coefficients of the compensation example of the BMP280 datasheet, and typical humidity ones
+    fn calibration_registers() -> ([u8; 26], [u8; 7]) {
+        let mut tp = [0u8; 26];
+        let words: [i32; 12] = [
+            27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            tp[2 * i..2 * i + 2].copy_from_slice(&(*word as u16).to_le_bytes());
+        }
+        tp[25] = 75;
+
+        // H2 = 362, H3 = 0, H4 = 313, H5 = 50, H6 = 30
+        let h = [0x6A, 0x01, 0x00, 0x13, 0x29, 0x03, 0x1E];
+        (tp, h)
+    }
+
+    fn device(chip_id: u8) -> MockI2CDevice {
+        let (tp, h) = calibration_registers();
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0x88, &tp);
+        device.regmap.write_regs(0xD0, &[chip_id]);
+        device.regmap.write_regs(0xE1, &h);
+
+        // adc_P = 415148, adc_T = 519888, adc_H = 30000
+        device
+            .regmap
+            .write_regs(0xF7, &[0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x75, 0x30]);
+        device
+    }
+
+    #[test]
+    fn decode_coefficients() {
+        let (tp, h) = calibration_registers();
+        let coefficients = Bme280Coefficients::from_registers(&tp, Some(&h));
+
+        assert_eq!(coefficients.t1, 27504);
+        assert_eq!(coefficients.t3, -1000);
+        assert_eq!(coefficients.p9, 6000);
+        assert_eq!(coefficients.h1, 75);
+        assert_eq!(coefficients.h2, 362);
+        assert_eq!(coefficients.h4, 313);
+        assert_eq!(coefficients.h5, 50);
+        assert_eq!(coefficients.h6, 30);
+
+        // 12 bits values are sign extended
+        let negative =
+            Bme280Coefficients::from_registers(&tp, Some(&[0, 0, 0, 0xFF, 0xFF, 0xFF, 0]));
+        assert_eq!((negative.h4, negative.h5), (-1, -1));
+    }
+
+    #[test]
+    fn compensation_formulas() {
+        let (tp, h) = calibration_registers();
+        let coefficients = Bme280Coefficients::from_registers(&tp, Some(&h));
+
+        // Datasheet example: 25.08°C and 100653.27Pa
+        let (t_fine, temperature) = coefficients.compensate_temperature(519888);
+        assert_eq!((t_fine, temperature), (128422, 2508));
+        assert_eq!(coefficients.compensate_pressure(415148, t_fine), 25767233);
+        assert_eq!(coefficients.compensate_humidity(30000, t_fine), 56317);
+
+        // A blank calibration doesn't divide by zero
+        assert_eq!(
+            Bme280Coefficients::default().compensate_pressure(415148, t_fine),
+            0
+        );
+    }
+
+    #[test]
+    fn register_settings() {
+        let settings = Bme280Settings {
+            temperature: Oversampling::X2,
+            pressure: Oversampling::X16,
+            humidity: Oversampling::X1,
+            filter: Filter::X16,
+            mode: Mode::Normal(Standby::Ms0_5),
+        };
+
+        assert_eq!(settings.ctrl_meas(0b11), 0b0101_0111);
+        assert_eq!(settings.config(), 0b0001_0000);
+        assert_eq!(settings.measurement_time().as_micros(), 46_100);
+    }
+
+    #[tokio::test]
+    async fn bme280_forced_read() {
+        let mut bme280 = Bme280::new(device(0x60));
+
+        let readouts = bme280.read().await.unwrap();
+        assert_eq!(
+            readouts.as_slice(),
+            [
+                Bme280Readout::Temperature(25.08),
+                Bme280Readout::Humidity(54.997_07),
+                Bme280Readout::Pressure(1006.5325),
+            ]
+        );
+
+        // Settings were applied, the measurement being triggered in forced mode
+        let ctrl = bme280.read_registers::<4>(0xF2).await.unwrap();
+        assert_eq!(ctrl[0], 0b001);
+        assert_eq!(ctrl[2], 0b0010_0101);
+        assert_eq!(ctrl[3], 0);
+    }
+
+    #[tokio::test]
+    async fn bmp280_has_no_humidity() {
+        let mut bmp280 = Bme280::with_settings(
+            device(0x58),
+            Bme280Settings {
+                mode: Mode::Normal(Standby::Ms125),
+                ..Bme280Settings::default()
+            },
+        );
+
+        let (variant, _) = bmp280.initialize().await.unwrap();
+        assert_eq!(variant, Bme280Variant::Bmp280);
+
+        let readouts = bmp280.read().await.unwrap();
+        assert_eq!(readouts.len(), 2);
+        assert!(!readouts
+            .iter()
+            .any(|r| matches!(r, Bme280Readout::Humidity(_))));
+    }
+
+    #[tokio::test]
+    async fn skipped_measurements() {
+        let mut device = device(0x60);
+        device.regmap.write_regs(0xF7, &[0x80, 0x00, 0x00]);
+        device.regmap.write_regs(0xFD, &[0x80, 0x00]);
+
+        let mut bme280 = Bme280::new(device);
+        let readouts = bme280.read().await.unwrap();
+        assert_eq!(readouts.as_slice(), [Bme280Readout::Temperature(25.08)]);
+    }
+
+    #[tokio::test]
+    async fn unknown_chip() {
+        let mut bme280 = Bme280::new(device(0x61));
+        match bme280.read().await {
+            Err(PiWeatherError::I2CError(msg)) => assert!(msg.contains("0x61")),
+            other => panic!("Unknown chip should be rejected, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::sensors::Sensor;
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Humidity, Modality, Payload, Pressure, Quantity, Temperature};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Linear correction `value * scale + offset` applied to a raw readout
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Adjustment {
+    #[serde(default)]
+    pub offset: f32,
+
+    #[serde(default = "Adjustment::default_scale")]
+    pub scale: f32,
+}
+
+impl Adjustment {
+    fn default_scale() -> f32 {
+        1.0
+    }
+
+    #[inline]
+    pub fn is_identity(&self) -> bool {
+        self.offset == 0.0 && self.scale == 1.0
+    }
+
+    #[inline]
+    pub fn apply(&self, value: f32) -> f32 {
+        value * self.scale + self.offset
+    }
+}
+
+impl Default for Adjustment {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            scale: Self::default_scale(),
+        }
+    }
+}
+
+/// Per-modality corrections of a sensor.
+/// Offsets are expressed in the unit the sensor reports the modality in.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Calibration {
+    #[serde(default)]
+    pub temperature: Adjustment,
+
+    #[serde(default)]
+    pub humidity: Adjustment,
+
+    #[serde(default)]
+    pub pressure: Adjustment,
+}
+
+impl Calibration {
+    #[inline]
+    pub fn is_identity(&self) -> bool {
+        self.temperature.is_identity() && self.humidity.is_identity() && self.pressure.is_identity()
+    }
+
+    /// Correct a single readout, modalities without calibration are returned untouched
+    pub fn apply(&self, modality: Modality) -> Modality {
+        match modality {
+            Modality::Temperature(Temperature::Celsius(t)) => {
+                Modality::Temperature(Temperature::Celsius(self.temperature.apply(t)))
+            }
+            Modality::Temperature(Temperature::Fahrenheit(t)) => {
+                Modality::Temperature(Temperature::Fahrenheit(self.temperature.apply(t)))
+            }
+            Modality::Humidity(Humidity::Relative(h)) => {
+                Modality::Humidity(Humidity::Relative(self.humidity.apply(h).clamp(0.0, 100.0)))
+            }
+            Modality::Pressure(p) => {
+                let corrected = self.pressure.apply(p.value()).max(0.0);
+                Modality::Pressure(Pressure::with_unit(corrected, p.unit()).unwrap_or(p))
+            }
+            other => other,
+        }
+    }
+}
+
+/// Sensor decorator correcting every readout with the provided calibration
+pub struct Calibrated {
+    sensor: Box<dyn Sensor>,
+    calibration: Calibration,
+}
+
+impl Calibrated {
+    /// Wrap `sensor`, unless `calibration` wouldn't change anything
+    pub fn wrap(sensor: Box<dyn Sensor>, calibration: Calibration) -> Box<dyn Sensor> {
+        if calibration.is_identity() {
+            sensor
+        } else {
+            Box::new(Self {
+                sensor,
+                calibration,
+            })
+        }
+    }
+}
+
+#[async_trait]
+impl Sensor for Calibrated {
+    fn driver(&self) -> &'static str {
+        self.sensor.driver()
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let mut payload = self.sensor.payload().await?;
+        if let Some(payload) = payload.as_mut() {
+            for readout in payload.readouts_mut() {
+                *readout = self.calibration.apply(*readout);
+            }
+        }
+
+        Ok(payload)
+    }
+
+    fn set_polling_period(&mut self, period: Duration) {
+        self.sensor.set_polling_period(period)
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        self.sensor.compensate(ambient)
+    }
+
+    fn state(&self) -> Option<serde_json::Value> {
+        self.sensor.state()
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), PiWeatherError> {
+        self.sensor.restore(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::{Adjustment, Calibration};
+    use piweather_common::{Humidity, Modality, Pressure, Temperature, Wind};
+
+    #[test]
+    fn identity_calibration() {
+        let calibration = Calibration::default();
+        assert!(calibration.is_identity());
+        assert_eq!(
+            calibration.apply(Modality::Humidity(Humidity::Relative(45.2))),
+            Modality::Humidity(Humidity::Relative(45.2))
+        );
+    }
+
+    #[test]
+    fn calibrate_modalities() {
+        let calibration = Calibration {
+            temperature: Adjustment {
+                offset: -0.5,
+                scale: 1.0,
+            },
+            humidity: Adjustment {
+                offset: 5.0,
+                scale: 1.0,
+            },
+            pressure: Adjustment {
+                offset: 0.0,
+                scale: 1.02,
+            },
+        };
+
+        assert_eq!(
+            calibration.apply(Modality::Temperature(Temperature::Celsius(21.5))),
+            Modality::Temperature(Temperature::Celsius(21.0))
+        );
+        assert_eq!(
+            calibration.apply(Modality::Humidity(Humidity::Relative(97.0))),
+            Modality::Humidity(Humidity::Relative(100.0))
+        );
+        assert_eq!(
+            calibration.apply(Modality::Pressure(Pressure::Hectopascal(1000.0))),
+            Modality::Pressure(Pressure::Hectopascal(1020.0))
+        );
+        assert_eq!(
+            calibration.apply(Modality::Wind(Wind::Kph(12.0))),
+            Modality::Wind(Wind::Kph(12.0))
+        );
+    }
+}
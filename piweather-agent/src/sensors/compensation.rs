@@ -0,0 +1,176 @@
+use crate::sensors::Sensor;
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Sensor decorator publishing every payload, for other sensors to compensate their readouts
+pub struct Shared {
+    sensor: Box<dyn Sensor>,
+    latest: watch::Sender<Option<Payload>>,
+}
+
+impl Shared {
+    pub fn wrap(
+        sensor: Box<dyn Sensor>,
+        latest: watch::Sender<Option<Payload>>,
+    ) -> Box<dyn Sensor> {
+        Box::new(Self { sensor, latest })
+    }
+}
+
+#[async_trait]
+impl Sensor for Shared {
+    fn driver(&self) -> &'static str {
+        self.sensor.driver()
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let payload = self.sensor.payload().await?;
+        if let Some(payload) = &payload {
+            self.latest.send_replace(Some(payload.clone()));
+        }
+
+        Ok(payload)
+    }
+
+    fn set_polling_period(&mut self, period: Duration) {
+        self.sensor.set_polling_period(period)
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        self.sensor.compensate(ambient)
+    }
+
+    fn state(&self) -> Option<serde_json::Value> {
+        self.sensor.state()
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), PiWeatherError> {
+        self.sensor.restore(state)
+    }
+}
+
+/// Sensor decorator feeding the latest payload published by a `Shared` sensor
+/// to the compensation of the wrapped one, before each acquisition
+pub struct Compensated {
+    sensor: Box<dyn Sensor>,
+    ambient: watch::Receiver<Option<Payload>>,
+}
+
+impl Compensated {
+    pub fn wrap(
+        sensor: Box<dyn Sensor>,
+        ambient: watch::Receiver<Option<Payload>>,
+    ) -> Box<dyn Sensor> {
+        Box::new(Self { sensor, ambient })
+    }
+}
+
+#[async_trait]
+impl Sensor for Compensated {
+    fn driver(&self) -> &'static str {
+        self.sensor.driver()
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        // The publisher going away only means no fresher conditions will be available
+        if self.ambient.has_changed().unwrap_or(false) {
+            if let Some(ambient) = self.ambient.borrow_and_update().as_ref() {
+                self.sensor.compensate(ambient);
+            }
+        }
+
+        self.sensor.payload().await
+    }
+
+    fn set_polling_period(&mut self, period: Duration) {
+        self.sensor.set_polling_period(period)
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        self.sensor.compensate(ambient)
+    }
+
+    fn state(&self) -> Option<serde_json::Value> {
+        self.sensor.state()
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), PiWeatherError> {
+        self.sensor.restore(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::{Compensated, Sensor, Shared};
+    use async_trait::async_trait;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Humidity, Modality, Payload, Temperature};
+    use tokio::sync::watch;
+
+    struct AmbientSensor;
+
+    #[async_trait]
+    impl Sensor for AmbientSensor {
+        fn driver(&self) -> &'static str {
+            "ambient"
+        }
+
+        async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+            Ok(Some(Payload::now([
+                Modality::Temperature(Temperature::Celsius(21.0)),
+                Modality::Humidity(Humidity::Relative(40.0)),
+            ])))
+        }
+    }
+
+    #[derive(Default)]
+    struct CompensatedSensor {
+        compensations: usize,
+    }
+
+    #[async_trait]
+    impl Sensor for CompensatedSensor {
+        fn driver(&self) -> &'static str {
+            "compensated"
+        }
+
+        async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+            Ok(None)
+        }
+
+        fn compensate(&mut self, ambient: &Payload) {
+            assert_eq!(ambient.readouts().len(), 2);
+            self.compensations += 1;
+        }
+
+        fn state(&self) -> Option<serde_json::Value> {
+            Some(self.compensations.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn compensate_from_shared_payloads() {
+        let (sender, receiver) = watch::channel(None);
+        let mut ambient = Shared::wrap(Box::new(AmbientSensor), sender);
+        let mut sensor = Compensated::wrap(Box::<CompensatedSensor>::default(), receiver);
+
+        // Nothing published yet
+        assert_eq!(sensor.payload().await.unwrap(), None);
+        assert_eq!(sensor.state(), Some(0.into()));
+
+        assert!(ambient.payload().await.unwrap().is_some());
+        sensor.payload().await.unwrap();
+        assert_eq!(sensor.state(), Some(1.into()));
+
+        // The same payload isn't fed twice
+        sensor.payload().await.unwrap();
+        assert_eq!(sensor.state(), Some(1.into()));
+
+        drop(ambient);
+        sensor.payload().await.unwrap();
+        assert_eq!(sensor.state(), Some(1.into()));
+    }
+}
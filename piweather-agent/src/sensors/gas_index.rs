@@ -0,0 +1,520 @@
+//! Sensirion gas index algorithm, turning the raw signals of the SGP4x into VOC and NOx indices.
+//!
+//! Port of the reference implementation (version 3.2). The algorithm learns the typical raw
+//! signal of the environment over hours, indices expressing the deviation from this baseline:
+//! 100 (VOC) or 1 (NOx) being the average conditions, up to 500.
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GasIndexKind {
+    Voc,
+    Nox,
+}
+
+const INITIAL_BLACKOUT: f32 = 45.0;
+const INDEX_GAIN: f32 = 230.0;
+const SRAW_STD_INITIAL: f32 = 50.0;
+const SRAW_STD_BONUS_VOC: f32 = 220.0;
+const SRAW_STD_NOX: f32 = 2000.0;
+const TAU_MEAN_HOURS: f32 = 12.0;
+const TAU_VARIANCE_HOURS: f32 = 12.0;
+const TAU_INITIAL_MEAN_VOC: f32 = 20.0;
+const TAU_INITIAL_MEAN_NOX: f32 = 1200.0;
+const INIT_DURATION_MEAN_VOC: f32 = 3600.0 * 0.75;
+const INIT_DURATION_MEAN_NOX: f32 = 3600.0 * 4.75;
+const INIT_TRANSITION_MEAN: f32 = 0.01;
+const TAU_INITIAL_VARIANCE: f32 = 2500.0;
+const INIT_DURATION_VARIANCE_VOC: f32 = 3600.0 * 1.45;
+const INIT_DURATION_VARIANCE_NOX: f32 = 3600.0 * 5.70;
+const INIT_TRANSITION_VARIANCE: f32 = 0.01;
+const GATING_THRESHOLD_VOC: f32 = 340.0;
+const GATING_THRESHOLD_NOX: f32 = 30.0;
+const GATING_THRESHOLD_INITIAL: f32 = 510.0;
+const GATING_THRESHOLD_TRANSITION: f32 = 0.09;
+const GATING_VOC_MAX_DURATION_MINUTES: f32 = 60.0 * 3.0;
+const GATING_NOX_MAX_DURATION_MINUTES: f32 = 60.0 * 12.0;
+const GATING_MAX_RATIO: f32 = 0.3;
+const SIGMOID_L: f32 = 500.0;
+const SIGMOID_K_VOC: f32 = -0.0065;
+const SIGMOID_X0_VOC: f32 = 213.0;
+const SIGMOID_K_NOX: f32 = -0.0101;
+const SIGMOID_X0_NOX: f32 = 614.0;
+const VOC_INDEX_OFFSET_DEFAULT: f32 = 100.0;
+const NOX_INDEX_OFFSET_DEFAULT: f32 = 1.0;
+const LP_TAU_FAST: f32 = 20.0;
+const LP_TAU_SLOW: f32 = 500.0;
+const LP_ALPHA: f32 = -0.2;
+const VOC_SRAW_MINIMUM: i32 = 20000;
+const NOX_SRAW_MINIMUM: i32 = 10000;
+const PERSISTENCE_UPTIME_GAMMA: f32 = 3.0 * 3600.0;
+const MVE_GAMMA_SCALING: f32 = 64.0;
+const MVE_ADDITIONAL_GAMMA_MEAN_SCALING: f32 = 8.0;
+const MVE_FIX16_MAX: f32 = 32767.0;
+
+/// Logistic function `1 / (1 + e^(k * (x - x0)))`, saturated far from `x0`
+#[derive(Debug, Copy, Clone, Default)]
+struct Sigmoid {
+    k: f32,
+    x0: f32,
+}
+
+impl Sigmoid {
+    fn process(&self, sample: f32) -> f32 {
+        let x = self.k * (sample - self.x0);
+        if x < -50.0 {
+            1.0
+        } else if x > 50.0 {
+            0.0
+        } else {
+            1.0 / (1.0 + x.exp())
+        }
+    }
+}
+
+/// Adaptive estimation of the mean and standard deviation of the raw signal
+#[derive(Debug, Clone, Default)]
+struct MeanVarianceEstimator {
+    initialized: bool,
+    mean: f32,
+    sraw_offset: f32,
+    std: f32,
+    gamma_mean: f32,
+    gamma_variance: f32,
+    gamma_initial_mean: f32,
+    gamma_initial_variance: f32,
+    current_gamma_mean: f32,
+    current_gamma_variance: f32,
+    uptime_gamma: f32,
+    uptime_gating: f32,
+    gating_duration_minutes: f32,
+    sigmoid: Sigmoid,
+}
+
+/// Smoothing of the index, reacting faster to large variations
+#[derive(Debug, Clone, Default)]
+struct AdaptiveLowpass {
+    initialized: bool,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    x3: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct GasIndexAlgorithm {
+    kind: GasIndexKind,
+    sampling_interval: f32,
+    index_offset: f32,
+    sraw_minimum: i32,
+    gating_max_duration_minutes: f32,
+    init_duration_mean: f32,
+    init_duration_variance: f32,
+    gating_threshold: f32,
+    uptime: f32,
+    sraw: f32,
+    gas_index: f32,
+    mve: MeanVarianceEstimator,
+    mox_sraw_std: f32,
+    mox_sraw_mean: f32,
+    sigmoid_scaled: Sigmoid,
+    sigmoid_scaled_offset_default: f32,
+    lowpass: AdaptiveLowpass,
+}
+
+impl GasIndexAlgorithm {
+    /// Algorithm fed with a raw signal every second
+    pub fn new(kind: GasIndexKind) -> Self {
+        Self::with_sampling_interval(kind, 1.0)
+    }
+
+    /// Algorithm fed with a raw signal every `sampling_interval` seconds,
+    /// validated by Sensirion from 1 to 10 seconds
+    pub fn with_sampling_interval(kind: GasIndexKind, sampling_interval: f32) -> Self {
+        let (
+            index_offset,
+            sraw_minimum,
+            gating_max_duration_minutes,
+            init_duration_mean,
+            init_duration_variance,
+            gating_threshold,
+        ) = match kind {
+            GasIndexKind::Voc => (
+                VOC_INDEX_OFFSET_DEFAULT,
+                VOC_SRAW_MINIMUM,
+                GATING_VOC_MAX_DURATION_MINUTES,
+                INIT_DURATION_MEAN_VOC,
+                INIT_DURATION_VARIANCE_VOC,
+                GATING_THRESHOLD_VOC,
+            ),
+            GasIndexKind::Nox => (
+                NOX_INDEX_OFFSET_DEFAULT,
+                NOX_SRAW_MINIMUM,
+                GATING_NOX_MAX_DURATION_MINUTES,
+                INIT_DURATION_MEAN_NOX,
+                INIT_DURATION_VARIANCE_NOX,
+                GATING_THRESHOLD_NOX,
+            ),
+        };
+
+        let mut algorithm = Self {
+            kind,
+            sampling_interval,
+            index_offset,
+            sraw_minimum,
+            gating_max_duration_minutes,
+            init_duration_mean,
+            init_duration_variance,
+            gating_threshold,
+            uptime: 0.0,
+            sraw: 0.0,
+            gas_index: 0.0,
+            mve: MeanVarianceEstimator::default(),
+            mox_sraw_std: 0.0,
+            mox_sraw_mean: 0.0,
+            sigmoid_scaled: Sigmoid::default(),
+            sigmoid_scaled_offset_default: 0.0,
+            lowpass: AdaptiveLowpass::default(),
+        };
+        algorithm.reset();
+        algorithm
+    }
+
+    pub fn kind(&self) -> GasIndexKind {
+        self.kind
+    }
+
+    /// Restart the learning from scratch
+    pub fn reset(&mut self) {
+        self.uptime = 0.0;
+        self.sraw = 0.0;
+        self.gas_index = 0.0;
+
+        self.mve_set_parameters();
+        self.mox_model_set_parameters(self.mve.std, self.mve_mean());
+        let (x0, k, offset_default) = match self.kind {
+            GasIndexKind::Voc => (SIGMOID_X0_VOC, SIGMOID_K_VOC, VOC_INDEX_OFFSET_DEFAULT),
+            GasIndexKind::Nox => (SIGMOID_X0_NOX, SIGMOID_K_NOX, NOX_INDEX_OFFSET_DEFAULT),
+        };
+        self.sigmoid_scaled = Sigmoid { k, x0 };
+        self.sigmoid_scaled_offset_default = offset_default;
+        self.lowpass = AdaptiveLowpass {
+            initialized: false,
+            a1: self.sampling_interval / (LP_TAU_FAST + self.sampling_interval),
+            a2: self.sampling_interval / (LP_TAU_SLOW + self.sampling_interval),
+            ..AdaptiveLowpass::default()
+        };
+    }
+
+    /// Learned mean and standard deviation of the raw signal, to persist across restarts
+    pub fn states(&self) -> (f32, f32) {
+        (self.mve_mean(), self.mve.std)
+    }
+
+    /// Whether the learned states are worth persisting, after 3 hours of operation
+    pub fn is_settled(&self) -> bool {
+        self.mve.uptime_gamma >= PERSISTENCE_UPTIME_GAMMA
+    }
+
+    /// Resume from states retrieved less than 10 minutes ago, skipping the initial learning.
+    /// Only supported by the VOC algorithm, the NOx one ignores them.
+    pub fn set_states(&mut self, mean: f32, std: f32) {
+        if self.kind == GasIndexKind::Nox {
+            return;
+        }
+
+        self.mve.mean = mean;
+        self.mve.std = std;
+        self.mve.uptime_gamma = PERSISTENCE_UPTIME_GAMMA;
+        self.mve.initialized = true;
+        self.mox_model_set_parameters(self.mve.std, self.mve_mean());
+        self.sraw = mean;
+    }
+
+    /// Index computed from the raw signal `sraw`, 0 during the initial blackout
+    pub fn process(&mut self, sraw: u16) -> u16 {
+        if self.uptime <= INITIAL_BLACKOUT {
+            self.uptime += self.sampling_interval;
+        } else {
+            let sraw = sraw as i32;
+            if sraw > 0 && sraw < 65000 {
+                let sraw = sraw.clamp(self.sraw_minimum + 1, self.sraw_minimum + 32767);
+                self.sraw = (sraw - self.sraw_minimum) as f32;
+            }
+
+            self.gas_index = if self.kind == GasIndexKind::Voc || self.mve.initialized {
+                let index = self.mox_model_process(self.sraw);
+                self.sigmoid_scaled_process(index)
+            } else {
+                self.index_offset
+            };
+
+            self.gas_index = self.lowpass_process(self.gas_index).max(0.5);
+
+            if self.sraw > 0.0 {
+                self.mve_process(self.sraw);
+                self.mox_model_set_parameters(self.mve.std, self.mve_mean());
+            }
+        }
+
+        (self.gas_index + 0.5) as u16
+    }
+
+    fn mve_set_parameters(&mut self) {
+        let interval = self.sampling_interval;
+        let tau_initial_mean = match self.kind {
+            GasIndexKind::Voc => TAU_INITIAL_MEAN_VOC,
+            GasIndexKind::Nox => TAU_INITIAL_MEAN_NOX,
+        };
+
+        self.mve = MeanVarianceEstimator {
+            initialized: false,
+            mean: 0.0,
+            sraw_offset: 0.0,
+            std: SRAW_STD_INITIAL,
+            gamma_mean: ((MVE_ADDITIONAL_GAMMA_MEAN_SCALING * MVE_GAMMA_SCALING)
+                * (interval / 3600.0))
+                / (TAU_MEAN_HOURS + (interval / 3600.0)),
+            gamma_variance: (MVE_GAMMA_SCALING * (interval / 3600.0))
+                / (TAU_VARIANCE_HOURS + (interval / 3600.0)),
+            gamma_initial_mean: ((MVE_ADDITIONAL_GAMMA_MEAN_SCALING * MVE_GAMMA_SCALING)
+                * interval)
+                / (tau_initial_mean + interval),
+            gamma_initial_variance: (MVE_GAMMA_SCALING * interval)
+                / (TAU_INITIAL_VARIANCE + interval),
+            current_gamma_mean: 0.0,
+            current_gamma_variance: 0.0,
+            uptime_gamma: 0.0,
+            uptime_gating: 0.0,
+            gating_duration_minutes: 0.0,
+            sigmoid: Sigmoid::default(),
+        };
+    }
+
+    fn mve_mean(&self) -> f32 {
+        self.mve.mean + self.mve.sraw_offset
+    }
+
+    fn mve_calculate_gamma(&mut self) {
+        let interval = self.sampling_interval;
+        let gas_index = self.gas_index;
+        let mve = &mut self.mve;
+
+        let uptime_limit = MVE_FIX16_MAX - interval;
+        if mve.uptime_gamma < uptime_limit {
+            mve.uptime_gamma += interval;
+        }
+        if mve.uptime_gating < uptime_limit {
+            mve.uptime_gating += interval;
+        }
+
+        mve.sigmoid = Sigmoid {
+            k: INIT_TRANSITION_MEAN,
+            x0: self.init_duration_mean,
+        };
+        let sigmoid_gamma_mean = mve.sigmoid.process(mve.uptime_gamma);
+        let gamma_mean =
+            mve.gamma_mean + (mve.gamma_initial_mean - mve.gamma_mean) * sigmoid_gamma_mean;
+        let gating_threshold_mean = self.gating_threshold
+            + (GATING_THRESHOLD_INITIAL - self.gating_threshold)
+                * mve.sigmoid.process(mve.uptime_gating);
+        mve.sigmoid = Sigmoid {
+            k: GATING_THRESHOLD_TRANSITION,
+            x0: gating_threshold_mean,
+        };
+        let sigmoid_gating_mean = mve.sigmoid.process(gas_index);
+        mve.current_gamma_mean = sigmoid_gating_mean * gamma_mean;
+
+        mve.sigmoid = Sigmoid {
+            k: INIT_TRANSITION_VARIANCE,
+            x0: self.init_duration_variance,
+        };
+        let sigmoid_gamma_variance = mve.sigmoid.process(mve.uptime_gamma);
+        let gamma_variance = mve.gamma_variance
+            + (mve.gamma_initial_variance - mve.gamma_variance)
+                * (sigmoid_gamma_variance - sigmoid_gamma_mean);
+        let gating_threshold_variance = self.gating_threshold
+            + (GATING_THRESHOLD_INITIAL - self.gating_threshold)
+                * mve.sigmoid.process(mve.uptime_gating);
+        mve.sigmoid = Sigmoid {
+            k: GATING_THRESHOLD_TRANSITION,
+            x0: gating_threshold_variance,
+        };
+        let sigmoid_gating_variance = mve.sigmoid.process(gas_index);
+        mve.current_gamma_variance = sigmoid_gating_variance * gamma_variance;
+
+        // Stop gating after too long, adapting to a new baseline
+        mve.gating_duration_minutes += (interval / 60.0)
+            * (((1.0 - sigmoid_gating_mean) * (1.0 + GATING_MAX_RATIO)) - GATING_MAX_RATIO);
+        if mve.gating_duration_minutes < 0.0 {
+            mve.gating_duration_minutes = 0.0;
+        }
+        if mve.gating_duration_minutes > self.gating_max_duration_minutes {
+            mve.uptime_gating = 0.0;
+        }
+    }
+
+    fn mve_process(&mut self, sraw: f32) {
+        if !self.mve.initialized {
+            self.mve.initialized = true;
+            self.mve.sraw_offset = sraw;
+            self.mve.mean = 0.0;
+            return;
+        }
+
+        // Keep the mean close to 0, preserving the precision of the original fixed point
+        if self.mve.mean >= 100.0 || self.mve.mean <= -100.0 {
+            self.mve.sraw_offset += self.mve.mean;
+            self.mve.mean = 0.0;
+        }
+
+        let sraw = sraw - self.mve.sraw_offset;
+        self.mve_calculate_gamma();
+
+        let mve = &mut self.mve;
+        let delta_sgp = (sraw - mve.mean) / MVE_GAMMA_SCALING;
+        let c = if delta_sgp < 0.0 {
+            mve.std - delta_sgp
+        } else {
+            mve.std + delta_sgp
+        };
+        let additional_scaling = if c > 1440.0 {
+            (c / 1440.0) * (c / 1440.0)
+        } else {
+            1.0
+        };
+
+        mve.std = (additional_scaling * (MVE_GAMMA_SCALING - mve.current_gamma_variance)).sqrt()
+            * ((mve.std * (mve.std / (MVE_GAMMA_SCALING * additional_scaling)))
+                + (((mve.current_gamma_variance * delta_sgp) / additional_scaling) * delta_sgp))
+                .sqrt();
+        mve.mean += (mve.current_gamma_mean * delta_sgp) / MVE_ADDITIONAL_GAMMA_MEAN_SCALING;
+    }
+
+    fn mox_model_set_parameters(&mut self, sraw_std: f32, sraw_mean: f32) {
+        self.mox_sraw_std = sraw_std;
+        self.mox_sraw_mean = sraw_mean;
+    }
+
+    fn mox_model_process(&self, sraw: f32) -> f32 {
+        match self.kind {
+            GasIndexKind::Nox => ((sraw - self.mox_sraw_mean) / SRAW_STD_NOX) * INDEX_GAIN,
+            GasIndexKind::Voc => {
+                ((sraw - self.mox_sraw_mean) / (-(self.mox_sraw_std + SRAW_STD_BONUS_VOC)))
+                    * INDEX_GAIN
+            }
+        }
+    }
+
+    fn sigmoid_scaled_process(&self, sample: f32) -> f32 {
+        let x = self.sigmoid_scaled.k * (sample - self.sigmoid_scaled.x0);
+        if x < -50.0 {
+            SIGMOID_L
+        } else if x > 50.0 {
+            0.0
+        } else if sample >= 0.0 {
+            let shift = if self.sigmoid_scaled_offset_default == 1.0 {
+                (500.0 / 499.0) * (1.0 - self.index_offset)
+            } else {
+                (SIGMOID_L - (5.0 * self.index_offset)) / 4.0
+            };
+            ((SIGMOID_L + shift) / (1.0 + x.exp())) - shift
+        } else {
+            (self.index_offset / self.sigmoid_scaled_offset_default) * (SIGMOID_L / (1.0 + x.exp()))
+        }
+    }
+
+    fn lowpass_process(&mut self, sample: f32) -> f32 {
+        let interval = self.sampling_interval;
+        let lowpass = &mut self.lowpass;
+        if !lowpass.initialized {
+            lowpass.x1 = sample;
+            lowpass.x2 = sample;
+            lowpass.x3 = sample;
+            lowpass.initialized = true;
+        }
+
+        lowpass.x1 = (1.0 - lowpass.a1) * lowpass.x1 + lowpass.a1 * sample;
+        lowpass.x2 = (1.0 - lowpass.a2) * lowpass.x2 + lowpass.a2 * sample;
+
+        let abs_delta = (lowpass.x1 - lowpass.x2).abs();
+        let f1 = (LP_ALPHA * abs_delta).exp();
+        let tau_a = (LP_TAU_SLOW - LP_TAU_FAST) * f1 + LP_TAU_FAST;
+        let a3 = interval / (interval + tau_a);
+        lowpass.x3 = (1.0 - a3) * lowpass.x3 + a3 * sample;
+        lowpass.x3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::gas_index::{GasIndexAlgorithm, GasIndexKind};
+
+    /// Feed `sraw` for `seconds`, returning the last index
+    fn feed(algorithm: &mut GasIndexAlgorithm, sraw: u16, seconds: usize) -> u16 {
+        (0..seconds).fold(0, |_, _| algorithm.process(sraw))
+    }
+
+    #[test]
+    fn initial_blackout() {
+        let mut voc = GasIndexAlgorithm::new(GasIndexKind::Voc);
+        assert_eq!(feed(&mut voc, 30000, 45), 0);
+        assert_ne!(feed(&mut voc, 30000, 2), 0);
+    }
+
+    #[test]
+    fn steady_environment_is_average() {
+        let mut voc = GasIndexAlgorithm::new(GasIndexKind::Voc);
+        assert_eq!(feed(&mut voc, 30000, 600), 100);
+
+        let mut nox = GasIndexAlgorithm::new(GasIndexKind::Nox);
+        assert_eq!(feed(&mut nox, 16000, 600), 1);
+    }
+
+    #[test]
+    fn voc_event() {
+        let mut voc = GasIndexAlgorithm::new(GasIndexKind::Voc);
+        feed(&mut voc, 30000, 600);
+
+        // The raw signal drops as the concentration of VOC rises
+        let index = feed(&mut voc, 29000, 60);
+        assert!(index > 150, "VOC index {} should rise", index);
+
+        // Back to the baseline
+        let index = feed(&mut voc, 30000, 1200);
+        assert!(index < 120, "VOC index {} should recover", index);
+    }
+
+    #[test]
+    fn nox_event() {
+        let mut nox = GasIndexAlgorithm::new(GasIndexKind::Nox);
+        feed(&mut nox, 16000, 600);
+
+        // Contrary to VOC, the raw signal rises with the concentration of NOx
+        let index = feed(&mut nox, 18000, 300);
+        assert!(index > 5, "NOx index {} should rise", index);
+    }
+
+    #[test]
+    fn restore_states() {
+        let mut voc = GasIndexAlgorithm::new(GasIndexKind::Voc);
+        feed(&mut voc, 30000, 600);
+        let (mean, std) = voc.states();
+        assert!((mean - 10000.0).abs() < 1.0, "Unexpected mean {}", mean);
+
+        // A restored algorithm doesn't learn the baseline from the first readouts
+        let mut restored = GasIndexAlgorithm::new(GasIndexKind::Voc);
+        restored.set_states(mean, std);
+        assert!(restored.is_settled());
+        assert!(!voc.is_settled());
+        feed(&mut restored, 29000, 46);
+        let index = feed(&mut restored, 29000, 60);
+        assert!(index > 200, "Restored VOC index {} should rise", index);
+
+        let mut nox = GasIndexAlgorithm::new(GasIndexKind::Nox);
+        nox.set_states(mean, std);
+        assert_eq!(
+            nox.states(),
+            GasIndexAlgorithm::new(GasIndexKind::Nox).states()
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::ranging::settle;
+use crate::sensors::registers::{read_registers, write_registers};
+use crate::sensors::{I2CSensor, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Modality, Payload};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const LTR390_DRIVER: &str = "ltr390";
+const LTR390_NAME: &str = "LTR390";
+const LTR390_I2C_SLAVE_ADDRESS: u16 = 0x53;
+
+const LTR390_REGISTER_MAIN_CTRL: u8 = 0x00;
+const LTR390_REGISTER_MEAS_RATE: u8 = 0x04;
+const LTR390_REGISTER_GAIN: u8 = 0x05;
+const LTR390_REGISTER_PART_ID: u8 = 0x06;
+const LTR390_REGISTER_MAIN_STATUS: u8 = 0x07;
+const LTR390_REGISTER_ALS_DATA: u8 = 0x0D;
+const LTR390_REGISTER_UVS_DATA: u8 = 0x10;
+
+const LTR390_PART_ID: u8 = 0xB;
+const LTR390_MAIN_CTRL_ENABLE: u8 = 0x02;
+const LTR390_MAIN_CTRL_UVS_MODE: u8 = 0x08;
+const LTR390_STATUS_DATA_READY: u8 = 0x08;
+const LTR390_STATUS_POLL_ATTEMPTS: usize = 3;
+
+/// Counts per UV index with a x18 gain and a 20 bits resolution
+const LTR390_UV_SENSITIVITY: f32 = 2300.0;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ltr390Gain {
+    /// Full sunlight stays within range
+    #[default]
+    X1,
+    X3,
+    X6,
+    X9,
+    X18,
+}
+
+impl Ltr390Gain {
+    fn bits(&self) -> u8 {
+        match self {
+            Ltr390Gain::X1 => 0b000,
+            Ltr390Gain::X3 => 0b001,
+            Ltr390Gain::X6 => 0b010,
+            Ltr390Gain::X9 => 0b011,
+            Ltr390Gain::X18 => 0b100,
+        }
+    }
+
+    fn factor(&self) -> f32 {
+        match self {
+            Ltr390Gain::X1 => 1.0,
+            Ltr390Gain::X3 => 3.0,
+            Ltr390Gain::X6 => 6.0,
+            Ltr390Gain::X9 => 9.0,
+            Ltr390Gain::X18 => 18.0,
+        }
+    }
+}
+
+/// Resolution of the conversion, the longer ones integrating more light
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ltr390Resolution {
+    Bits20,
+    Bits19,
+    #[default]
+    Bits18,
+    Bits17,
+    Bits16,
+    Bits13,
+}
+
+impl Ltr390Resolution {
+    /// Resolution and the shortest measurement rate it fits in
+    fn bits(&self) -> u8 {
+        let (resolution, rate) = match self {
+            Ltr390Resolution::Bits20 => (0b000, 0b100),
+            Ltr390Resolution::Bits19 => (0b001, 0b011),
+            Ltr390Resolution::Bits18 => (0b010, 0b010),
+            Ltr390Resolution::Bits17 => (0b011, 0b001),
+            Ltr390Resolution::Bits16 => (0b100, 0b000),
+            Ltr390Resolution::Bits13 => (0b101, 0b000),
+        };
+        (resolution << 4) | rate
+    }
+
+    fn conversion_time(&self) -> Duration {
+        Duration::from_micros(match self {
+            Ltr390Resolution::Bits20 => 400_000,
+            Ltr390Resolution::Bits19 => 200_000,
+            Ltr390Resolution::Bits18 => 100_000,
+            Ltr390Resolution::Bits17 => 50_000,
+            Ltr390Resolution::Bits16 => 25_000,
+            Ltr390Resolution::Bits13 => 12_500,
+        })
+    }
+
+    /// Integration relative to the 18 bits conversion
+    fn factor(&self) -> f32 {
+        self.conversion_time().as_secs_f32()
+            / Ltr390Resolution::Bits18.conversion_time().as_secs_f32()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ltr390Settings {
+    pub gain: Ltr390Gain,
+    pub resolution: Ltr390Resolution,
+
+    /// Compensation of the attenuation of the window covering the sensor, 1 without any
+    pub window_factor: f32,
+}
+
+impl Default for Ltr390Settings {
+    fn default() -> Self {
+        Self {
+            gain: Ltr390Gain::default(),
+            resolution: Ltr390Resolution::default(),
+            window_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ltr390Readout {
+    /// Expressed in lux
+    Illuminance(f32),
+    UvIndex(f32),
+}
+
+impl From<Ltr390Readout> for Modality {
+    fn from(value: Ltr390Readout) -> Self {
+        match value {
+            Ltr390Readout::Illuminance(lux) => Modality::Illuminance(lux),
+            Ltr390Readout::UvIndex(index) => Modality::UvIndex(index),
+        }
+    }
+}
+
+pub struct Ltr390<T: I2CDevice + Sized> {
+    settings: Ltr390Settings,
+    started: bool,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Ltr390<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Ltr390Settings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Ltr390Settings) -> Self {
+        Self {
+            settings,
+            started: false,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    async fn read_registers<const N: usize>(
+        &self,
+        register: u8,
+    ) -> Result<[u8; N], PiWeatherError> {
+        read_registers(&self.device, LTR390_NAME, register).await
+    }
+
+    async fn write_register(&self, register: u8, value: u8) -> Result<(), PiWeatherError> {
+        write_registers(&self.device, LTR390_NAME, register, &[value]).await
+    }
+
+    fn lux_from_count(&self, count: u32) -> f32 {
+        let settings = &self.settings;
+        0.6 * count as f32 / (settings.gain.factor() * settings.resolution.factor())
+            * settings.window_factor
+    }
+
+    fn uv_index_from_count(&self, count: u32) -> f32 {
+        let settings = &self.settings;
+        let sensitivity = LTR390_UV_SENSITIVITY
+            * (settings.gain.factor() / 18.0)
+            * (settings.resolution.factor() / Ltr390Resolution::Bits20.factor());
+        count as f32 / sensitivity * settings.window_factor
+    }
+
+    /// Check the identity of the sensor and apply the settings
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        let [part_id] = self.read_registers::<1>(LTR390_REGISTER_PART_ID).await?;
+        if part_id >> 4 != LTR390_PART_ID {
+            return Err(PiWeatherError::I2CError(format!(
+                "Unexpected LTR390 part identifier 0x{:02X}",
+                part_id
+            )));
+        }
+
+        self.write_register(LTR390_REGISTER_GAIN, self.settings.gain.bits())
+            .await?;
+        self.write_register(LTR390_REGISTER_MEAS_RATE, self.settings.resolution.bits())
+            .await?;
+        self.started = true;
+        Ok(())
+    }
+
+    /// Switch to the ambient light or UV mode and read the resulting conversion
+    async fn measure(&self, mode: u8, register: u8) -> Result<u32, PiWeatherError> {
+        self.write_register(LTR390_REGISTER_MAIN_CTRL, LTR390_MAIN_CTRL_ENABLE | mode)
+            .await?;
+
+        let conversion_time = self.settings.resolution.conversion_time();
+        settle(conversion_time).await;
+
+        for _ in 0..LTR390_STATUS_POLL_ATTEMPTS {
+            let [status] = self
+                .read_registers::<1>(LTR390_REGISTER_MAIN_STATUS)
+                .await?;
+            if status & LTR390_STATUS_DATA_READY != 0 {
+                let [low, middle, high] = self.read_registers::<3>(register).await?;
+                return Ok(u32::from_le_bytes([low, middle, high & 0x0F, 0]));
+            }
+
+            sleep(conversion_time).await;
+        }
+
+        Err(PiWeatherError::I2CError(
+            "LTR390 didn't complete its measurement".into(),
+        ))
+    }
+
+    pub async fn read(&mut self) -> Result<[Ltr390Readout; 2], PiWeatherError> {
+        if !self.started {
+            self.start().await?;
+        }
+
+        let ambient = self.measure(0, LTR390_REGISTER_ALS_DATA).await?;
+        let uv = self
+            .measure(LTR390_MAIN_CTRL_UVS_MODE, LTR390_REGISTER_UVS_DATA)
+            .await?;
+
+        Ok([
+            Ltr390Readout::Illuminance(self.lux_from_count(ambient)),
+            Ltr390Readout::UvIndex(self.uv_index_from_count(uv)),
+        ])
+    }
+}
+
+impl<D> I2CSensor<D> for Ltr390<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = LTR390_DRIVER;
+    const DEFAULT_ADDRESS: u16 = LTR390_I2C_SLAVE_ADDRESS;
+    type Settings = Ltr390Settings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        if !(settings.window_factor.is_finite() && settings.window_factor >= 1.0) {
+            return Err(PiWeatherError::InvalidConfiguration(format!(
+                "invalid {} settings: window_factor must be at least 1, got {}",
+                LTR390_DRIVER, settings.window_factor
+            )));
+        }
+
+        let device = factory.open(address)?;
+        Ok(Ltr390::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Ltr390<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        LTR390_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let readouts = self.read().await?;
+        Ok(Some(Payload::now(readouts.map(Modality::from))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::ltr390::{
+        Ltr390, Ltr390Gain, Ltr390Readout, Ltr390Resolution, Ltr390Settings,
+    };
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+
+    fn device() -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0x06, &[0xB2, 0x08]);
+
+        // 10000 counts of ambient light, 230 of UV
+        device.regmap.write_regs(0x0D, &[0x10, 0x27, 0x00]);
+        device.regmap.write_regs(0x10, &[0xE6, 0x00, 0xF0]);
+        device
+    }
+
+    #[tokio::test]
+    async fn ltr390_read() {
+        let mut ltr390 = Ltr390::new(device());
+        match ltr390.read().await.unwrap() {
+            [Ltr390Readout::Illuminance(lux), Ltr390Readout::UvIndex(index)] => {
+                assert!((lux - 6000.0).abs() < 0.01, "Unexpected {} lux", lux);
+                assert!((index - 7.2).abs() < 0.01, "Unexpected UV index {}", index);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // Left measuring UV, with a x1 gain and a 18 bits resolution every 100ms
+        assert_eq!(
+            ltr390.read_registers::<6>(0x00).await.unwrap(),
+            [0x0A, 0x00, 0x00, 0x00, 0x22, 0x00]
+        );
+    }
+
+    #[tokio::test]
+    async fn ltr390_settings() {
+        let mut ltr390 = Ltr390::with_settings(
+            device(),
+            Ltr390Settings {
+                gain: Ltr390Gain::X18,
+                resolution: Ltr390Resolution::Bits20,
+                window_factor: 1.25,
+            },
+        );
+        ltr390.start().await.unwrap();
+        assert_eq!(
+            ltr390.read_registers::<2>(0x04).await.unwrap(),
+            [0x04, 0x04]
+        );
+
+        assert!((ltr390.lux_from_count(10000) - 104.1667).abs() < 0.01);
+        assert!((ltr390.uv_index_from_count(2300) - 1.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Ltr390Settings =
+            toml::from_str("gain = \"x18\"\nresolution = \"bits20\"\nwindow_factor = 1.25")
+                .unwrap();
+        assert_eq!(
+            settings,
+            Ltr390Settings {
+                gain: Ltr390Gain::X18,
+                resolution: Ltr390Resolution::Bits20,
+                window_factor: 1.25,
+            }
+        );
+
+        assert_eq!(
+            toml::from_str::<Ltr390Settings>("").unwrap(),
+            Ltr390Settings::default()
+        );
+        assert!(toml::from_str::<Ltr390Settings>("gain = \"x2\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn ltr390_unknown_device() {
+        let mut device = device();
+        device.regmap.write_regs(0x06, &[0x50]);
+
+        let mut ltr390 = Ltr390::new(device);
+        assert!(matches!(
+            ltr390.read().await,
+            Err(PiWeatherError::I2CError(_))
+        ));
+    }
+}
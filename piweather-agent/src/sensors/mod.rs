@@ -1,20 +1,142 @@
 mod am2315;
+mod bme280;
+mod calibration;
+mod compensation;
+mod gas_index;
+mod ltr390;
+mod persistence;
 mod pmsa003;
+mod ranging;
+mod registers;
+mod registry;
+mod scd30;
+mod scd4x;
+mod sensirion;
+mod sgp4x;
+mod sht3x;
+mod sht4x;
+mod tsl2591;
+mod veml7700;
 
 use crate::i2c::I2CDeviceFactory;
 pub use am2315::*;
+use async_trait::async_trait;
+pub use bme280::*;
+pub use calibration::*;
+pub use compensation::*;
+pub use gas_index::*;
+use i2cdev::core::I2CDevice;
+pub use ltr390::*;
+pub use persistence::*;
 use piweather_common::errors::PiWeatherError;
 use piweather_common::Payload;
+pub use pmsa003::*;
+pub use ranging::{AutoRanging, Ranging};
+pub use registry::*;
+pub use scd30::*;
+pub use scd4x::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+pub use sgp4x::*;
+pub use sht3x::*;
+pub use sht4x::*;
+use std::time::Duration;
+pub use tsl2591::*;
+pub use veml7700::*;
 
-pub trait Sensor<T, D, const N: usize>
+#[async_trait]
+pub trait Sensor: Send {
+    /// Name of the driver handling this sensor
+    fn driver(&self) -> &'static str;
+
+    /// Acquire the latest readouts from the sensor.
+    /// Returns `None` if the sensor has nothing to report yet.
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError>;
+
+    /// Notify the sensor of the time elapsed between two calls to `payload`,
+    /// before the first one
+    fn set_polling_period(&mut self, _period: Duration) {}
+
+    /// Ambient conditions measured by another sensor of the station,
+    /// for sensors whose readouts depend on them
+    fn compensate(&mut self, _ambient: &Payload) {}
+
+    /// Learned state worth persisting across restarts, if any
+    fn state(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Resume from a state previously returned by `state`
+    fn restore(&mut self, _state: serde_json::Value) -> Result<(), PiWeatherError> {
+        Ok(())
+    }
+}
+
+/// Ambient condition measured by a sensor, which others may compensate their readouts for
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Ambient {
+    Temperature,
+    Humidity,
+    Pressure,
+}
+
+impl Ambient {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ambient::Temperature => "temperature",
+            Ambient::Humidity => "humidity",
+            Ambient::Pressure => "pressure",
+        }
+    }
+}
+
+pub trait I2CSensor<D>: Sensor + Sized
 where
-    T: I2CDeviceFactory<Device = D>,
-    Self: Sized,
+    D: I2CDevice + Sized,
 {
-    ///
-    fn with_i2c_factory(factory: T) -> Result<Self, PiWeatherError>;
+    /// Name under which the driver is registered
+    const DRIVER: &'static str;
+
+    /// Address the sensor answers to, unless configured otherwise
+    const DEFAULT_ADDRESS: u16;
+
+    /// Ambient conditions the sensor measures
+    const MEASURES: &'static [Ambient] = &[];
+
+    /// Ambient conditions the readouts are compensated for, all measured by a single other
+    /// sensor. Empty if the driver doesn't compensate its readouts
+    const COMPENSATED_FOR: &'static [Ambient] = &[];
 
-    ///
-    ///
-    fn payload(&mut self) -> Result<Option<Payload<N>>, PiWeatherError>;
+    /// Driver specific settings, read from the `settings` table of the sensor's configuration
+    type Settings: DeserializeOwned + Default;
+
+    /// Open the sensor at `address` on the bus exposed by `factory`, configured with `settings`
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>;
+
+    /// Open the sensor at `address` on the bus exposed by `factory`, with its default settings
+    fn with_i2c_address<F>(factory: F, address: u16) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        Self::with_i2c_settings(factory, address, Self::Settings::default())
+    }
+
+    /// Open the sensor at its default address on the bus exposed by `factory`
+    fn with_i2c_factory<F>(factory: F) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        Self::with_i2c_address(factory, Self::DEFAULT_ADDRESS)
+    }
 }
+
+/// Settings of the drivers which don't have any, rejecting every key
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoSettings {}
@@ -0,0 +1,179 @@
+use crate::sensors::Sensor;
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tracing::{debug, info, warn};
+
+/// Minimum time between two writes of the state
+const PERSISTENCE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn io_error(path: &Path, action: &str, err: std::io::Error) -> PiWeatherError {
+    PiWeatherError::Io(format!("Failed to {} {}: {}", action, path.display(), err))
+}
+
+/// Sensor decorator saving the state learned by the sensor to a JSON file,
+/// restoring it when the agent restarts
+pub struct Persisted {
+    sensor: Box<dyn Sensor>,
+    path: PathBuf,
+    last_save: Option<Instant>,
+}
+
+impl Persisted {
+    /// Wrap `sensor`, restoring the state found at `path` if any.
+    /// An unreadable or rejected state is reported and the sensor starts from scratch.
+    pub async fn wrap<P: Into<PathBuf>>(mut sensor: Box<dyn Sensor>, path: P) -> Box<dyn Sensor> {
+        let path = path.into();
+        match Self::load(&path).await {
+            Ok(Some(state)) => match sensor.restore(state) {
+                Ok(()) => info!("Restored {} state from {}", sensor.driver(), path.display()),
+                Err(err) => warn!("Discarding {} state: {}", sensor.driver(), err),
+            },
+            Ok(None) => debug!("No state to restore from {}", path.display()),
+            Err(err) => warn!("Discarding {} state: {}", sensor.driver(), err),
+        }
+
+        Box::new(Self {
+            sensor,
+            path,
+            last_save: None,
+        })
+    }
+
+    async fn load(path: &Path) -> Result<Option<serde_json::Value>, PiWeatherError> {
+        let content = match fs::read(path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(io_error(path, "read", err)),
+        };
+
+        serde_json::from_slice(&content).map(Some).map_err(|err| {
+            PiWeatherError::Io(format!("Invalid state in {}: {}", path.display(), err))
+        })
+    }
+
+    /// Atomically persist `state` to `path`
+    async fn save(path: &Path, state: serde_json::Value) -> Result<(), PiWeatherError> {
+        let staging = path.with_extension("tmp");
+        fs::write(&staging, state.to_string())
+            .await
+            .map_err(|err| io_error(&staging, "write", err))?;
+        fs::rename(&staging, path)
+            .await
+            .map_err(|err| io_error(path, "write", err))
+    }
+}
+
+#[async_trait]
+impl Sensor for Persisted {
+    fn driver(&self) -> &'static str {
+        self.sensor.driver()
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let payload = self.sensor.payload().await?;
+
+        let due = self
+            .last_save
+            .is_none_or(|last_save| last_save.elapsed() >= PERSISTENCE_INTERVAL);
+        let state = self.sensor.state().filter(|_| payload.is_some() && due);
+        if let Some(state) = state {
+            // Failing to save the state doesn't invalidate the readouts
+            match Self::save(&self.path, state).await {
+                Ok(()) => self.last_save = Some(Instant::now()),
+                Err(err) => warn!("Failed to save {} state: {}", self.sensor.driver(), err),
+            }
+        }
+
+        Ok(payload)
+    }
+
+    fn set_polling_period(&mut self, period: Duration) {
+        self.sensor.set_polling_period(period)
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        self.sensor.compensate(ambient)
+    }
+
+    fn state(&self) -> Option<serde_json::Value> {
+        self.sensor.state()
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), PiWeatherError> {
+        self.sensor.restore(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::{Persisted, Sensor};
+    use async_trait::async_trait;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Modality, Payload, Pressure};
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct LearningSensor {
+        samples: u64,
+    }
+
+    #[async_trait]
+    impl Sensor for LearningSensor {
+        fn driver(&self) -> &'static str {
+            "learning"
+        }
+
+        async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+            self.samples += 1;
+            Ok(Some(Payload::now([Modality::Pressure(
+                Pressure::Hectopascal(1013.0),
+            )])))
+        }
+
+        fn state(&self) -> Option<serde_json::Value> {
+            Some(json!({ "samples": self.samples }))
+        }
+
+        fn restore(&mut self, state: serde_json::Value) -> Result<(), PiWeatherError> {
+            self.samples = state["samples"]
+                .as_u64()
+                .ok_or_else(|| PiWeatherError::Io("missing samples".to_string()))?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn persist_state_across_restarts() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("learning.json");
+
+        let mut sensor = Persisted::wrap(Box::<LearningSensor>::default(), &path).await;
+        assert_eq!(sensor.state(), Some(json!({ "samples": 0 })));
+        sensor.payload().await.unwrap();
+
+        // Saved at most once a minute
+        sensor.payload().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), r#"{"samples":1}"#);
+
+        let restarted = Persisted::wrap(Box::<LearningSensor>::default(), &path).await;
+        assert_eq!(restarted.state(), Some(json!({ "samples": 1 })));
+    }
+
+    #[tokio::test]
+    async fn discard_invalid_state() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("learning.json");
+
+        std::fs::write(&path, "{").unwrap();
+        let sensor = Persisted::wrap(Box::<LearningSensor>::default(), &path).await;
+        assert_eq!(sensor.state(), Some(json!({ "samples": 0 })));
+
+        std::fs::write(&path, "{}").unwrap();
+        let sensor = Persisted::wrap(Box::<LearningSensor>::default(), &path).await;
+        assert_eq!(sensor.state(), Some(json!({ "samples": 0 })));
+    }
+}
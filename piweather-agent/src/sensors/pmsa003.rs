@@ -1,9 +1,11 @@
-use crate::i2c::I2CDeviceFactory;
-use crate::sensors::Sensor;
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::{I2CSensor, NoSettings, Sensor};
+use async_trait::async_trait;
 use i2cdev::core::I2CDevice;
 use piweather_common::errors::PiWeatherError;
-use piweather_common::{Modality, Particle, Payload};
+use piweather_common::{AirQuality, ConcentrationUnit, Modality, Particle, Payload};
 
+const PMSA003_DRIVER: &str = "pmsa003";
 const PMSA003_I2C_SLAVE_ADDRESS: u16 = 0x12;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -29,45 +31,58 @@ impl From<PmsA003Particle> for Particle {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum ConcentrationUnit {
-    Standard,
-    Environmental,
-}
-
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum PmsA003Readout {
     Concentration(PmsA003Particle, ConcentrationUnit, u16),
     Count(PmsA003Particle, u16),
 }
 
+impl From<PmsA003Readout> for AirQuality {
+    fn from(value: PmsA003Readout) -> Self {
+        match value {
+            PmsA003Readout::Concentration(particle, unit, c) => {
+                AirQuality::Concentration(particle.into(), unit, c)
+            }
+            PmsA003Readout::Count(particle, c) => AirQuality::Count(particle.into(), c),
+        }
+    }
+}
+
 impl From<PmsA003Readout> for Modality {
     fn from(value: PmsA003Readout) -> Self {
-        value.into()
+        Modality::AirQuality(value.into())
     }
 }
 
 pub struct PmsA003<T: I2CDevice + Sized> {
-    device: T,
+    device: AsyncI2CDevice<T>,
 }
 
 impl<T> PmsA003<T>
 where
-    T: I2CDevice + Sized,
+    T: I2CDevice + Send + 'static,
 {
     pub fn new(device: T) -> Self {
-        Self { device }
+        Self {
+            device: AsyncI2CDevice::new(device),
+        }
     }
 
-    fn read(&mut self) -> Result<Option<[PmsA003Readout; 12]>, PiWeatherError> {
-        let mut data = [0u8; 32];
-
-        self.device.read(&mut data).map_err(|e| {
-            PiWeatherError::I2CError(format!("Failed to read data from PmsA003: {}", e))
-        })?;
+    /// Read and decode the latest frame emitted by the sensor
+    pub async fn read(&mut self) -> Result<Option<[PmsA003Readout; 12]>, PiWeatherError> {
+        let data = self
+            .device
+            .transaction(|device| {
+                let mut data = [0u8; 32];
+                device.read(&mut data).map_err(|e| {
+                    PiWeatherError::I2CError(format!("Failed to read data from PmsA003: {}", e))
+                })?;
+                Ok(data)
+            })
+            .await?;
 
         // Check headers and size of the payload
-        if data[0] != 'B' as u8 || data[1] != 'M' as u8 {
+        if data[0] != b'B' || data[1] != b'M' {
             return Err(PiWeatherError::I2CError(
                 "Invalid header received from PmsA003".into(),
             ));
@@ -85,9 +100,11 @@ where
         let sum: u16 = data[0..30].iter().fold(0u16, |acc, x| acc + (*x as u16));
 
         if sum != checksum {
-            return Err(PiWeatherError::I2CError(
-                "Invalid checksum data received from PmsA003".into(),
-            ));
+            return Err(PiWeatherError::ChecksumMismatch {
+                sensor: "PmsA003",
+                expected: checksum,
+                computed: sum,
+            });
         }
 
         Ok(Some([
@@ -149,20 +166,39 @@ where
     }
 }
 
-impl<F, D> Sensor<F, D, 12> for PmsA003<D>
+impl<D> I2CSensor<D> for PmsA003<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = PMSA003_DRIVER;
+    const DEFAULT_ADDRESS: u16 = PMSA003_I2C_SLAVE_ADDRESS;
+    type Settings = NoSettings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        _settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(PmsA003::new(device))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for PmsA003<D>
 where
-    F: I2CDeviceFactory<Device = D>,
-    D: I2CDevice + Sized,
-    Self: Sized,
+    D: I2CDevice + Send + 'static,
 {
-    fn with_i2c_factory(factory: F) -> Result<Self, PiWeatherError> {
-        let device = factory.open(PMSA003_I2C_SLAVE_ADDRESS)?;
-        Ok(Self { device })
+    fn driver(&self) -> &'static str {
+        PMSA003_DRIVER
     }
 
-    fn payload(&mut self) -> Result<Option<Payload<12>>, PiWeatherError> {
-        if let Some(readouts) = self.read()? {
-            let modalities = readouts.map(|r| Modality::from(r));
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        if let Some(readouts) = self.read().await? {
+            let modalities = readouts.map(Modality::from);
             return Ok(Some(Payload::now(modalities)));
         }
 
@@ -172,15 +208,134 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::sensors::pmsa003::ConcentrationUnit::{Environmental, Standard};
     use crate::sensors::pmsa003::{PmsA003, PmsA003Particle, PmsA003Readout};
+    use crate::sensors::Sensor;
     use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::ConcentrationUnit::{Environmental, Standard};
+    use piweather_common::{AirQuality, Modality, Particle};
+
+    // Frames recorded from a PMSA003 sensor, respectively indoor and outdoor on a hazy day
+    const INDOOR_FRAME: [u8; 32] = [
+        0x42, 0x4D, 0x00, 0x1C, 0x00, 0x05, 0x00, 0x08, 0x00, 0x09, 0x00, 0x05, 0x00, 0x08, 0x00,
+        0x09, 0x04, 0x08, 0x01, 0x29, 0x00, 0x38, 0x00, 0x04, 0x00, 0x02, 0x00, 0x00, 0x97, 0x00,
+        0x01, 0xE2,
+    ];
+
+    const OUTDOOR_FRAME: [u8; 32] = [
+        0x42, 0x4D, 0x00, 0x1C, 0x00, 0x23, 0x00, 0x34, 0x00, 0x3D, 0x00, 0x1E, 0x00, 0x2F, 0x00,
+        0x3D, 0x17, 0x85, 0x06, 0xE3, 0x01, 0x74, 0x00, 0x1F, 0x00, 0x06, 0x00, 0x02, 0x97, 0x00,
+        0x04, 0x81,
+    ];
+
+    fn with_frame(frame: &[u8; 32]) -> PmsA003<MockI2CDevice> {
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0x0, frame);
+        PmsA003::new(device)
+    }
 
     #[test]
-    fn pmsa003_read() {
+    fn readout_to_modality() {
+        assert_eq!(
+            Modality::from(PmsA003Readout::Concentration(
+                PmsA003Particle::PM2_5,
+                Environmental,
+                12
+            )),
+            Modality::AirQuality(AirQuality::Concentration(
+                Particle::PM2_5,
+                Environmental,
+                12
+            ))
+        );
+        assert_eq!(
+            Modality::from(PmsA003Readout::Count(PmsA003Particle::PM0_3, 1032)),
+            Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 1032))
+        );
+    }
+
+    #[tokio::test]
+    async fn pmsa003_indoor_payload() {
+        let mut pmsa003 = with_frame(&INDOOR_FRAME);
+        let payload = pmsa003
+            .payload()
+            .await
+            .expect("Error while reading from the sensor")
+            .expect("PmsA003 returned no data");
+
+        assert_eq!(
+            payload.readouts(),
+            &[
+                Modality::AirQuality(AirQuality::Concentration(Particle::PM1_0, Standard, 5)),
+                Modality::AirQuality(AirQuality::Concentration(Particle::PM2_5, Standard, 8)),
+                Modality::AirQuality(AirQuality::Concentration(Particle::PM10_0, Standard, 9)),
+                Modality::AirQuality(AirQuality::Concentration(Particle::PM1_0, Environmental, 5)),
+                Modality::AirQuality(AirQuality::Concentration(Particle::PM2_5, Environmental, 8)),
+                Modality::AirQuality(AirQuality::Concentration(
+                    Particle::PM10_0,
+                    Environmental,
+                    9
+                )),
+                Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 1032)),
+                Modality::AirQuality(AirQuality::Count(Particle::PM0_5, 297)),
+                Modality::AirQuality(AirQuality::Count(Particle::PM1_0, 56)),
+                Modality::AirQuality(AirQuality::Count(Particle::PM2_5, 4)),
+                Modality::AirQuality(AirQuality::Count(Particle::PM5_0, 2)),
+                Modality::AirQuality(AirQuality::Count(Particle::PM10_0, 0)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pmsa003_outdoor_payload() {
+        let mut pmsa003 = with_frame(&OUTDOOR_FRAME);
+        let payload = pmsa003
+            .payload()
+            .await
+            .expect("Error while reading from the sensor")
+            .expect("PmsA003 returned no data");
+
+        let readouts = payload.readouts();
+        assert_eq!(readouts.len(), 12);
+        assert_eq!(
+            readouts[1],
+            Modality::AirQuality(AirQuality::Concentration(Particle::PM2_5, Standard, 52))
+        );
+        assert_eq!(
+            readouts[4],
+            Modality::AirQuality(AirQuality::Concentration(
+                Particle::PM2_5,
+                Environmental,
+                47
+            ))
+        );
+        assert_eq!(
+            readouts[6],
+            Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 6021))
+        );
+    }
+
+    #[tokio::test]
+    async fn pmsa003_invalid_checksum() {
+        let mut frame = OUTDOOR_FRAME;
+        frame[7] ^= 0x01;
+
+        let mut pmsa003 = with_frame(&frame);
+        assert!(matches!(
+            pmsa003.payload().await,
+            Err(PiWeatherError::ChecksumMismatch {
+                expected: 0x0481,
+                computed: 0x0482,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn pmsa003_read() {
         const REGISTER: [u8; 32] = [
-            'B' as u8,
-            'M' as u8,
+            b'B',
+            b'M',
             0,
             28,
             1,
@@ -215,12 +370,12 @@ mod tests {
 
         // Write dummy data to the register
         let mut device = MockI2CDevice::new();
-        (&mut device.regmap).write_regs(0x0, &REGISTER);
+        device.regmap.write_regs(0x0, &REGISTER);
 
         let mut pmsa003 = PmsA003::new(device);
 
         // Handle read
-        let readouts = pmsa003.read();
+        let readouts = pmsa003.read().await;
         assert!(readouts.is_ok(), "Error while reading from the sensor");
 
         let readouts = readouts.unwrap();
@@ -0,0 +1,132 @@
+//! Automatic ranging shared by the light sensors: settings are ordered by increasing sensitivity,
+//! the sensor moving to the next one when its count is too low and back when it saturates.
+
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use serde::Deserialize;
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::debug;
+
+/// How a light sensor picks its gain and integration time, configured as `"auto"` or
+/// `{ fixed = ["x2", "ms100"] }`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranging<G, I> {
+    /// Adjust to the ambient light, keeping the count within the resolution of the sensor
+    #[default]
+    Auto,
+    Fixed(G, I),
+}
+
+/// Light sensor following the ambient light along a ladder of gains and integration times
+#[async_trait]
+pub trait AutoRanging: Send + Sync {
+    type Gain: Copy + Debug + Send + Sync + 'static;
+    type IntegrationTime: Copy + Debug + Send + Sync + 'static;
+
+    /// Channels read by a measurement
+    type Counts: Send;
+
+    /// Name of the sensor, in the logs
+    const NAME: &'static str;
+
+    /// Gains and integration times by increasing sensitivity
+    const RANGES: &'static [(Self::Gain, Self::IntegrationTime)];
+
+    fn ranging(&self) -> Ranging<Self::Gain, Self::IntegrationTime>;
+
+    /// Position in `RANGES` of the range in use when ranging automatically
+    fn range_index(&self) -> usize;
+
+    fn set_range_index(&mut self, index: usize);
+
+    /// Count the range is picked from
+    fn count(counts: &Self::Counts) -> u32;
+
+    /// Counts between which the current range is accurate
+    fn count_limits(&self) -> (u32, u32);
+
+    /// Apply the current range and wait for a measurement
+    async fn configure(&self) -> Result<(), PiWeatherError>;
+
+    async fn measure(&self) -> Result<Self::Counts, PiWeatherError>;
+
+    /// Gain and integration time currently in use
+    fn range(&self) -> (Self::Gain, Self::IntegrationTime) {
+        match self.ranging() {
+            Ranging::Auto => Self::RANGES[self.range_index()],
+            Ranging::Fixed(gain, integration_time) => (gain, integration_time),
+        }
+    }
+
+    /// Measure, moving along the ranges first when ranging automatically
+    async fn measure_in_range(&mut self) -> Result<Self::Counts, PiWeatherError> {
+        let mut counts = self.measure().await?;
+        if !matches!(self.ranging(), Ranging::Auto) {
+            return Ok(counts);
+        }
+
+        for _ in 0..Self::RANGES.len() {
+            let count = Self::count(&counts);
+            let (low, high) = self.count_limits();
+            let Some(index) =
+                adjust_range(self.range_index(), Self::RANGES.len(), count, low, high)
+            else {
+                break;
+            };
+
+            debug!(
+                "{} count {}, switching to {:?}",
+                Self::NAME,
+                count,
+                Self::RANGES[index]
+            );
+            self.set_range_index(index);
+            self.configure().await?;
+            counts = self.measure().await?;
+        }
+
+        Ok(counts)
+    }
+}
+
+/// Position in a ladder of `len` settings the sensor should move to, `None` if `count` is fine
+pub(crate) fn adjust_range(
+    index: usize,
+    len: usize,
+    count: u32,
+    low: u32,
+    high: u32,
+) -> Option<usize> {
+    if count < low && index + 1 < len {
+        Some(index + 1)
+    } else if count > high && index > 0 {
+        Some(index - 1)
+    } else {
+        None
+    }
+}
+
+/// Wait until a conversion completed with the new configuration or mode: the one in progress
+/// when it changed isn't reliable
+pub(crate) async fn settle(conversion_time: Duration) {
+    sleep(conversion_time * 2).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::ranging::adjust_range;
+
+    #[test]
+    fn adjust_within_ladder() {
+        assert_eq!(adjust_range(2, 5, 500, 100, 10000), None);
+        assert_eq!(adjust_range(2, 5, 50, 100, 10000), Some(3));
+        assert_eq!(adjust_range(2, 5, 20000, 100, 10000), Some(1));
+
+        // Already at the ends of the ladder
+        assert_eq!(adjust_range(4, 5, 50, 100, 10000), None);
+        assert_eq!(adjust_range(0, 5, 20000, 100, 10000), None);
+    }
+}
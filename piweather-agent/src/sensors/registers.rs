@@ -0,0 +1,75 @@
+//! Access to the 8 bits registers of the sensors: the address of the register is written first,
+//! followed by the values to write, or the values are read back.
+
+use crate::i2c::AsyncI2CDevice;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+
+/// Read `N` consecutive registers, starting at `register`
+pub(crate) async fn read_registers<D, const N: usize>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+    register: u8,
+) -> Result<[u8; N], PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    device
+        .transaction(move |device| {
+            let mut data = [0u8; N];
+            device
+                .write(&[register])
+                .and_then(|_| device.read(&mut data))
+                .map_err(|e| {
+                    PiWeatherError::I2CError(format!(
+                        "Failed to read register 0x{:02X} from {}: {}",
+                        register, sensor, e
+                    ))
+                })?;
+            Ok(data)
+        })
+        .await
+}
+
+/// Write `values` to the consecutive registers starting at `register`
+pub(crate) async fn write_registers<D>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+    register: u8,
+    values: &[u8],
+) -> Result<(), PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    let mut data = vec![register];
+    data.extend_from_slice(values);
+    device
+        .transaction(move |device| {
+            device.write(&data).map_err(|e| {
+                PiWeatherError::I2CError(format!(
+                    "Failed to write register 0x{:02X} of {}: {}",
+                    register, sensor, e
+                ))
+            })
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i2c::AsyncI2CDevice;
+    use crate::sensors::registers::{read_registers, write_registers};
+    use i2cdev::mock::MockI2CDevice;
+
+    #[tokio::test]
+    async fn write_then_read_back() {
+        let device = AsyncI2CDevice::new(MockI2CDevice::new());
+        write_registers(&device, "mock", 0x04, &[0x12, 0x34])
+            .await
+            .unwrap();
+        assert_eq!(
+            read_registers::<_, 3>(&device, "mock", 0x03).await.unwrap(),
+            [0x00, 0x12, 0x34]
+        );
+    }
+}
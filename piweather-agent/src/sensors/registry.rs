@@ -0,0 +1,245 @@
+use crate::i2c::I2CDeviceFactory;
+use crate::sensors::{
+    Am2315, Ambient, Bme280, Bmp280, I2CSensor, Ltr390, PmsA003, Scd30, Scd4x, Sensor, Sgp40,
+    Sgp41, Sht3x, Sht4x, Tsl2591, Veml7700,
+};
+use piweather_common::errors::PiWeatherError;
+use std::collections::BTreeMap;
+use toml::{Table, Value};
+
+/// Open a sensor on the bus exposed by the factory, at the provided address or the default one,
+/// configured with the driver specific settings
+pub type SensorConstructor<F> =
+    fn(&F, Option<u16>, &Table) -> Result<Box<dyn Sensor>, PiWeatherError>;
+
+/// What a registered driver measures and compensates its readouts for
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DriverInfo {
+    pub name: &'static str,
+    pub measures: &'static [Ambient],
+    pub compensated_for: &'static [Ambient],
+}
+
+/// Drivers available to the agent, keyed by their name
+pub struct SensorRegistry<F: I2CDeviceFactory> {
+    drivers: BTreeMap<&'static str, (DriverInfo, SensorConstructor<F>)>,
+}
+
+fn construct<S, F>(
+    factory: &F,
+    address: Option<u16>,
+    settings: &Table,
+) -> Result<Box<dyn Sensor>, PiWeatherError>
+where
+    S: I2CSensor<F::Device> + 'static,
+    F: I2CDeviceFactory,
+{
+    let settings = Value::Table(settings.clone()).try_into().map_err(|err| {
+        PiWeatherError::InvalidConfiguration(format!("invalid {} settings: {}", S::DRIVER, err))
+    })?;
+
+    let sensor = S::with_i2c_settings(factory, address.unwrap_or(S::DEFAULT_ADDRESS), settings)?;
+    Ok(Box::new(sensor))
+}
+
+impl<F> SensorRegistry<F>
+where
+    F: I2CDeviceFactory,
+    F::Device: Send + 'static,
+{
+    /// Create a registry without any driver
+    pub fn empty() -> Self {
+        Self {
+            drivers: BTreeMap::new(),
+        }
+    }
+
+    /// Create a registry with all the drivers shipped with the agent
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register::<Am2315<F::Device>>();
+        registry.register::<Bme280<F::Device>>();
+        registry.register::<Bmp280<F::Device>>();
+        registry.register::<Ltr390<F::Device>>();
+        registry.register::<PmsA003<F::Device>>();
+        registry.register::<Scd30<F::Device>>();
+        registry.register::<Scd4x<F::Device>>();
+        registry.register::<Sgp40<F::Device>>();
+        registry.register::<Sgp41<F::Device>>();
+        registry.register::<Sht3x<F::Device>>();
+        registry.register::<Sht4x<F::Device>>();
+        registry.register::<Tsl2591<F::Device>>();
+        registry.register::<Veml7700<F::Device>>();
+        registry
+    }
+
+    /// Register the driver `S` under its name, replacing any driver with the same name
+    pub fn register<S>(&mut self)
+    where
+        S: I2CSensor<F::Device> + 'static,
+    {
+        let info = DriverInfo {
+            name: S::DRIVER,
+            measures: S::MEASURES,
+            compensated_for: S::COMPENSATED_FOR,
+        };
+        self.drivers.insert(S::DRIVER, (info, construct::<S, F>));
+    }
+
+    /// Names of the registered drivers, in alphabetical order
+    pub fn drivers(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.drivers.keys().copied()
+    }
+
+    /// Description of the registered drivers, in alphabetical order
+    pub fn infos(&self) -> impl Iterator<Item = DriverInfo> + '_ {
+        self.drivers.values().map(|(info, _)| *info)
+    }
+
+    /// Open the sensor handled by `driver` on the bus exposed by `factory`, configured with
+    /// its driver specific `settings`
+    pub fn open(
+        &self,
+        driver: &str,
+        factory: &F,
+        address: Option<u16>,
+        settings: &Table,
+    ) -> Result<Box<dyn Sensor>, PiWeatherError> {
+        let (_, constructor) = self.drivers.get(driver).ok_or_else(|| {
+            let available = self.drivers().collect::<Vec<_>>().join(", ");
+            PiWeatherError::UnknownSensorDriver(format!("{} (available: {})", driver, available))
+        })?;
+
+        constructor(factory, address, settings)
+    }
+}
+
+impl<F> Default for SensorRegistry<F>
+where
+    F: I2CDeviceFactory,
+    F::Device: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i2c::I2CDeviceFactory;
+    use crate::sensors::{Ambient, SensorRegistry};
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+    use std::cell::RefCell;
+    use toml::Table;
+
+    #[derive(Default)]
+    struct MockI2CDeviceFactory {
+        opened: RefCell<Vec<u16>>,
+    }
+
+    impl I2CDeviceFactory for MockI2CDeviceFactory {
+        type Device = MockI2CDevice;
+
+        fn open(&self, address: u16) -> Result<Self::Device, PiWeatherError> {
+            self.opened.borrow_mut().push(address);
+            Ok(MockI2CDevice::new())
+        }
+    }
+
+    #[test]
+    fn builtin_drivers() {
+        let registry = SensorRegistry::<MockI2CDeviceFactory>::new();
+        assert_eq!(
+            registry.drivers().collect::<Vec<_>>(),
+            vec![
+                "am2315", "bme280", "bmp280", "ltr390", "pmsa003", "scd30", "scd4x", "sgp40",
+                "sgp41", "sht3x", "sht4x", "tsl2591", "veml7700"
+            ]
+        );
+    }
+
+    #[test]
+    fn builtin_driver_infos() {
+        let registry = SensorRegistry::<MockI2CDeviceFactory>::new();
+        let infos = registry.infos().collect::<Vec<_>>();
+        assert_eq!(infos.len(), registry.drivers().count());
+
+        let sgp41 = infos.iter().find(|info| info.name == "sgp41").unwrap();
+        assert_eq!(
+            sgp41.compensated_for,
+            &[Ambient::Humidity, Ambient::Temperature]
+        );
+        assert!(sgp41.measures.is_empty());
+
+        let bme280 = infos.iter().find(|info| info.name == "bme280").unwrap();
+        assert!(bme280.measures.contains(&Ambient::Pressure));
+        assert!(bme280.compensated_for.is_empty());
+    }
+
+    #[test]
+    fn open_registered_drivers() {
+        let factory = MockI2CDeviceFactory::default();
+        let registry = SensorRegistry::new();
+
+        let am2315 = registry
+            .open("am2315", &factory, None, &Table::new())
+            .unwrap();
+        assert_eq!(am2315.driver(), "am2315");
+
+        let pmsa003 = registry
+            .open("pmsa003", &factory, Some(0x13), &Table::new())
+            .unwrap();
+        assert_eq!(pmsa003.driver(), "pmsa003");
+
+        let settings = "filter = \"x16\"\nmode = { normal = \"ms125\" }"
+            .parse()
+            .unwrap();
+        let bme280 = registry.open("bme280", &factory, None, &settings).unwrap();
+        assert_eq!(bme280.driver(), "bme280");
+
+        let settings = "forced_recalibration_ppm = 420".parse().unwrap();
+        let scd30 = registry.open("scd30", &factory, None, &settings).unwrap();
+        assert_eq!(scd30.driver(), "scd30");
+        let scd4x = registry.open("scd4x", &factory, None, &settings).unwrap();
+        assert_eq!(scd4x.driver(), "scd4x");
+
+        assert_eq!(*factory.opened.borrow(), vec![0x5C, 0x13, 0x76, 0x61, 0x62]);
+    }
+
+    #[test]
+    fn open_with_invalid_settings() {
+        let factory = MockI2CDeviceFactory::default();
+        let registry = SensorRegistry::new();
+
+        for (driver, settings) in [
+            ("bme280", "filter = \"x3\""),
+            ("bmp280", "temperature = \"skipped\""),
+            ("am2315", "heater = true"),
+            ("ltr390", "window_factor = 0.5"),
+            ("scd30", "forced_recalibration_ppm = 100"),
+        ] {
+            match registry.open(driver, &factory, None, &settings.parse().unwrap()) {
+                Err(PiWeatherError::InvalidConfiguration(msg)) => assert!(msg.contains(driver)),
+                _ => panic!("{} should not be opened with {}", driver, settings),
+            }
+        }
+        assert!(factory.opened.borrow().is_empty());
+    }
+
+    #[test]
+    fn open_unknown_driver() {
+        let factory = MockI2CDeviceFactory::default();
+        let registry = SensorRegistry::new();
+
+        match registry.open("dht22", &factory, None, &Table::new()) {
+            Err(PiWeatherError::UnknownSensorDriver(msg)) => {
+                assert!(msg.starts_with("dht22"));
+                assert!(msg.contains(
+                    "am2315, bme280, bmp280, ltr390, pmsa003, scd30, scd4x, sgp40, sgp41, sht3x, sht4x, tsl2591, veml7700"
+                ));
+            }
+            _ => panic!("dht22 driver should not be registered"),
+        }
+    }
+}
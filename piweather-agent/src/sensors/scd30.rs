@@ -0,0 +1,519 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::sensirion::{encode_command, query, write_command};
+use crate::sensors::{Ambient, I2CSensor, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Humidity, Modality, Payload, Pressure, Quantity, Temperature};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const SCD30_DRIVER: &str = "scd30";
+const SCD30_NAME: &str = "SCD30";
+const SCD30_I2C_SLAVE_ADDRESS: u16 = 0x61;
+
+const SCD30_COMMAND_START_CONTINUOUS_MEASUREMENT: u16 = 0x0010;
+const SCD30_COMMAND_STOP_CONTINUOUS_MEASUREMENT: u16 = 0x0104;
+const SCD30_COMMAND_SET_MEASUREMENT_INTERVAL: u16 = 0x4600;
+const SCD30_COMMAND_GET_DATA_READY: u16 = 0x0202;
+const SCD30_COMMAND_READ_MEASUREMENT: u16 = 0x0300;
+const SCD30_COMMAND_AUTOMATIC_SELF_CALIBRATION: u16 = 0x5306;
+const SCD30_COMMAND_FORCED_RECALIBRATION: u16 = 0x5204;
+const SCD30_COMMAND_ALTITUDE_COMPENSATION: u16 = 0x5102;
+const SCD30_COMMAND_READ_FIRMWARE_VERSION: u16 = 0xD100;
+
+/// The sensor needs some time between a command and the read of its response
+const SCD30_COMMAND_TIME: Duration = Duration::from_millis(3);
+
+/// Ambient pressure range, in hPa, the sensor can compensate for
+const SCD30_AMBIENT_PRESSURE_RANGE: (f32, f32) = (700.0, 1400.0);
+
+/// Reference concentration range, in ppm, accepted by the forced recalibration
+const SCD30_RECALIBRATION_RANGE: (u16, u16) = (400, 2000);
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scd30Settings {
+    /// Seconds between two measurements, from 2 to 1800
+    pub interval: u16,
+
+    /// `None` keeps the setting persisted by the sensor
+    pub automatic_self_calibration: Option<bool>,
+
+    /// Meters above sea level, superseded by the ambient pressure
+    pub altitude: Option<u16>,
+
+    /// Updated by the compensation with the pressure measured by another sensor
+    pub ambient_pressure: Option<Pressure>,
+
+    /// Concentration, in ppm, the sensor is exposed to when the agent starts: applied once as a
+    /// forced recalibration, the sensor having been measuring in that environment beforehand
+    pub forced_recalibration_ppm: Option<u16>,
+}
+
+impl Default for Scd30Settings {
+    fn default() -> Self {
+        Self {
+            interval: 2,
+            automatic_self_calibration: None,
+            altitude: None,
+            ambient_pressure: None,
+            forced_recalibration_ppm: None,
+        }
+    }
+}
+
+impl Scd30Settings {
+    fn validate(&self) -> Result<(), PiWeatherError> {
+        let (min, max) = SCD30_RECALIBRATION_RANGE;
+        match self.forced_recalibration_ppm {
+            Some(ppm) if !(min..=max).contains(&ppm) => {
+                Err(PiWeatherError::InvalidConfiguration(format!(
+                    "invalid {} settings: forced_recalibration_ppm must be within {} and {}",
+                    SCD30_DRIVER, min, max
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scd30Readout {
+    /// Expressed in ppm
+    CarbonDioxide(u16),
+    Temperature(f32),
+    Humidity(f32),
+}
+
+impl From<Scd30Readout> for Modality {
+    fn from(value: Scd30Readout) -> Self {
+        match value {
+            Scd30Readout::CarbonDioxide(co2) => Modality::CarbonDioxide(co2),
+            Scd30Readout::Temperature(t) => Modality::Temperature(Temperature::Celsius(t)),
+            Scd30Readout::Humidity(h) => Modality::Humidity(Humidity::Relative(h)),
+        }
+    }
+}
+
+pub struct Scd30<T: I2CDevice + Sized> {
+    settings: Scd30Settings,
+    started: bool,
+
+    /// The ambient pressure changed since the measurements were started
+    pressure_changed: bool,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Scd30<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Scd30Settings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Scd30Settings) -> Self {
+        Self {
+            settings,
+            started: false,
+            pressure_changed: false,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    async fn write(&self, command: u16, arguments: &[u16]) -> Result<(), PiWeatherError> {
+        write_command(
+            &self.device,
+            SCD30_NAME,
+            &encode_command(command, arguments),
+        )
+        .await
+    }
+
+    /// Values are transmitted as big-endian IEEE754 floats, split over two words
+    fn decode_measurement(words: [u16; 6]) -> [Scd30Readout; 3] {
+        let float = |high: u16, low: u16| f32::from_bits(((high as u32) << 16) | low as u32);
+
+        // NaN and negative concentrations, reported while warming up, saturate to 0
+        [
+            Scd30Readout::CarbonDioxide(float(words[0], words[1]).round() as u16),
+            Scd30Readout::Temperature(float(words[2], words[3])),
+            Scd30Readout::Humidity(float(words[4], words[5])),
+        ]
+    }
+
+    /// Firmware version, major and minor
+    pub async fn firmware_version(&self) -> Result<(u8, u8), PiWeatherError> {
+        let [version] = query(
+            &self.device,
+            SCD30_NAME,
+            SCD30_COMMAND_READ_FIRMWARE_VERSION,
+            &[],
+            SCD30_COMMAND_TIME,
+        )
+        .await?;
+        let [major, minor] = version.to_be_bytes();
+        Ok((major, minor))
+    }
+
+    pub async fn set_automatic_self_calibration(
+        &self,
+        enabled: bool,
+    ) -> Result<(), PiWeatherError> {
+        self.write(SCD30_COMMAND_AUTOMATIC_SELF_CALIBRATION, &[enabled as u16])
+            .await
+    }
+
+    /// Calibrate against a reference concentration, after at least 2 minutes of measurements
+    /// in a stable environment
+    pub async fn forced_recalibration(&self, reference_ppm: u16) -> Result<(), PiWeatherError> {
+        let (min, max) = SCD30_RECALIBRATION_RANGE;
+        if !(min..=max).contains(&reference_ppm) {
+            return Err(PiWeatherError::InvalidConfiguration(format!(
+                "{} recalibration reference must be within {} and {} ppm, got {}",
+                SCD30_NAME, min, max, reference_ppm
+            )));
+        }
+
+        self.write(SCD30_COMMAND_FORCED_RECALIBRATION, &[reference_ppm])
+            .await
+    }
+
+    /// Persisted by the sensor, ignored while compensating for the ambient pressure
+    pub async fn set_altitude(&self, meters: u16) -> Result<(), PiWeatherError> {
+        self.write(SCD30_COMMAND_ALTITUDE_COMPENSATION, &[meters])
+            .await
+    }
+
+    /// (Re)start continuous measurements, compensating for `pressure` if specified
+    pub async fn start_measurement(
+        &self,
+        pressure: Option<Pressure>,
+    ) -> Result<(), PiWeatherError> {
+        let (min, max) = SCD30_AMBIENT_PRESSURE_RANGE;
+        let hectopascal = match pressure.map(|p| p.to_hectopascal().value()) {
+            Some(p) if (min..=max).contains(&p) => p.round() as u16,
+            Some(p) => {
+                return Err(PiWeatherError::InvalidConfiguration(format!(
+                    "{} can't compensate an ambient pressure of {}hPa",
+                    SCD30_NAME, p
+                )))
+            }
+            None => 0,
+        };
+
+        self.write(SCD30_COMMAND_START_CONTINUOUS_MEASUREMENT, &[hectopascal])
+            .await
+    }
+
+    pub async fn stop_measurement(&self) -> Result<(), PiWeatherError> {
+        self.write(SCD30_COMMAND_STOP_CONTINUOUS_MEASUREMENT, &[])
+            .await
+    }
+
+    /// Apply the settings and start continuous measurements
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        let (major, minor) = self.firmware_version().await?;
+        info!("{} firmware version {}.{}", SCD30_NAME, major, minor);
+
+        self.write(
+            SCD30_COMMAND_SET_MEASUREMENT_INTERVAL,
+            &[self.settings.interval.clamp(2, 1800)],
+        )
+        .await?;
+
+        if let Some(enabled) = self.settings.automatic_self_calibration {
+            self.set_automatic_self_calibration(enabled).await?;
+        }
+
+        if let Some(altitude) = self.settings.altitude {
+            self.set_altitude(altitude).await?;
+        }
+
+        self.start_measurement(self.settings.ambient_pressure)
+            .await?;
+
+        // Once per run of the agent, the sensor persisting the calibration
+        if let Some(reference_ppm) = self.settings.forced_recalibration_ppm.take() {
+            self.forced_recalibration(reference_ppm).await?;
+            info!("{} recalibrated to {} ppm", SCD30_NAME, reference_ppm);
+        }
+
+        self.started = true;
+        self.pressure_changed = false;
+        Ok(())
+    }
+
+    pub async fn data_ready(&self) -> Result<bool, PiWeatherError> {
+        let [ready] = query(
+            &self.device,
+            SCD30_NAME,
+            SCD30_COMMAND_GET_DATA_READY,
+            &[],
+            SCD30_COMMAND_TIME,
+        )
+        .await?;
+        Ok(ready != 0)
+    }
+
+    pub async fn read_measurement(&self) -> Result<[Scd30Readout; 3], PiWeatherError> {
+        let words = query(
+            &self.device,
+            SCD30_NAME,
+            SCD30_COMMAND_READ_MEASUREMENT,
+            &[],
+            SCD30_COMMAND_TIME,
+        )
+        .await?;
+        Ok(Self::decode_measurement(words))
+    }
+
+    /// Latest measurement, `None` until the sensor completed a new one
+    pub async fn read(&mut self) -> Result<Option<[Scd30Readout; 3]>, PiWeatherError> {
+        if !self.started {
+            self.start().await?;
+        } else if self.pressure_changed {
+            // Restarting the measurements is the only way to update the compensation
+            self.start_measurement(self.settings.ambient_pressure)
+                .await?;
+            self.pressure_changed = false;
+        }
+
+        if !self.data_ready().await? {
+            debug!("{} has no new measurement", SCD30_NAME);
+            return Ok(None);
+        }
+
+        self.read_measurement().await.map(Some)
+    }
+}
+
+impl<D> I2CSensor<D> for Scd30<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = SCD30_DRIVER;
+    const DEFAULT_ADDRESS: u16 = SCD30_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] = &[Ambient::Temperature, Ambient::Humidity];
+    const COMPENSATED_FOR: &'static [Ambient] = &[Ambient::Pressure];
+    type Settings = Scd30Settings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        settings.validate()?;
+        let device = factory.open(address)?;
+        Ok(Scd30::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Scd30<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        SCD30_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        if let Some(readouts) = self.read().await? {
+            return Ok(Some(Payload::now(readouts.into_iter().map(Modality::from))));
+        }
+
+        Ok(None)
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        let pressure = ambient.readouts().iter().find_map(|readout| match readout {
+            Modality::Pressure(p) => Some(*p),
+            _ => None,
+        });
+        let Some(pressure) = pressure else {
+            debug!(
+                "{} ignoring ambient conditions without pressure",
+                SCD30_NAME
+            );
+            return;
+        };
+
+        let (min, max) = SCD30_AMBIENT_PRESSURE_RANGE;
+        let hectopascal = pressure.to_hectopascal().value().round();
+        if !(min..=max).contains(&hectopascal) {
+            warn!(
+                "{} can't compensate an ambient pressure of {}hPa",
+                SCD30_NAME, hectopascal
+            );
+            return;
+        }
+
+        // The sensor only takes whole hectopascals into account
+        let current = self
+            .settings
+            .ambient_pressure
+            .map(|p| p.to_hectopascal().value().round());
+        if current != Some(hectopascal) {
+            self.settings.ambient_pressure = Some(pressure);
+            self.pressure_changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::scd30::{Scd30, Scd30Readout, Scd30Settings};
+    use crate::sensors::Sensor;
+    use i2cdev::core::I2CDevice;
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Modality, Payload, Pressure};
+
+    /// Content of the register map from `offset`
+    async fn registers<const N: usize>(scd30: &Scd30<MockI2CDevice>, offset: u8) -> [u8; N] {
+        scd30
+            .device
+            .transaction(move |device| {
+                let mut data = [0u8; N];
+                device.write(&[offset]).unwrap();
+                device.read(&mut data).unwrap();
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn decode_floats() {
+        let readouts = Scd30::<MockI2CDevice>::decode_measurement([
+            0x43C8, 0x0000, 0x41AC, 0x0000, 0x4234, 0x0000,
+        ]);
+        assert_eq!(
+            readouts,
+            [
+                Scd30Readout::CarbonDioxide(400),
+                Scd30Readout::Temperature(21.5),
+                Scd30Readout::Humidity(45.0),
+            ]
+        );
+
+        let [co2, ..] = Scd30::<MockI2CDevice>::decode_measurement([0xFFC0, 0, 0, 0, 0, 0]);
+        assert_eq!(co2, Scd30Readout::CarbonDioxide(0));
+    }
+
+    #[tokio::test]
+    async fn scd30_read_measurement() {
+        // Responses are read right after the 2 bytes command
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(
+            0x04,
+            &[
+                0x43, 0xC8, 0xDB, 0x00, 0x00, 0x81, 0x41, 0xAC, 0x7D, 0x00, 0x00, 0x81, 0x42, 0x34,
+                0xD0, 0x00, 0x00, 0x81,
+            ],
+        );
+
+        let scd30 = Scd30::new(device);
+        let readouts = scd30.read_measurement().await.unwrap();
+        assert_eq!(readouts[0], Scd30Readout::CarbonDioxide(400));
+    }
+
+    #[tokio::test]
+    async fn scd30_data_ready() {
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0x03, &[0x00, 0x01, 0xB0]);
+        device.regmap.write_regs(0xD2, &[0x03, 0x42, 0xF3]);
+
+        let scd30 = Scd30::new(device);
+        assert!(scd30.data_ready().await.unwrap());
+        assert_eq!(scd30.firmware_version().await.unwrap(), (3, 0x42));
+    }
+
+    #[tokio::test]
+    async fn scd30_calibration() {
+        let scd30 = Scd30::new(MockI2CDevice::new());
+
+        // Arguments are followed by their CRC-8
+        scd30.forced_recalibration(1010).await.unwrap();
+        assert_eq!(registers::<3>(&scd30, 0x53).await, [0x03, 0xF2, 0x4C]);
+
+        scd30
+            .start_measurement(Some(Pressure::Hectopascal(800.0)))
+            .await
+            .unwrap();
+        assert_eq!(registers::<3>(&scd30, 0x01).await, [0x03, 0x20, 0x2A]);
+
+        assert!(matches!(
+            scd30.forced_recalibration(5000).await,
+            Err(PiWeatherError::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            scd30
+                .start_measurement(Some(Pressure::Hectopascal(500.0)))
+                .await,
+            Err(PiWeatherError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn scd30_start_recalibrates_once() {
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0xD2, &[0x03, 0x42, 0xF3]);
+
+        let settings = Scd30Settings {
+            forced_recalibration_ppm: Some(1010),
+            ..Default::default()
+        };
+        let mut scd30 = Scd30::with_settings(device, settings);
+        scd30.start().await.unwrap();
+        assert_eq!(registers::<3>(&scd30, 0x53).await, [0x03, 0xF2, 0x4C]);
+        assert_eq!(scd30.settings.forced_recalibration_ppm, None);
+    }
+
+    #[tokio::test]
+    async fn compensate_ambient_pressure() {
+        let mut scd30 = Scd30::new(MockI2CDevice::new());
+        scd30.started = true;
+
+        let ambient = |hpa| Payload::now([Modality::Pressure(Pressure::Hectopascal(hpa))]);
+        scd30.compensate(&ambient(800.2));
+        assert!(scd30.pressure_changed);
+
+        // Measurements are restarted with the new pressure on the next read
+        let _ = scd30.read().await;
+        assert!(!scd30.pressure_changed);
+        assert_eq!(registers::<2>(&scd30, 0x00).await, [0x10, 0x03]);
+
+        // Changes below the resolution of the sensor and unsupported pressures are ignored
+        scd30.compensate(&ambient(799.9));
+        scd30.compensate(&ambient(500.0));
+        assert!(!scd30.pressure_changed);
+        assert_eq!(
+            scd30.settings.ambient_pressure,
+            Some(Pressure::Hectopascal(800.2))
+        );
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Scd30Settings =
+            toml::from_str("interval = 5\nautomatic_self_calibration = false\naltitude = 300")
+                .unwrap();
+        assert_eq!(
+            settings,
+            Scd30Settings {
+                interval: 5,
+                automatic_self_calibration: Some(false),
+                altitude: Some(300),
+                ambient_pressure: None,
+                forced_recalibration_ppm: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,549 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::sensirion::{encode_command, query, serial_number, write_command};
+use crate::sensors::{Ambient, I2CSensor, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Humidity, Modality, Payload, Pressure, Quantity, Temperature};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+const SCD4X_DRIVER: &str = "scd4x";
+const SCD4X_NAME: &str = "SCD4x";
+const SCD4X_I2C_SLAVE_ADDRESS: u16 = 0x62;
+
+const SCD4X_COMMAND_START_PERIODIC_MEASUREMENT: u16 = 0x21B1;
+const SCD4X_COMMAND_START_LOW_POWER_PERIODIC_MEASUREMENT: u16 = 0x21AC;
+const SCD4X_COMMAND_STOP_PERIODIC_MEASUREMENT: u16 = 0x3F86;
+const SCD4X_COMMAND_READ_MEASUREMENT: u16 = 0xEC05;
+const SCD4X_COMMAND_GET_DATA_READY: u16 = 0xE4B8;
+const SCD4X_COMMAND_AUTOMATIC_SELF_CALIBRATION: u16 = 0x2416;
+const SCD4X_COMMAND_FORCED_RECALIBRATION: u16 = 0x362F;
+const SCD4X_COMMAND_SENSOR_ALTITUDE: u16 = 0x2427;
+const SCD4X_COMMAND_AMBIENT_PRESSURE: u16 = 0xE000;
+
+/// Data is ready when any of the 11 least significant bits of the status is set
+const SCD4X_DATA_READY_MASK: u16 = 0x07FF;
+
+/// Returned by the forced recalibration when it failed
+const SCD4X_RECALIBRATION_FAILED: u16 = 0xFFFF;
+
+const SCD4X_COMMAND_TIME: Duration = Duration::from_millis(1);
+const SCD4X_STOP_TIME: Duration = Duration::from_millis(500);
+const SCD4X_RECALIBRATION_TIME: Duration = Duration::from_millis(400);
+
+/// Ambient pressure range, in hPa, the sensor can compensate for
+const SCD4X_AMBIENT_PRESSURE_RANGE: (f32, f32) = (700.0, 1200.0);
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scd4xMode {
+    /// One measurement every 5 seconds
+    #[default]
+    Periodic,
+
+    /// One measurement every 30 seconds
+    LowPowerPeriodic,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scd4xSettings {
+    pub mode: Scd4xMode,
+
+    /// `None` keeps the setting persisted by the sensor
+    pub automatic_self_calibration: Option<bool>,
+
+    /// Meters above sea level, superseded by the ambient pressure
+    pub altitude: Option<u16>,
+
+    /// Updated by the compensation with the pressure measured by another sensor
+    pub ambient_pressure: Option<Pressure>,
+
+    /// Concentration, in ppm, the sensor is exposed to when the agent starts: applied once as a
+    /// forced recalibration, the sensor having been measuring in that environment beforehand
+    pub forced_recalibration_ppm: Option<u16>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Scd4xReadout {
+    /// Expressed in ppm
+    CarbonDioxide(u16),
+    Temperature(f32),
+    Humidity(f32),
+}
+
+impl From<Scd4xReadout> for Modality {
+    fn from(value: Scd4xReadout) -> Self {
+        match value {
+            Scd4xReadout::CarbonDioxide(co2) => Modality::CarbonDioxide(co2),
+            Scd4xReadout::Temperature(t) => Modality::Temperature(Temperature::Celsius(t)),
+            Scd4xReadout::Humidity(h) => Modality::Humidity(Humidity::Relative(h)),
+        }
+    }
+}
+
+pub struct Scd4x<T: I2CDevice + Sized> {
+    settings: Scd4xSettings,
+    measuring: bool,
+
+    /// Ambient pressure received since the last read, applied before the next one
+    pending_pressure: Option<Pressure>,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Scd4x<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Scd4xSettings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Scd4xSettings) -> Self {
+        Self {
+            settings,
+            measuring: false,
+            pending_pressure: None,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    async fn write(&self, command: u16, arguments: &[u16]) -> Result<(), PiWeatherError> {
+        write_command(
+            &self.device,
+            SCD4X_NAME,
+            &encode_command(command, arguments),
+        )
+        .await
+    }
+
+    fn decode_measurement(words: [u16; 3]) -> [Scd4xReadout; 3] {
+        [
+            Scd4xReadout::CarbonDioxide(words[0]),
+            Scd4xReadout::Temperature(-45.0 + 175.0 * words[1] as f32 / 65535.0),
+            Scd4xReadout::Humidity(100.0 * words[2] as f32 / 65535.0),
+        ]
+    }
+
+    /// Unique 48 bits serial number of the sensor
+    pub async fn serial_number(&self) -> Result<u64, PiWeatherError> {
+        serial_number(&self.device, SCD4X_NAME).await
+    }
+
+    pub async fn start_measurement(&mut self) -> Result<(), PiWeatherError> {
+        let command = match self.settings.mode {
+            Scd4xMode::Periodic => SCD4X_COMMAND_START_PERIODIC_MEASUREMENT,
+            Scd4xMode::LowPowerPeriodic => SCD4X_COMMAND_START_LOW_POWER_PERIODIC_MEASUREMENT,
+        };
+        self.write(command, &[]).await?;
+        self.measuring = true;
+
+        // The only compensation accepted while measuring
+        if let Some(pressure) = self.settings.ambient_pressure {
+            self.set_ambient_pressure(pressure).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop measuring, most commands being only accepted while the sensor is idle
+    pub async fn stop_measurement(&mut self) -> Result<(), PiWeatherError> {
+        self.write(SCD4X_COMMAND_STOP_PERIODIC_MEASUREMENT, &[])
+            .await?;
+        sleep(SCD4X_STOP_TIME).await;
+        self.measuring = false;
+        Ok(())
+    }
+
+    /// Run `command` while the sensor is idle, resuming the measurements afterward
+    async fn while_idle<const N: usize>(
+        &mut self,
+        command: u16,
+        arguments: &[u16],
+        delay: Duration,
+    ) -> Result<[u16; N], PiWeatherError> {
+        let measuring = self.measuring;
+        if measuring {
+            self.stop_measurement().await?;
+        }
+
+        let response = if N == 0 {
+            self.write(command, arguments).await?;
+            sleep(delay).await;
+            [0; N]
+        } else {
+            query(&self.device, SCD4X_NAME, command, arguments, delay).await?
+        };
+
+        if measuring {
+            self.start_measurement().await?;
+        }
+
+        Ok(response)
+    }
+
+    pub async fn set_automatic_self_calibration(
+        &mut self,
+        enabled: bool,
+    ) -> Result<(), PiWeatherError> {
+        self.settings.automatic_self_calibration = Some(enabled);
+        self.while_idle::<0>(
+            SCD4X_COMMAND_AUTOMATIC_SELF_CALIBRATION,
+            &[enabled as u16],
+            SCD4X_COMMAND_TIME,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Calibrate against a reference concentration, after at least 3 minutes of measurements
+    /// in a stable environment. Returns the correction applied, in ppm.
+    pub async fn forced_recalibration(
+        &mut self,
+        reference_ppm: u16,
+    ) -> Result<i32, PiWeatherError> {
+        let [correction] = self
+            .while_idle(
+                SCD4X_COMMAND_FORCED_RECALIBRATION,
+                &[reference_ppm],
+                SCD4X_RECALIBRATION_TIME,
+            )
+            .await?;
+
+        if correction == SCD4X_RECALIBRATION_FAILED {
+            return Err(PiWeatherError::SensorError(format!(
+                "{} forced recalibration failed, the sensor wasn't measuring long enough",
+                SCD4X_NAME
+            )));
+        }
+
+        Ok(correction as i32 - 0x8000)
+    }
+
+    /// Ignored while compensating for the ambient pressure
+    pub async fn set_altitude(&mut self, meters: u16) -> Result<(), PiWeatherError> {
+        self.settings.altitude = Some(meters);
+        self.while_idle::<0>(SCD4X_COMMAND_SENSOR_ALTITUDE, &[meters], SCD4X_COMMAND_TIME)
+            .await
+            .map(|_| ())
+    }
+
+    /// Compensate for the ambient pressure, which can be updated while measuring
+    pub async fn set_ambient_pressure(&mut self, pressure: Pressure) -> Result<(), PiWeatherError> {
+        let (min, max) = SCD4X_AMBIENT_PRESSURE_RANGE;
+        let hectopascal = pressure.to_hectopascal().value();
+        if !(min..=max).contains(&hectopascal) {
+            return Err(PiWeatherError::InvalidConfiguration(format!(
+                "{} can't compensate an ambient pressure of {}hPa",
+                SCD4X_NAME, hectopascal
+            )));
+        }
+
+        let hectopascal = hectopascal.round() as u16;
+        self.settings.ambient_pressure = Some(pressure);
+        self.write(SCD4X_COMMAND_AMBIENT_PRESSURE, &[hectopascal])
+            .await?;
+        sleep(SCD4X_COMMAND_TIME).await;
+        Ok(())
+    }
+
+    /// Apply the settings and start the periodic measurements
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        // The sensor keeps measuring across restarts of the agent
+        self.measuring = true;
+        self.stop_measurement().await?;
+
+        info!(
+            "{} serial number {:012X}",
+            SCD4X_NAME,
+            self.serial_number().await?
+        );
+
+        if let Some(enabled) = self.settings.automatic_self_calibration {
+            self.set_automatic_self_calibration(enabled).await?;
+        }
+
+        if let Some(altitude) = self.settings.altitude {
+            self.set_altitude(altitude).await?;
+        }
+
+        // Not repeated when the measurements are restarted after a failure
+        if let Some(reference_ppm) = self.settings.forced_recalibration_ppm.take() {
+            let correction = self.forced_recalibration(reference_ppm).await?;
+            info!(
+                "{} recalibrated to {} ppm, correction {} ppm",
+                SCD4X_NAME, reference_ppm, correction
+            );
+        }
+
+        self.start_measurement().await
+    }
+
+    pub async fn data_ready(&self) -> Result<bool, PiWeatherError> {
+        let [status] = query(
+            &self.device,
+            SCD4X_NAME,
+            SCD4X_COMMAND_GET_DATA_READY,
+            &[],
+            SCD4X_COMMAND_TIME,
+        )
+        .await?;
+        Ok(status & SCD4X_DATA_READY_MASK != 0)
+    }
+
+    pub async fn read_measurement(&self) -> Result<[Scd4xReadout; 3], PiWeatherError> {
+        let words = query(
+            &self.device,
+            SCD4X_NAME,
+            SCD4X_COMMAND_READ_MEASUREMENT,
+            &[],
+            SCD4X_COMMAND_TIME,
+        )
+        .await?;
+        Ok(Self::decode_measurement(words))
+    }
+
+    /// Latest measurement, `None` until the sensor completed a new one
+    pub async fn read(&mut self) -> Result<Option<[Scd4xReadout; 3]>, PiWeatherError> {
+        if let Some(pressure) = self.pending_pressure.take() {
+            if self.measuring {
+                self.set_ambient_pressure(pressure).await?;
+            } else {
+                self.settings.ambient_pressure = Some(pressure);
+            }
+        }
+
+        if !self.measuring {
+            self.start().await?;
+        }
+
+        if !self.data_ready().await? {
+            debug!("{} has no new measurement", SCD4X_NAME);
+            return Ok(None);
+        }
+
+        self.read_measurement().await.map(Some)
+    }
+}
+
+impl<D> I2CSensor<D> for Scd4x<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = SCD4X_DRIVER;
+    const DEFAULT_ADDRESS: u16 = SCD4X_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] = &[Ambient::Temperature, Ambient::Humidity];
+    const COMPENSATED_FOR: &'static [Ambient] = &[Ambient::Pressure];
+    type Settings = Scd4xSettings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(Scd4x::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Scd4x<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        SCD4X_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        if let Some(readouts) = self.read().await? {
+            return Ok(Some(Payload::now(readouts.into_iter().map(Modality::from))));
+        }
+
+        Ok(None)
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        let pressure = ambient.readouts().iter().find_map(|readout| match readout {
+            Modality::Pressure(p) => Some(*p),
+            _ => None,
+        });
+        let Some(pressure) = pressure else {
+            debug!(
+                "{} ignoring ambient conditions without pressure",
+                SCD4X_NAME
+            );
+            return;
+        };
+
+        let (min, max) = SCD4X_AMBIENT_PRESSURE_RANGE;
+        let hectopascal = pressure.to_hectopascal().value().round();
+        if !(min..=max).contains(&hectopascal) {
+            warn!(
+                "{} can't compensate an ambient pressure of {}hPa",
+                SCD4X_NAME, hectopascal
+            );
+            return;
+        }
+
+        // The sensor only takes whole hectopascals into account
+        let current = self
+            .settings
+            .ambient_pressure
+            .map(|p| p.to_hectopascal().value().round());
+        if current != Some(hectopascal) {
+            self.pending_pressure = Some(pressure);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::scd4x::{Scd4x, Scd4xMode, Scd4xReadout, Scd4xSettings};
+    use crate::sensors::Sensor;
+    use i2cdev::core::I2CDevice;
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Modality, Payload, Pressure};
+
+    fn device() -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+
+        // Responses are read right after the command and its arguments
+        device.regmap.write_regs(
+            0x37,
+            &[0x12, 0x34, 0x37, 0x56, 0x78, 0x7D, 0x03, 0x42, 0xF3],
+        );
+        device.regmap.write_regs(0xE5, &[0x80, 0x06, 0x04]);
+        device.regmap.write_regs(
+            0xED,
+            &[0x01, 0xF4, 0x33, 0x66, 0x66, 0x93, 0x80, 0x00, 0xA2],
+        );
+        device
+    }
+
+    #[tokio::test]
+    async fn scd4x_read() {
+        let mut scd4x = Scd4x::with_settings(
+            device(),
+            Scd4xSettings {
+                ambient_pressure: Some(Pressure::Hectopascal(1013.25)),
+                ..Scd4xSettings::default()
+            },
+        );
+
+        let readouts = scd4x.read().await.unwrap().unwrap();
+        assert_eq!(readouts[0], Scd4xReadout::CarbonDioxide(500));
+        assert_eq!(readouts[1], Scd4xReadout::Temperature(25.0));
+        assert!(matches!(readouts[2], Scd4xReadout::Humidity(h) if (h - 50.0).abs() < 0.01));
+        assert!(scd4x.measuring);
+
+        let scd4x = Scd4x::new(device());
+        assert_eq!(scd4x.serial_number().await.unwrap(), 0x123456780342);
+    }
+
+    #[tokio::test]
+    async fn scd4x_data_not_ready() {
+        let mut device = device();
+        device.regmap.write_regs(0xE5, &[0x80, 0x00, 0xA2]);
+
+        let mut scd4x = Scd4x::new(device);
+        assert_eq!(scd4x.read().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn scd4x_forced_recalibration() {
+        // Response follows the 2 bytes command and the 3 bytes argument
+        let mut device = device();
+        device.regmap.write_regs(0x3A, &[0x7F, 0xE2, 0x80]);
+
+        let mut scd4x = Scd4x::new(device);
+        assert_eq!(scd4x.forced_recalibration(420).await.unwrap(), -30);
+
+        let mut device = self::device();
+        device.regmap.write_regs(0x3A, &[0xFF, 0xFF, 0xAC]);
+
+        let mut scd4x = Scd4x::new(device);
+        assert!(matches!(
+            scd4x.forced_recalibration(420).await,
+            Err(PiWeatherError::SensorError(_))
+        ));
+        assert!(!scd4x.measuring);
+    }
+
+    #[tokio::test]
+    async fn scd4x_start_recalibrates_once() {
+        let mut device = device();
+        device.regmap.write_regs(0x3A, &[0x7F, 0xE2, 0x80]);
+
+        let settings = Scd4xSettings {
+            forced_recalibration_ppm: Some(420),
+            ..Default::default()
+        };
+        let mut scd4x = Scd4x::with_settings(device, settings);
+        scd4x.start().await.unwrap();
+        assert!(scd4x.measuring);
+        assert_eq!(scd4x.settings.forced_recalibration_ppm, None);
+    }
+
+    #[tokio::test]
+    async fn compensate_ambient_pressure() {
+        let mut scd4x = Scd4x::new(device());
+        scd4x.read().await.unwrap();
+
+        let ambient = |hpa| Payload::now([Modality::Pressure(Pressure::Hectopascal(hpa))]);
+        scd4x.compensate(&ambient(1013.25));
+        scd4x.compensate(&ambient(300.0));
+        assert_eq!(scd4x.pending_pressure, Some(Pressure::Hectopascal(1013.25)));
+
+        // Applied while measuring, before the next read
+        scd4x.read().await.unwrap();
+        assert_eq!(scd4x.pending_pressure, None);
+        assert_eq!(
+            scd4x.settings.ambient_pressure,
+            Some(Pressure::Hectopascal(1013.25))
+        );
+
+        let argument = scd4x
+            .device
+            .transaction(|device| {
+                let mut data = [0u8; 2];
+                device.write(&[0xE1]).unwrap();
+                device.read(&mut data).unwrap();
+                Ok(data)
+            })
+            .await
+            .unwrap();
+        assert_eq!(argument, 1013u16.to_be_bytes());
+
+        // Changes below the resolution of the sensor are ignored
+        scd4x.compensate(&ambient(1012.8));
+        assert_eq!(scd4x.pending_pressure, None);
+
+        assert!(matches!(
+            scd4x
+                .set_ambient_pressure(Pressure::Hectopascal(1500.0))
+                .await,
+            Err(PiWeatherError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Scd4xSettings =
+            toml::from_str("mode = \"low_power_periodic\"\nautomatic_self_calibration = true")
+                .unwrap();
+        assert_eq!(settings.mode, Scd4xMode::LowPowerPeriodic);
+        assert_eq!(settings.automatic_self_calibration, Some(true));
+        assert_eq!(settings.altitude, None);
+
+        let settings: Scd4xSettings = toml::from_str("forced_recalibration_ppm = 420").unwrap();
+        assert_eq!(settings.forced_recalibration_ppm, Some(420));
+    }
+}
@@ -0,0 +1,201 @@
+//! Framing shared by the Sensirion sensors: 16 bits big-endian words, each followed by its CRC-8.
+
+use crate::i2c::AsyncI2CDevice;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Serial number command common to the recent Sensirion sensors
+const SENSIRION_COMMAND_GET_SERIAL_NUMBER: u16 = 0x3682;
+const SENSIRION_SERIAL_NUMBER_TIME: Duration = Duration::from_millis(1);
+
+/// CRC-8 (polynomial 0x31, initial value 0xFF) protecting every word
+pub(crate) fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0xFFu8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Frame a 16 bits command followed by its arguments, each protected by its CRC-8
+pub(crate) fn encode_command(command: u16, arguments: &[u16]) -> Vec<u8> {
+    let mut frame = command.to_be_bytes().to_vec();
+    for argument in arguments {
+        let word = argument.to_be_bytes();
+        frame.extend_from_slice(&word);
+        frame.push(crc8(&word));
+    }
+
+    frame
+}
+
+/// Validate and decode the words of a response, 3 bytes each
+pub(crate) fn decode_words<const N: usize>(
+    sensor: &'static str,
+    data: &[u8],
+) -> Result<[u16; N], PiWeatherError> {
+    if data.len() != 3 * N {
+        return Err(PiWeatherError::Io(format!(
+            "Expected {} bytes from {}, got {}",
+            3 * N,
+            sensor,
+            data.len()
+        )));
+    }
+
+    let mut words = [0u16; N];
+    for (word, chunk) in words.iter_mut().zip(data.chunks_exact(3)) {
+        let crc = crc8(&chunk[..2]);
+        if crc != chunk[2] {
+            return Err(PiWeatherError::ChecksumMismatch {
+                sensor,
+                expected: chunk[2] as u16,
+                computed: crc as u16,
+            });
+        }
+
+        *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+
+    Ok(words)
+}
+
+/// Send `command` to the sensor
+pub(crate) async fn write_command<D>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+    command: &[u8],
+) -> Result<(), PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    let command = command.to_vec();
+    device
+        .transaction(move |device| {
+            device.write(&command).map_err(|e| {
+                PiWeatherError::I2CError(format!(
+                    "Failed to write command {:02X?} to {}: {}",
+                    command, sensor, e
+                ))
+            })
+        })
+        .await
+}
+
+/// Read the `N` words of the response to the last command
+pub(crate) async fn read_words<D, const N: usize>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+) -> Result<[u16; N], PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    let data = device
+        .transaction(move |device| {
+            let mut data = vec![0u8; 3 * N];
+            device.read(&mut data).map_err(|e| {
+                PiWeatherError::I2CError(format!("Failed to read data from {}: {}", sensor, e))
+            })?;
+            Ok(data)
+        })
+        .await?;
+
+    decode_words(sensor, &data)
+}
+
+/// Send `command` with its arguments, then read the `N` words of the response once the sensor
+/// had `delay` to process it
+pub(crate) async fn query<D, const N: usize>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+    command: u16,
+    arguments: &[u16],
+    delay: Duration,
+) -> Result<[u16; N], PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    exchange(device, sensor, &encode_command(command, arguments), delay).await
+}
+
+/// Send the already framed `command`, then read the `N` words of the response once the sensor
+/// had `delay` to process it
+pub(crate) async fn exchange<D, const N: usize>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+    command: &[u8],
+    delay: Duration,
+) -> Result<[u16; N], PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    write_command(device, sensor, command).await?;
+    sleep(delay).await;
+    read_words(device, sensor).await
+}
+
+/// Unique 48 bits serial number of the sensor, transmitted as 3 words
+pub(crate) async fn serial_number<D>(
+    device: &AsyncI2CDevice<D>,
+    sensor: &'static str,
+) -> Result<u64, PiWeatherError>
+where
+    D: I2CDevice + Send + 'static,
+{
+    let words: [u16; 3] = query(
+        device,
+        sensor,
+        SENSIRION_COMMAND_GET_SERIAL_NUMBER,
+        &[],
+        SENSIRION_SERIAL_NUMBER_TIME,
+    )
+    .await?;
+    Ok(words
+        .iter()
+        .fold(0u64, |serial, word| (serial << 16) | *word as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::sensirion::{crc8, decode_words, encode_command};
+    use piweather_common::errors::PiWeatherError;
+
+    #[test]
+    fn crc8_datasheet_example() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+        assert_eq!(crc8(&[0x00, 0x00]), 0x81);
+    }
+
+    #[test]
+    fn encode_arguments() {
+        assert_eq!(encode_command(0x21B1, &[]), [0x21, 0xB1]);
+        assert_eq!(
+            encode_command(0x362F, &[0xBEEF]),
+            [0x36, 0x2F, 0xBE, 0xEF, 0x92]
+        );
+    }
+
+    #[test]
+    fn decode_checked_words() {
+        let words = decode_words::<2>("SHT4x", &[0xBE, 0xEF, 0x92, 0x00, 0x00, 0x81]).unwrap();
+        assert_eq!(words, [0xBEEF, 0x0000]);
+
+        match decode_words::<2>("SHT4x", &[0xBE, 0xEF, 0x92, 0x00, 0x01, 0x81]) {
+            Err(PiWeatherError::ChecksumMismatch {
+                sensor, expected, ..
+            }) => {
+                assert_eq!(sensor, "SHT4x");
+                assert_eq!(expected, 0x81);
+            }
+            other => panic!("Corrupted word should be rejected, got {:?}", other),
+        }
+
+        assert!(decode_words::<1>("SHT4x", &[0xBE, 0xEF]).is_err());
+    }
+}
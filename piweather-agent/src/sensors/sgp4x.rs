@@ -0,0 +1,465 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::sensirion::{query, serial_number};
+use crate::sensors::{Ambient, GasIndexAlgorithm, GasIndexKind, I2CSensor, NoSettings, Sensor};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Gas, Humidity, Modality, Payload, Quantity};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const SGP4X_I2C_SLAVE_ADDRESS: u16 = 0x59;
+
+const SGP40_COMMAND_MEASURE_RAW_SIGNAL: u16 = 0x260F;
+const SGP41_COMMAND_EXECUTE_CONDITIONING: u16 = 0x2612;
+const SGP41_COMMAND_MEASURE_RAW_SIGNALS: u16 = 0x2619;
+
+const SGP40_MEASUREMENT_TIME: Duration = Duration::from_millis(30);
+const SGP41_MEASUREMENT_TIME: Duration = Duration::from_millis(50);
+
+/// The NOx pixel must be conditioned after power-up, without exceeding 10 seconds
+const SGP41_CONDITIONING_DURATION: Duration = Duration::from_secs(10);
+
+/// Sampling intervals the gas index algorithm is validated for
+const SGP4X_MIN_SAMPLING_INTERVAL: Duration = Duration::from_secs(1);
+const SGP4X_MAX_SAMPLING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Compensation applied until the ambient conditions are known: 50%RH and 25°C
+const SGP4X_DEFAULT_HUMIDITY_TICKS: u16 = 0x8000;
+const SGP4X_DEFAULT_TEMPERATURE_TICKS: u16 = 0x6666;
+
+/// The learned states are only valid if the sensor was off for a short time
+const SGP4X_STATE_MAX_AGE: TimeDelta = TimeDelta::minutes(10);
+
+/// Sensor of the SGP4x family, measuring the VOC and optionally the NOx raw signals
+pub trait Sgp4xModel: Send + 'static {
+    const DRIVER: &'static str;
+    const NAME: &'static str;
+    const MEASURES_NOX: bool;
+}
+
+pub struct Sgp40Model;
+
+impl Sgp4xModel for Sgp40Model {
+    const DRIVER: &'static str = "sgp40";
+    const NAME: &'static str = "SGP40";
+    const MEASURES_NOX: bool = false;
+}
+
+pub struct Sgp41Model;
+
+impl Sgp4xModel for Sgp41Model {
+    const DRIVER: &'static str = "sgp41";
+    const NAME: &'static str = "SGP41";
+    const MEASURES_NOX: bool = true;
+}
+
+pub type Sgp40<T> = Sgp4x<T, Sgp40Model>;
+pub type Sgp41<T> = Sgp4x<T, Sgp41Model>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Sgp4xReadout {
+    VocIndex(u16),
+    NoxIndex(u16),
+}
+
+impl From<Sgp4xReadout> for Modality {
+    fn from(value: Sgp4xReadout) -> Self {
+        match value {
+            Sgp4xReadout::VocIndex(index) => Modality::GasIndex(Gas::Voc, index),
+            Sgp4xReadout::NoxIndex(index) => Modality::GasIndex(Gas::Nox, index),
+        }
+    }
+}
+
+/// States of the VOC algorithm, persisted across restarts
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+struct Sgp4xState {
+    saved_at: DateTime<Utc>,
+    voc_mean: f32,
+    voc_std: f32,
+}
+
+pub struct Sgp4x<T: I2CDevice + Sized, M: Sgp4xModel> {
+    humidity_ticks: u16,
+    temperature_ticks: u16,
+    sampling_interval: Duration,
+    started_at: Option<Instant>,
+    restored: Option<Sgp4xState>,
+    voc: Option<GasIndexAlgorithm>,
+    nox: Option<GasIndexAlgorithm>,
+    device: AsyncI2CDevice<T>,
+    model: PhantomData<fn() -> M>,
+}
+
+impl<T, M> Sgp4x<T, M>
+where
+    T: I2CDevice + Send + 'static,
+    M: Sgp4xModel,
+{
+    pub fn new(device: T) -> Self {
+        Self {
+            humidity_ticks: SGP4X_DEFAULT_HUMIDITY_TICKS,
+            temperature_ticks: SGP4X_DEFAULT_TEMPERATURE_TICKS,
+            sampling_interval: SGP4X_MIN_SAMPLING_INTERVAL,
+            started_at: None,
+            restored: None,
+            voc: None,
+            nox: None,
+            device: AsyncI2CDevice::new(device),
+            model: PhantomData,
+        }
+    }
+
+    /// Unique 48 bits serial number of the sensor
+    pub async fn serial_number(&self) -> Result<u64, PiWeatherError> {
+        serial_number(&self.device, M::NAME).await
+    }
+
+    /// Ambient conditions the raw signals are compensated for
+    pub fn set_compensation(&mut self, humidity: f32, temperature: f32) {
+        self.humidity_ticks = (humidity.clamp(0.0, 100.0) * 65535.0 / 100.0).round() as u16;
+        self.temperature_ticks =
+            ((temperature.clamp(-45.0, 130.0) + 45.0) * 65535.0 / 175.0).round() as u16;
+    }
+
+    /// Resume the learning of the VOC algorithm from the restored state, if any
+    fn apply_restored(&mut self) {
+        if let (Some(voc), Some(state)) = (self.voc.as_mut(), self.restored) {
+            voc.set_states(state.voc_mean, state.voc_std);
+        }
+    }
+
+    /// Report the serial number and start the conditioning of the sensor
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        info!(
+            "{} serial number {:012X}",
+            M::NAME,
+            self.serial_number().await?
+        );
+
+        let interval = self.sampling_interval.as_secs_f32();
+        self.voc = Some(GasIndexAlgorithm::with_sampling_interval(
+            GasIndexKind::Voc,
+            interval,
+        ));
+        if M::MEASURES_NOX {
+            self.nox = Some(GasIndexAlgorithm::with_sampling_interval(
+                GasIndexKind::Nox,
+                interval,
+            ));
+        }
+        self.apply_restored();
+
+        self.started_at = Some(Instant::now());
+        Ok(())
+    }
+
+    fn is_conditioning(&self) -> bool {
+        M::MEASURES_NOX
+            && self
+                .started_at
+                .is_none_or(|started_at| started_at.elapsed() < SGP41_CONDITIONING_DURATION)
+    }
+
+    /// Raw VOC signal, along with the NOx one once the sensor is conditioned
+    pub async fn measure_raw_signals(&self) -> Result<(u16, Option<u16>), PiWeatherError> {
+        let compensation = [self.humidity_ticks, self.temperature_ticks];
+        if !M::MEASURES_NOX {
+            let [voc] = query(
+                &self.device,
+                M::NAME,
+                SGP40_COMMAND_MEASURE_RAW_SIGNAL,
+                &compensation,
+                SGP40_MEASUREMENT_TIME,
+            )
+            .await?;
+            return Ok((voc, None));
+        }
+
+        if self.is_conditioning() {
+            let [voc] = query(
+                &self.device,
+                M::NAME,
+                SGP41_COMMAND_EXECUTE_CONDITIONING,
+                &compensation,
+                SGP41_MEASUREMENT_TIME,
+            )
+            .await?;
+            return Ok((voc, None));
+        }
+
+        let [voc, nox] = query(
+            &self.device,
+            M::NAME,
+            SGP41_COMMAND_MEASURE_RAW_SIGNALS,
+            &compensation,
+            SGP41_MEASUREMENT_TIME,
+        )
+        .await?;
+        Ok((voc, Some(nox)))
+    }
+
+    /// Gas indices, omitted until their algorithm completed its initial blackout
+    pub async fn read(&mut self) -> Result<Vec<Sgp4xReadout>, PiWeatherError> {
+        if self.started_at.is_none() {
+            self.start().await?;
+        }
+
+        let (voc, nox) = self.measure_raw_signals().await?;
+        let mut readouts = Vec::with_capacity(2);
+
+        if let Some(algorithm) = self.voc.as_mut() {
+            match algorithm.process(voc) {
+                0 => debug!("{} VOC index isn't available yet", M::NAME),
+                index => readouts.push(Sgp4xReadout::VocIndex(index)),
+            }
+        }
+
+        if let (Some(algorithm), Some(nox)) = (self.nox.as_mut(), nox) {
+            match algorithm.process(nox) {
+                0 => debug!("{} NOx index isn't available yet", M::NAME),
+                index => readouts.push(Sgp4xReadout::NoxIndex(index)),
+            }
+        }
+
+        Ok(readouts)
+    }
+}
+
+impl<D, M> I2CSensor<D> for Sgp4x<D, M>
+where
+    D: I2CDevice + Send + 'static,
+    M: Sgp4xModel,
+{
+    const DRIVER: &'static str = M::DRIVER;
+    const DEFAULT_ADDRESS: u16 = SGP4X_I2C_SLAVE_ADDRESS;
+    const COMPENSATED_FOR: &'static [Ambient] = &[Ambient::Humidity, Ambient::Temperature];
+    type Settings = NoSettings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        _settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(Sgp4x::new(device))
+    }
+}
+
+#[async_trait]
+impl<D, M> Sensor for Sgp4x<D, M>
+where
+    D: I2CDevice + Send + 'static,
+    M: Sgp4xModel,
+{
+    fn driver(&self) -> &'static str {
+        M::DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let readouts = self.read().await?;
+        if readouts.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Payload::now(readouts.into_iter().map(Modality::from))))
+    }
+
+    /// The gas index algorithm expects to be fed at the sampling interval it was created for
+    fn set_polling_period(&mut self, period: Duration) {
+        if !(SGP4X_MIN_SAMPLING_INTERVAL..=SGP4X_MAX_SAMPLING_INTERVAL).contains(&period) {
+            warn!(
+                "{} gas index algorithm isn't validated for a {}s interval, use 1 to 10s",
+                M::NAME,
+                period.as_secs_f32()
+            );
+        }
+
+        self.sampling_interval = period;
+        self.started_at = None;
+    }
+
+    fn compensate(&mut self, ambient: &Payload) {
+        let mut humidity = None;
+        let mut temperature = None;
+        for readout in ambient.readouts() {
+            match readout {
+                Modality::Humidity(Humidity::Relative(h)) => humidity = Some(*h),
+                Modality::Temperature(t) => temperature = Some(t.to_celsius().value()),
+                _ => {}
+            }
+        }
+
+        match (humidity, temperature) {
+            (Some(humidity), Some(temperature)) => self.set_compensation(humidity, temperature),
+            _ => debug!(
+                "{} ignoring ambient conditions without humidity and temperature",
+                M::NAME
+            ),
+        }
+    }
+
+    fn state(&self) -> Option<serde_json::Value> {
+        let voc = self.voc.as_ref().filter(|voc| voc.is_settled())?;
+        let (voc_mean, voc_std) = voc.states();
+        let state = Sgp4xState {
+            saved_at: Utc::now(),
+            voc_mean,
+            voc_std,
+        };
+
+        serde_json::to_value(state).ok()
+    }
+
+    fn restore(&mut self, state: serde_json::Value) -> Result<(), PiWeatherError> {
+        let state: Sgp4xState = serde_json::from_value(state)
+            .map_err(|err| PiWeatherError::Io(format!("Invalid {} state: {}", M::NAME, err)))?;
+
+        let age = Utc::now() - state.saved_at;
+        if age > SGP4X_STATE_MAX_AGE {
+            return Err(PiWeatherError::Io(format!(
+                "{} state saved {} minutes ago is outdated",
+                M::NAME,
+                age.num_minutes()
+            )));
+        }
+
+        self.restored = Some(state);
+        self.apply_restored();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::sgp4x::{Sgp40, Sgp41, Sgp4xReadout, Sgp4xState};
+    use crate::sensors::Sensor;
+    use chrono::{TimeDelta, Utc};
+    use i2cdev::core::I2CDevice;
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::{Humidity, Modality, Payload, Temperature};
+    use std::time::{Duration, Instant};
+
+    fn device() -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+
+        // Responses are read after the 2 bytes command and its 2 arguments
+        device.regmap.write_regs(
+            0x37,
+            &[0xCA, 0xFE, 0x58, 0xBE, 0xEF, 0x92, 0x00, 0x00, 0x81],
+        );
+        device
+            .regmap
+            .write_regs(0x2D, &[0x75, 0x30, 0x08, 0x3E, 0x80, 0x24]);
+        device
+    }
+
+    /// Arguments of the last measurement
+    async fn compensation(sensor: &Sgp41<MockI2CDevice>) -> Vec<u8> {
+        sensor
+            .device
+            .transaction(|device| {
+                let mut data = vec![0u8; 6];
+                device.write(&[0x27]).unwrap();
+                device.read(&mut data).unwrap();
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sgp40_read() {
+        let mut sgp40 = Sgp40::new(device());
+        sgp40.set_polling_period(Duration::from_secs(10));
+        assert_eq!(sgp40.serial_number().await.unwrap(), 0xCAFEBEEF0000);
+
+        // Nothing is reported during the initial blackout
+        for _ in 0..5 {
+            assert_eq!(sgp40.payload().await.unwrap(), None);
+        }
+
+        // The index then ramps up from the first readout until learning the baseline
+        match sgp40.read().await.unwrap().as_slice() {
+            [Sgp4xReadout::VocIndex(index)] => assert!(*index < 100),
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert_eq!(sgp40.measure_raw_signals().await.unwrap(), (30000, None));
+    }
+
+    #[tokio::test]
+    async fn sgp41_conditioning() {
+        let mut sgp41 = Sgp41::new(device());
+        sgp41.start().await.unwrap();
+        assert!(sgp41.is_conditioning());
+        assert_eq!(sgp41.measure_raw_signals().await.unwrap(), (30000, None));
+
+        sgp41.started_at = Instant::now().checked_sub(Duration::from_secs(10));
+        assert!(!sgp41.is_conditioning());
+        assert_eq!(
+            sgp41.measure_raw_signals().await.unwrap(),
+            (30000, Some(16000))
+        );
+    }
+
+    #[tokio::test]
+    async fn sgp41_compensation() {
+        let mut sgp41 = Sgp41::new(device());
+        sgp41.measure_raw_signals().await.unwrap();
+        assert_eq!(
+            compensation(&sgp41).await,
+            [0x80, 0x00, 0xA2, 0x66, 0x66, 0x93]
+        );
+
+        // Ambient conditions published by another sensor, in its own units
+        sgp41.compensate(&Payload::now([
+            Modality::Temperature(Temperature::Fahrenheit(77.0)),
+            Modality::Humidity(Humidity::Relative(25.0)),
+        ]));
+        sgp41.measure_raw_signals().await.unwrap();
+        assert_eq!(&compensation(&sgp41).await[..3], [0x40, 0x00, 0x08]);
+
+        // Incomplete conditions are ignored
+        sgp41.compensate(&Payload::now([Modality::Humidity(Humidity::Relative(
+            80.0,
+        ))]));
+        assert_eq!(sgp41.humidity_ticks, 0x4000);
+    }
+
+    #[tokio::test]
+    async fn sgp41_restore_state() {
+        let mut sgp41 = Sgp41::new(device());
+        sgp41.set_polling_period(Duration::from_secs(10));
+        assert_eq!(sgp41.state(), None);
+
+        let state = Sgp4xState {
+            saved_at: Utc::now() - TimeDelta::minutes(2),
+            voc_mean: 9800.0,
+            voc_std: 60.0,
+        };
+        sgp41.restore(serde_json::to_value(state).unwrap()).unwrap();
+
+        // Applied once the algorithms are created
+        sgp41.start().await.unwrap();
+        assert_eq!(sgp41.voc.as_ref().unwrap().states(), (9800.0, 60.0));
+        let saved: Sgp4xState = serde_json::from_value(sgp41.state().unwrap()).unwrap();
+        assert_eq!((saved.voc_mean, saved.voc_std), (9800.0, 60.0));
+
+        let outdated = Sgp4xState {
+            saved_at: Utc::now() - TimeDelta::minutes(11),
+            ..state
+        };
+        let mut sgp41 = Sgp41::new(device());
+        assert!(sgp41
+            .restore(serde_json::to_value(outdated).unwrap())
+            .is_err());
+        assert!(sgp41.restore(serde_json::json!({})).is_err());
+    }
+}
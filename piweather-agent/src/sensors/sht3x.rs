@@ -0,0 +1,558 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::sensirion::{exchange, write_command};
+use crate::sensors::{Ambient, HeaterDuration, I2CSensor, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Humidity, Modality, Payload, Temperature};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+const SHT3X_DRIVER: &str = "sht3x";
+const SHT3X_NAME: &str = "SHT3x";
+
+/// Address with ADDR pulled to ground, 0x45 when pulled to VDD
+const SHT3X_I2C_SLAVE_ADDRESS: u16 = 0x44;
+
+const SHT3X_COMMAND_SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+const SHT3X_COMMAND_BREAK: [u8; 2] = [0x30, 0x93];
+const SHT3X_COMMAND_FETCH_DATA: [u8; 2] = [0xE0, 0x00];
+const SHT3X_COMMAND_HEATER_ENABLE: [u8; 2] = [0x30, 0x6D];
+const SHT3X_COMMAND_HEATER_DISABLE: [u8; 2] = [0x30, 0x66];
+const SHT3X_COMMAND_READ_STATUS: [u8; 2] = [0xF3, 0x2D];
+const SHT3X_COMMAND_READ_SERIAL_NUMBER: [u8; 2] = [0x36, 0x82];
+
+const SHT3X_STATUS_HEATER: u16 = 1 << 13;
+
+const SHT3X_RESET_TIME: Duration = Duration::from_millis(2);
+const SHT3X_COMMAND_TIME: Duration = Duration::from_millis(1);
+
+/// Relative humidity above which condensation is likely on the sensor
+const SHT3X_CONDENSATION_HUMIDITY: f32 = 95.0;
+
+/// The heater must not be on for more than a tenth of the time
+const SHT3X_HEATER_MAX_DUTY_CYCLE: u32 = 10;
+
+/// Trade-off between the noise of the measurement and its duration
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Repeatability {
+    #[default]
+    High,
+    Medium,
+    Low,
+}
+
+impl Repeatability {
+    fn single_shot_command(&self) -> [u8; 2] {
+        // Clock stretching disabled, the sensor doesn't acknowledge reads until done
+        match self {
+            Repeatability::High => [0x24, 0x00],
+            Repeatability::Medium => [0x24, 0x0B],
+            Repeatability::Low => [0x24, 0x16],
+        }
+    }
+
+    /// Longest time a single-shot measurement can take
+    fn measurement_time(&self) -> Duration {
+        match self {
+            Repeatability::High => Duration::from_micros(15_500),
+            Repeatability::Medium => Duration::from_micros(6_500),
+            Repeatability::Low => Duration::from_micros(4_500),
+        }
+    }
+}
+
+/// Number of measurements per second in periodic mode
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rate {
+    Half,
+    #[default]
+    One,
+    Two,
+    Four,
+    Ten,
+}
+
+impl Rate {
+    fn period(&self) -> Duration {
+        match self {
+            Rate::Half => Duration::from_secs(2),
+            Rate::One => Duration::from_secs(1),
+            Rate::Two => Duration::from_millis(500),
+            Rate::Four => Duration::from_millis(250),
+            Rate::Ten => Duration::from_millis(100),
+        }
+    }
+
+    fn periodic_command(&self, repeatability: Repeatability) -> [u8; 2] {
+        let lsb = |high: u8, medium: u8, low: u8| match repeatability {
+            Repeatability::High => high,
+            Repeatability::Medium => medium,
+            Repeatability::Low => low,
+        };
+
+        match self {
+            Rate::Half => [0x20, lsb(0x32, 0x24, 0x2F)],
+            Rate::One => [0x21, lsb(0x30, 0x26, 0x2D)],
+            Rate::Two => [0x22, lsb(0x36, 0x20, 0x2B)],
+            Rate::Four => [0x23, lsb(0x34, 0x22, 0x29)],
+            Rate::Ten => [0x27, lsb(0x37, 0x21, 0x2A)],
+        }
+    }
+}
+
+/// Configured as `"single_shot"` or `{ periodic = "one" }`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sht3xMode {
+    /// Perform a single measurement when polled, the sensor idling in between
+    #[default]
+    SingleShot,
+
+    /// Measure continuously, polling fetches the latest measurement
+    Periodic(Rate),
+}
+
+/// Heating of the sensor, its power being fixed
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sht3xHeaterPulse {
+    pub duration: HeaterDuration,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sht3xSettings {
+    pub repeatability: Repeatability,
+    pub mode: Sht3xMode,
+
+    /// Pulse the heater when the air is close to saturation, evaporating the condensation
+    /// that would otherwise keep the humidity readouts stuck high
+    pub condensation_recovery: Option<Sht3xHeaterPulse>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sht3xReadout {
+    Temperature(f32),
+    Humidity(f32),
+}
+
+impl From<Sht3xReadout> for Modality {
+    fn from(value: Sht3xReadout) -> Self {
+        match value {
+            Sht3xReadout::Temperature(t) => Modality::Temperature(Temperature::Celsius(t)),
+            Sht3xReadout::Humidity(h) => Modality::Humidity(Humidity::Relative(h)),
+        }
+    }
+}
+
+pub struct Sht3x<T: I2CDevice + Sized> {
+    settings: Sht3xSettings,
+    started: bool,
+    last_read: Option<Instant>,
+    last_readouts: Option<[Sht3xReadout; 2]>,
+    last_heating: Option<Instant>,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Sht3x<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Sht3xSettings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Sht3xSettings) -> Self {
+        Self {
+            settings,
+            started: false,
+            last_read: None,
+            last_readouts: None,
+            last_heating: None,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    fn temperature_from_raw(raw: u16) -> f32 {
+        -45.0 + 175.0 * raw as f32 / 65535.0
+    }
+
+    fn humidity_from_raw(raw: u16) -> f32 {
+        100.0 * raw as f32 / 65535.0
+    }
+
+    /// Unique serial number of the sensor
+    pub async fn serial_number(&self) -> Result<u32, PiWeatherError> {
+        let [high, low] = exchange(
+            &self.device,
+            SHT3X_NAME,
+            &SHT3X_COMMAND_READ_SERIAL_NUMBER,
+            SHT3X_COMMAND_TIME,
+        )
+        .await?;
+        Ok(((high as u32) << 16) | low as u32)
+    }
+
+    /// Switch the internal heater, evaporating condensation at the cost of biased readouts.
+    /// Periodic measurements have to be stopped for the sensor to accept the command.
+    pub async fn set_heater(&self, enabled: bool) -> Result<(), PiWeatherError> {
+        let command = if enabled {
+            SHT3X_COMMAND_HEATER_ENABLE
+        } else {
+            SHT3X_COMMAND_HEATER_DISABLE
+        };
+        write_command(&self.device, SHT3X_NAME, &command).await
+    }
+
+    /// Pulse the heater, pausing the periodic measurements meanwhile. The readouts following
+    /// the pulse are biased by the heat.
+    pub async fn heat(&mut self, pulse: Sht3xHeaterPulse) -> Result<(), PiWeatherError> {
+        let periodic = match self.settings.mode {
+            Sht3xMode::SingleShot => None,
+            Sht3xMode::Periodic(rate) => Some(rate.periodic_command(self.settings.repeatability)),
+        };
+
+        if periodic.is_some() {
+            write_command(&self.device, SHT3X_NAME, &SHT3X_COMMAND_BREAK).await?;
+            sleep(SHT3X_COMMAND_TIME).await;
+        }
+
+        self.set_heater(true).await?;
+        sleep(pulse.duration.duration()).await;
+        self.set_heater(false).await?;
+        self.last_heating = Some(Instant::now());
+
+        if let Some(command) = periodic {
+            write_command(&self.device, SHT3X_NAME, &command).await?;
+            self.last_read = Some(Instant::now());
+        }
+
+        Ok(())
+    }
+
+    pub async fn heater_enabled(&self) -> Result<bool, PiWeatherError> {
+        let [status] = exchange(
+            &self.device,
+            SHT3X_NAME,
+            &SHT3X_COMMAND_READ_STATUS,
+            SHT3X_COMMAND_TIME,
+        )
+        .await?;
+        Ok(status & SHT3X_STATUS_HEATER != 0)
+    }
+
+    /// Reset the sensor, report its serial number and start periodic measurements if requested
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        // A running periodic acquisition ignores the reset
+        let _ = write_command(&self.device, SHT3X_NAME, &SHT3X_COMMAND_BREAK).await;
+        sleep(SHT3X_COMMAND_TIME).await;
+        write_command(&self.device, SHT3X_NAME, &SHT3X_COMMAND_SOFT_RESET).await?;
+        sleep(SHT3X_RESET_TIME).await;
+
+        info!(
+            "{} serial number {:08X}",
+            SHT3X_NAME,
+            self.serial_number().await?
+        );
+
+        if let Sht3xMode::Periodic(rate) = self.settings.mode {
+            let command = rate.periodic_command(self.settings.repeatability);
+            write_command(&self.device, SHT3X_NAME, &command).await?;
+            self.last_read = Some(Instant::now());
+        }
+
+        self.started = true;
+        Ok(())
+    }
+
+    pub async fn read_temperature_and_humidity(
+        &mut self,
+    ) -> Result<[Sht3xReadout; 2], PiWeatherError> {
+        let [temperature, humidity] = match self.settings.mode {
+            Sht3xMode::SingleShot => {
+                let repeatability = self.settings.repeatability;
+                exchange(
+                    &self.device,
+                    SHT3X_NAME,
+                    &repeatability.single_shot_command(),
+                    repeatability.measurement_time(),
+                )
+                .await?
+            }
+            Sht3xMode::Periodic(_) => {
+                exchange(
+                    &self.device,
+                    SHT3X_NAME,
+                    &SHT3X_COMMAND_FETCH_DATA,
+                    Duration::ZERO,
+                )
+                .await?
+            }
+        };
+
+        self.last_read = Some(Instant::now());
+
+        Ok([
+            Sht3xReadout::Temperature(Self::temperature_from_raw(temperature)),
+            Sht3xReadout::Humidity(Self::humidity_from_raw(humidity)),
+        ])
+    }
+
+    pub async fn read(&mut self) -> Result<Option<[Sht3xReadout; 2]>, PiWeatherError> {
+        if !self.started {
+            self.start().await?;
+        }
+
+        // The sensor doesn't acknowledge fetches until a new measurement is available
+        if let (Sht3xMode::Periodic(rate), Some(last_read)) = (self.settings.mode, self.last_read) {
+            let since_last_read = Instant::now() - last_read;
+            if since_last_read < rate.period() {
+                debug!(
+                    "{} read {}s ago, using cached value",
+                    SHT3X_NAME,
+                    &since_last_read.as_secs_f32()
+                );
+                return Ok(self.last_readouts);
+            }
+        }
+
+        let readouts = self.read_temperature_and_humidity().await?;
+        self.last_readouts = Some(readouts);
+
+        if let (Some(pulse), [_, Sht3xReadout::Humidity(humidity)]) =
+            (self.settings.condensation_recovery, readouts)
+        {
+            let rested = self.last_heating.is_none_or(|last_heating| {
+                last_heating.elapsed() >= pulse.duration.duration() * SHT3X_HEATER_MAX_DUTY_CYCLE
+            });
+
+            if humidity >= SHT3X_CONDENSATION_HUMIDITY && rested {
+                debug!("{} saturated at {}%, heating", SHT3X_NAME, humidity);
+                self.heat(pulse).await?;
+            }
+        }
+
+        Ok(self.last_readouts)
+    }
+}
+
+impl<D> I2CSensor<D> for Sht3x<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = SHT3X_DRIVER;
+    const DEFAULT_ADDRESS: u16 = SHT3X_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] = &[Ambient::Temperature, Ambient::Humidity];
+    type Settings = Sht3xSettings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(Sht3x::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Sht3x<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        SHT3X_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        if let Some(readouts) = self.read().await? {
+            let modalities = [readouts[0].into(), readouts[1].into()];
+            return Ok(Some(Payload::now(modalities)));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::registers::read_registers;
+    use crate::sensors::sensirion::crc8;
+    use crate::sensors::sht3x::{
+        Rate, Repeatability, Sht3x, Sht3xHeaterPulse, Sht3xMode, Sht3xSettings,
+    };
+    use crate::sensors::{HeaterDuration, Sht3xReadout};
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+
+    fn device() -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+
+        // Responses are read right after the 2 bytes command
+        device
+            .regmap
+            .write_regs(0x37, &[0x12, 0x34, 0x37, 0x56, 0x78, 0x7D]);
+        device
+            .regmap
+            .write_regs(0x25, &[0x66, 0x66, 0x93, 0x80, 0x00, 0xA2]);
+        device.regmap.write_regs(0xF4, &[0x20, 0x00, 0x5D]);
+        device
+    }
+
+    #[test]
+    fn convert_raw_values() {
+        assert_eq!(Sht3x::<MockI2CDevice>::temperature_from_raw(0x6666), 25.0);
+        assert_eq!(Sht3x::<MockI2CDevice>::temperature_from_raw(0), -45.0);
+        assert_eq!(Sht3x::<MockI2CDevice>::humidity_from_raw(0xFFFF), 100.0);
+    }
+
+    #[test]
+    fn periodic_commands() {
+        assert_eq!(
+            Rate::One.periodic_command(Repeatability::High),
+            [0x21, 0x30]
+        );
+        assert_eq!(Rate::Ten.periodic_command(Repeatability::Low), [0x27, 0x2A]);
+        assert_eq!(
+            Rate::Half.periodic_command(Repeatability::Medium),
+            [0x20, 0x24]
+        );
+    }
+
+    #[tokio::test]
+    async fn sht3x_single_shot() {
+        let mut sht3x = Sht3x::new(device());
+        assert_eq!(sht3x.serial_number().await.unwrap(), 0x12345678);
+
+        let readouts = sht3x.read().await.unwrap().unwrap();
+        assert_eq!(readouts[0], Sht3xReadout::Temperature(25.0));
+        match readouts[1] {
+            Sht3xReadout::Humidity(h) => assert!((h - 50.0).abs() < 0.01),
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        assert!(sht3x.heater_enabled().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn sht3x_periodic() {
+        let mut device = device();
+        device
+            .regmap
+            .write_regs(0xE1, &[0x6A, 0x3D, 0xB3, 0x60, 0x00, 0xD4]);
+
+        let mut sht3x = Sht3x::with_settings(
+            device,
+            Sht3xSettings {
+                mode: Sht3xMode::Periodic(Rate::Ten),
+                ..Sht3xSettings::default()
+            },
+        );
+
+        // Nothing was measured yet
+        assert_eq!(sht3x.read().await.unwrap(), None);
+
+        tokio::time::sleep(Rate::Ten.period()).await;
+        let readouts = sht3x.read().await.unwrap().unwrap();
+        match readouts {
+            [Sht3xReadout::Temperature(t), Sht3xReadout::Humidity(h)] => {
+                assert!((t - 27.625).abs() < 0.01);
+                assert!((h - 37.5).abs() < 0.01);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // Fetched again only once the next measurement is due
+        assert_eq!(sht3x.read().await.unwrap(), Some(readouts));
+    }
+
+    #[tokio::test]
+    async fn sht3x_read_corrupted() {
+        let mut device = device();
+        device.regmap.write_regs(0x28, &[0x01]);
+
+        let mut sht3x = Sht3x::new(device);
+        match sht3x.read().await {
+            Err(PiWeatherError::ChecksumMismatch {
+                expected, computed, ..
+            }) => {
+                assert_eq!(expected, 0xA2);
+                assert_ne!(computed, expected);
+            }
+            other => panic!("Corrupted word should be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Sht3xSettings =
+            toml::from_str("repeatability = \"low\"\nmode = { periodic = \"ten\" }").unwrap();
+        assert_eq!(
+            settings,
+            Sht3xSettings {
+                repeatability: Repeatability::Low,
+                mode: Sht3xMode::Periodic(Rate::Ten),
+                condensation_recovery: None,
+            }
+        );
+
+        let settings: Sht3xSettings =
+            toml::from_str("condensation_recovery = { duration = \"seconds1\" }").unwrap();
+        assert_eq!(
+            settings.condensation_recovery,
+            Some(Sht3xHeaterPulse {
+                duration: HeaterDuration::Seconds1
+            })
+        );
+
+        assert_eq!(
+            toml::from_str::<Sht3xSettings>("").unwrap(),
+            Sht3xSettings::default()
+        );
+        assert!(toml::from_str::<Sht3xSettings>("rate = \"ten\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn sht3x_condensation_recovery() {
+        // 96% of relative humidity
+        let mut device = device();
+        device
+            .regmap
+            .write_regs(0x28, &[0xF5, 0xC3, crc8(&[0xF5, 0xC3])]);
+
+        let mut sht3x = Sht3x::with_settings(
+            device,
+            Sht3xSettings {
+                condensation_recovery: Some(Sht3xHeaterPulse {
+                    duration: HeaterDuration::Milliseconds100,
+                }),
+                ..Sht3xSettings::default()
+            },
+        );
+
+        // Heated, then switched off
+        let readouts = sht3x.read().await.unwrap().unwrap();
+        assert!(matches!(readouts[1], Sht3xReadout::Humidity(h) if h > 95.0));
+        assert!(sht3x.last_heating.is_some());
+        assert_eq!(
+            read_registers(&sht3x.device, "SHT3x", 0x30).await.unwrap(),
+            [0x66]
+        );
+
+        // Not heated again until the heater rested
+        sht3x.set_heater(true).await.unwrap();
+        sht3x.read().await.unwrap();
+        assert_eq!(
+            read_registers(&sht3x.device, "SHT3x", 0x30).await.unwrap(),
+            [0x6D]
+        );
+    }
+}
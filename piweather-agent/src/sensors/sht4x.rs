@@ -0,0 +1,396 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::sensirion::{exchange, write_command};
+use crate::sensors::{Ambient, I2CSensor, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Humidity, Modality, Payload, Temperature};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+const SHT4X_DRIVER: &str = "sht4x";
+const SHT4X_NAME: &str = "SHT4x";
+
+/// Address of SHT40/41/45-AD1B, 0x45 and 0x46 for the other variants
+const SHT4X_I2C_SLAVE_ADDRESS: u16 = 0x44;
+
+const SHT4X_COMMAND_SOFT_RESET: u8 = 0x94;
+const SHT4X_COMMAND_READ_SERIAL_NUMBER: u8 = 0x89;
+
+const SHT4X_COMMAND_TIME: Duration = Duration::from_millis(1);
+
+/// Humidity from which condensation is likely to have formed on the sensor
+const SHT4X_CONDENSATION_HUMIDITY: f32 = 95.0;
+
+/// The heater must not be on for more than a tenth of the time
+const SHT4X_HEATER_MAX_DUTY_CYCLE: u32 = 10;
+
+/// Trade-off between the noise of the measurement and its duration
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Precision {
+    #[default]
+    High,
+    Medium,
+    Low,
+}
+
+impl Precision {
+    fn command(&self) -> u8 {
+        match self {
+            Precision::High => 0xFD,
+            Precision::Medium => 0xF6,
+            Precision::Low => 0xE0,
+        }
+    }
+
+    /// Longest time a measurement can take
+    fn measurement_time(&self) -> Duration {
+        match self {
+            Precision::High => Duration::from_micros(8_300),
+            Precision::Medium => Duration::from_micros(4_500),
+            Precision::Low => Duration::from_micros(1_600),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaterPower {
+    Milliwatts200,
+    Milliwatts110,
+    Milliwatts20,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaterDuration {
+    Seconds1,
+    Milliseconds100,
+}
+
+impl HeaterDuration {
+    pub(crate) fn duration(&self) -> Duration {
+        match self {
+            HeaterDuration::Seconds1 => Duration::from_secs(1),
+            HeaterDuration::Milliseconds100 => Duration::from_millis(100),
+        }
+    }
+}
+
+/// Heating of the sensor, followed by a high precision measurement
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaterPulse {
+    pub power: HeaterPower,
+    pub duration: HeaterDuration,
+}
+
+impl HeaterPulse {
+    fn command(&self) -> u8 {
+        match (self.power, self.duration) {
+            (HeaterPower::Milliwatts200, HeaterDuration::Seconds1) => 0x39,
+            (HeaterPower::Milliwatts200, HeaterDuration::Milliseconds100) => 0x32,
+            (HeaterPower::Milliwatts110, HeaterDuration::Seconds1) => 0x2F,
+            (HeaterPower::Milliwatts110, HeaterDuration::Milliseconds100) => 0x24,
+            (HeaterPower::Milliwatts20, HeaterDuration::Seconds1) => 0x1E,
+            (HeaterPower::Milliwatts20, HeaterDuration::Milliseconds100) => 0x15,
+        }
+    }
+
+    /// Time until the measurement following the pulse is available
+    fn completion_time(&self) -> Duration {
+        self.duration.duration() + self.duration.duration() / 10
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sht4xSettings {
+    pub precision: Precision,
+
+    /// Pulse the heater when the air is close to saturation, evaporating the condensation
+    /// that would otherwise keep the humidity readouts stuck high
+    pub condensation_recovery: Option<HeaterPulse>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sht4xReadout {
+    Temperature(f32),
+    Humidity(f32),
+}
+
+impl From<Sht4xReadout> for Modality {
+    fn from(value: Sht4xReadout) -> Self {
+        match value {
+            Sht4xReadout::Temperature(t) => Modality::Temperature(Temperature::Celsius(t)),
+            Sht4xReadout::Humidity(h) => Modality::Humidity(Humidity::Relative(h)),
+        }
+    }
+}
+
+pub struct Sht4x<T: I2CDevice + Sized> {
+    settings: Sht4xSettings,
+    started: bool,
+    last_heating: Option<Instant>,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Sht4x<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Sht4xSettings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Sht4xSettings) -> Self {
+        Self {
+            settings,
+            started: false,
+            last_heating: None,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    fn temperature_from_raw(raw: u16) -> f32 {
+        -45.0 + 175.0 * raw as f32 / 65535.0
+    }
+
+    /// The conversion slightly overshoots the physical range, which is cropped
+    fn humidity_from_raw(raw: u16) -> f32 {
+        (-6.0 + 125.0 * raw as f32 / 65535.0).clamp(0.0, 100.0)
+    }
+
+    async fn measure(
+        &self,
+        command: u8,
+        delay: Duration,
+    ) -> Result<[Sht4xReadout; 2], PiWeatherError> {
+        let [temperature, humidity] = exchange(&self.device, SHT4X_NAME, &[command], delay).await?;
+        Ok([
+            Sht4xReadout::Temperature(Self::temperature_from_raw(temperature)),
+            Sht4xReadout::Humidity(Self::humidity_from_raw(humidity)),
+        ])
+    }
+
+    /// Unique serial number of the sensor
+    pub async fn serial_number(&self) -> Result<u32, PiWeatherError> {
+        let [high, low] = exchange(
+            &self.device,
+            SHT4X_NAME,
+            &[SHT4X_COMMAND_READ_SERIAL_NUMBER],
+            SHT4X_COMMAND_TIME,
+        )
+        .await?;
+        Ok(((high as u32) << 16) | low as u32)
+    }
+
+    /// Pulse the heater. The readouts measured at the end of the pulse are biased by the heat.
+    pub async fn heat(&mut self, pulse: HeaterPulse) -> Result<[Sht4xReadout; 2], PiWeatherError> {
+        let readouts = self
+            .measure(pulse.command(), pulse.completion_time())
+            .await?;
+        self.last_heating = Some(Instant::now());
+        Ok(readouts)
+    }
+
+    /// Reset the sensor and report its serial number
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        write_command(&self.device, SHT4X_NAME, &[SHT4X_COMMAND_SOFT_RESET]).await?;
+        sleep(SHT4X_COMMAND_TIME).await;
+
+        info!(
+            "{} serial number {:08X}",
+            SHT4X_NAME,
+            self.serial_number().await?
+        );
+
+        self.started = true;
+        Ok(())
+    }
+
+    pub async fn read(&mut self) -> Result<[Sht4xReadout; 2], PiWeatherError> {
+        if !self.started {
+            self.start().await?;
+        }
+
+        let precision = self.settings.precision;
+        let readouts = self
+            .measure(precision.command(), precision.measurement_time())
+            .await?;
+
+        if let (Some(pulse), [_, Sht4xReadout::Humidity(humidity)]) =
+            (self.settings.condensation_recovery, readouts)
+        {
+            let rested = self.last_heating.is_none_or(|last_heating| {
+                last_heating.elapsed() >= pulse.duration.duration() * SHT4X_HEATER_MAX_DUTY_CYCLE
+            });
+
+            if humidity >= SHT4X_CONDENSATION_HUMIDITY && rested {
+                debug!("{} saturated at {}%, heating", SHT4X_NAME, humidity);
+                self.heat(pulse).await?;
+            }
+        }
+
+        Ok(readouts)
+    }
+}
+
+impl<D> I2CSensor<D> for Sht4x<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = SHT4X_DRIVER;
+    const DEFAULT_ADDRESS: u16 = SHT4X_I2C_SLAVE_ADDRESS;
+    const MEASURES: &'static [Ambient] = &[Ambient::Temperature, Ambient::Humidity];
+    type Settings = Sht4xSettings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(Sht4x::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Sht4x<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        SHT4X_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let readouts = self.read().await?;
+        let modalities = [readouts[0].into(), readouts[1].into()];
+        Ok(Some(Payload::now(modalities)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::sht4x::{
+        HeaterDuration, HeaterPower, HeaterPulse, Precision, Sht4x, Sht4xSettings,
+    };
+    use crate::sensors::Sht4xReadout;
+    use i2cdev::mock::MockI2CDevice;
+
+    // The default high precision measurement isn't addressable by the mock register map
+    const SETTINGS: Sht4xSettings = Sht4xSettings {
+        precision: Precision::Medium,
+        condensation_recovery: None,
+    };
+
+    const PULSE: HeaterPulse = HeaterPulse {
+        power: HeaterPower::Milliwatts200,
+        duration: HeaterDuration::Milliseconds100,
+    };
+
+    fn device() -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+
+        // Responses are read from the 1 byte command
+        device
+            .regmap
+            .write_regs(0x89, &[0xCA, 0xFE, 0x58, 0xBE, 0xEF, 0x92]);
+        device
+            .regmap
+            .write_regs(0xF6, &[0x66, 0x66, 0x93, 0x80, 0x00, 0xA2]);
+        device
+            .regmap
+            .write_regs(0x32, &[0x7C, 0x3A, 0x10, 0x0F, 0x1E, 0x44]);
+        device
+    }
+
+    #[test]
+    fn convert_raw_values() {
+        assert_eq!(Sht4x::<MockI2CDevice>::temperature_from_raw(0x6666), 25.0);
+        assert_eq!(Sht4x::<MockI2CDevice>::humidity_from_raw(0), 0.0);
+        assert_eq!(Sht4x::<MockI2CDevice>::humidity_from_raw(0xFFFF), 100.0);
+    }
+
+    #[tokio::test]
+    async fn sht4x_read() {
+        let mut sht4x = Sht4x::with_settings(device(), SETTINGS);
+        assert_eq!(sht4x.serial_number().await.unwrap(), 0xCAFEBEEF);
+
+        match sht4x.read().await.unwrap() {
+            [Sht4xReadout::Temperature(t), Sht4xReadout::Humidity(h)] => {
+                assert_eq!(t, 25.0);
+                assert!((h - 56.5).abs() < 0.01);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // Not saturated, the heater isn't used
+        assert!(sht4x.last_heating.is_none());
+    }
+
+    #[tokio::test]
+    async fn sht4x_heater_pulse() {
+        let mut sht4x = Sht4x::with_settings(device(), SETTINGS);
+
+        match sht4x.heat(PULSE).await.unwrap() {
+            [Sht4xReadout::Temperature(t), Sht4xReadout::Humidity(h)] => {
+                assert!((t - 39.92).abs() < 0.01);
+                assert!((h - 1.38).abs() < 0.01);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(sht4x.last_heating.is_some());
+    }
+
+    #[tokio::test]
+    async fn sht4x_condensation_recovery() {
+        let mut device = device();
+        device.regmap.write_regs(0xF9, &[0xD2, 0xF0, 0x1E]);
+
+        let mut sht4x = Sht4x::with_settings(
+            device,
+            Sht4xSettings {
+                condensation_recovery: Some(PULSE),
+                ..SETTINGS
+            },
+        );
+
+        // Readouts measured before heating are reported
+        let readouts = sht4x.read().await.unwrap();
+        assert!(matches!(readouts[1], Sht4xReadout::Humidity(h) if h > 96.9));
+        let heated = sht4x.last_heating.unwrap();
+
+        // The heater rests between pulses
+        sht4x.read().await.unwrap();
+        assert_eq!(sht4x.last_heating, Some(heated));
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Sht4xSettings = toml::from_str(
+            "precision = \"medium\"\n\
+             condensation_recovery = { power = \"milliwatts200\", duration = \"milliseconds100\" }",
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Sht4xSettings {
+                precision: Precision::Medium,
+                condensation_recovery: Some(PULSE),
+            }
+        );
+
+        assert_eq!(
+            toml::from_str::<Sht4xSettings>("").unwrap(),
+            Sht4xSettings::default()
+        );
+    }
+}
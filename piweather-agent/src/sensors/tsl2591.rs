@@ -0,0 +1,441 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::ranging::settle;
+use crate::sensors::registers::{read_registers, write_registers};
+use crate::sensors::{AutoRanging, I2CSensor, Ranging, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Modality, Payload};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+
+const TSL2591_DRIVER: &str = "tsl2591";
+const TSL2591_NAME: &str = "TSL2591";
+const TSL2591_I2C_SLAVE_ADDRESS: u16 = 0x29;
+
+/// Every register access is prefixed by the command bit, selecting a normal operation
+const TSL2591_COMMAND: u8 = 0xA0;
+
+const TSL2591_REGISTER_ENABLE: u8 = 0x00;
+const TSL2591_REGISTER_CONTROL: u8 = 0x01;
+const TSL2591_REGISTER_ID: u8 = 0x12;
+const TSL2591_REGISTER_STATUS: u8 = 0x13;
+const TSL2591_REGISTER_C0DATAL: u8 = 0x14;
+
+const TSL2591_ID: u8 = 0x50;
+const TSL2591_ENABLE_POWER_ON: u8 = 0x01;
+const TSL2591_ENABLE_ALS: u8 = 0x02;
+const TSL2591_STATUS_VALID: u8 = 0x01;
+const TSL2591_STATUS_POLL_ATTEMPTS: usize = 3;
+
+/// Lux coefficient of the device and glass attenuation
+const TSL2591_LUX_DF: f32 = 408.0;
+
+/// Counts below which the readouts lack resolution
+const TSL2591_MIN_COUNT: u32 = 100;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tsl2591Gain {
+    Low,
+    Medium,
+    High,
+    Max,
+}
+
+impl Tsl2591Gain {
+    fn bits(&self) -> u8 {
+        match self {
+            Tsl2591Gain::Low => 0x00,
+            Tsl2591Gain::Medium => 0x10,
+            Tsl2591Gain::High => 0x20,
+            Tsl2591Gain::Max => 0x30,
+        }
+    }
+
+    fn factor(&self) -> f32 {
+        match self {
+            Tsl2591Gain::Low => 1.0,
+            Tsl2591Gain::Medium => 25.0,
+            Tsl2591Gain::High => 428.0,
+            Tsl2591Gain::Max => 9876.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tsl2591IntegrationTime {
+    Ms100,
+    Ms200,
+    Ms300,
+    Ms400,
+    Ms500,
+    Ms600,
+}
+
+impl Tsl2591IntegrationTime {
+    fn bits(&self) -> u8 {
+        match self {
+            Tsl2591IntegrationTime::Ms100 => 0x00,
+            Tsl2591IntegrationTime::Ms200 => 0x01,
+            Tsl2591IntegrationTime::Ms300 => 0x02,
+            Tsl2591IntegrationTime::Ms400 => 0x03,
+            Tsl2591IntegrationTime::Ms500 => 0x04,
+            Tsl2591IntegrationTime::Ms600 => 0x05,
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_millis(100 * (self.bits() as u64 + 1))
+    }
+
+    /// Largest count a channel can reach
+    fn max_count(&self) -> u32 {
+        match self {
+            Tsl2591IntegrationTime::Ms100 => 37888,
+            _ => 65535,
+        }
+    }
+}
+
+/// Settings by increasing sensitivity, the integration only lengthening at the maximum gain
+const TSL2591_RANGES: [(Tsl2591Gain, Tsl2591IntegrationTime); 7] = [
+    (Tsl2591Gain::Low, Tsl2591IntegrationTime::Ms100),
+    (Tsl2591Gain::Medium, Tsl2591IntegrationTime::Ms100),
+    (Tsl2591Gain::High, Tsl2591IntegrationTime::Ms100),
+    (Tsl2591Gain::Max, Tsl2591IntegrationTime::Ms100),
+    (Tsl2591Gain::Max, Tsl2591IntegrationTime::Ms200),
+    (Tsl2591Gain::Max, Tsl2591IntegrationTime::Ms400),
+    (Tsl2591Gain::Max, Tsl2591IntegrationTime::Ms600),
+];
+
+/// Range the automatic ranging starts from
+const TSL2591_INITIAL_RANGE: usize = 1;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tsl2591Settings {
+    pub ranging: Ranging<Tsl2591Gain, Tsl2591IntegrationTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tsl2591Readout {
+    /// Expressed in lux
+    Illuminance(f32),
+}
+
+impl From<Tsl2591Readout> for Modality {
+    fn from(value: Tsl2591Readout) -> Self {
+        match value {
+            Tsl2591Readout::Illuminance(lux) => Modality::Illuminance(lux),
+        }
+    }
+}
+
+pub struct Tsl2591<T: I2CDevice + Sized> {
+    settings: Tsl2591Settings,
+    range: usize,
+    started: bool,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Tsl2591<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Tsl2591Settings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Tsl2591Settings) -> Self {
+        Self {
+            settings,
+            range: TSL2591_INITIAL_RANGE,
+            started: false,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    async fn read_registers<const N: usize>(
+        &self,
+        register: u8,
+    ) -> Result<[u8; N], PiWeatherError> {
+        read_registers(&self.device, TSL2591_NAME, TSL2591_COMMAND | register).await
+    }
+
+    async fn write_register(&self, register: u8, value: u8) -> Result<(), PiWeatherError> {
+        write_registers(
+            &self.device,
+            TSL2591_NAME,
+            TSL2591_COMMAND | register,
+            &[value],
+        )
+        .await
+    }
+
+    /// Full spectrum and infrared counts
+    async fn read_channels(&self) -> Result<(u16, u16), PiWeatherError> {
+        let (_, integration_time) = self.range();
+        for _ in 0..TSL2591_STATUS_POLL_ATTEMPTS {
+            let [status] = self.read_registers::<1>(TSL2591_REGISTER_STATUS).await?;
+            if status & TSL2591_STATUS_VALID != 0 {
+                let data = self.read_registers::<4>(TSL2591_REGISTER_C0DATAL).await?;
+                return Ok((
+                    u16::from_le_bytes([data[0], data[1]]),
+                    u16::from_le_bytes([data[2], data[3]]),
+                ));
+            }
+
+            sleep(integration_time.duration()).await;
+        }
+
+        Err(PiWeatherError::I2CError(
+            "TSL2591 didn't complete its measurement".into(),
+        ))
+    }
+
+    fn lux_from_channels(
+        full: u16,
+        infrared: u16,
+        gain: Tsl2591Gain,
+        integration_time: Tsl2591IntegrationTime,
+    ) -> f32 {
+        if full == 0 {
+            return 0.0;
+        }
+
+        let (full, infrared) = (full as f32, infrared as f32);
+        let counts_per_lux =
+            integration_time.duration().as_millis() as f32 * gain.factor() / TSL2591_LUX_DF;
+        ((full - infrared) * (1.0 - infrared / full) / counts_per_lux).max(0.0)
+    }
+
+    /// Check the identity of the sensor and power it on
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        let [id] = self.read_registers::<1>(TSL2591_REGISTER_ID).await?;
+        if id != TSL2591_ID {
+            return Err(PiWeatherError::I2CError(format!(
+                "Unexpected TSL2591 identifier 0x{:02X}",
+                id
+            )));
+        }
+
+        self.write_register(
+            TSL2591_REGISTER_ENABLE,
+            TSL2591_ENABLE_POWER_ON | TSL2591_ENABLE_ALS,
+        )
+        .await?;
+        self.configure().await?;
+        self.started = true;
+        Ok(())
+    }
+
+    pub async fn read(&mut self) -> Result<Tsl2591Readout, PiWeatherError> {
+        if !self.started {
+            self.start().await?;
+        }
+
+        let (full, infrared) = self.measure_in_range().await?;
+        let (gain, integration_time) = self.range();
+        Ok(Tsl2591Readout::Illuminance(Self::lux_from_channels(
+            full,
+            infrared,
+            gain,
+            integration_time,
+        )))
+    }
+}
+
+#[async_trait]
+impl<T> AutoRanging for Tsl2591<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    type Gain = Tsl2591Gain;
+    type IntegrationTime = Tsl2591IntegrationTime;
+    type Counts = (u16, u16);
+
+    const NAME: &'static str = TSL2591_NAME;
+    const RANGES: &'static [(Tsl2591Gain, Tsl2591IntegrationTime)] = &TSL2591_RANGES;
+
+    fn ranging(&self) -> Ranging<Tsl2591Gain, Tsl2591IntegrationTime> {
+        self.settings.ranging
+    }
+
+    fn range_index(&self) -> usize {
+        self.range
+    }
+
+    fn set_range_index(&mut self, index: usize) {
+        self.range = index;
+    }
+
+    /// Ranging on the full spectrum channel
+    fn count(&(full, _): &(u16, u16)) -> u32 {
+        full as u32
+    }
+
+    /// Keep a margin below saturation, which distorts the ratio between the channels
+    fn count_limits(&self) -> (u32, u32) {
+        let (_, integration_time) = self.range();
+        (TSL2591_MIN_COUNT, integration_time.max_count() * 9 / 10)
+    }
+
+    async fn configure(&self) -> Result<(), PiWeatherError> {
+        let (gain, integration_time) = self.range();
+        self.write_register(
+            TSL2591_REGISTER_CONTROL,
+            gain.bits() | integration_time.bits(),
+        )
+        .await?;
+
+        settle(integration_time.duration()).await;
+        Ok(())
+    }
+
+    async fn measure(&self) -> Result<(u16, u16), PiWeatherError> {
+        self.read_channels().await
+    }
+}
+
+impl<D> I2CSensor<D> for Tsl2591<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = TSL2591_DRIVER;
+    const DEFAULT_ADDRESS: u16 = TSL2591_I2C_SLAVE_ADDRESS;
+    type Settings = Tsl2591Settings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(Tsl2591::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Tsl2591<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        TSL2591_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let readout = self.read().await?;
+        Ok(Some(Payload::now([readout.into()])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::tsl2591::{
+        Tsl2591, Tsl2591Gain, Tsl2591IntegrationTime, Tsl2591Readout, Tsl2591Settings,
+    };
+    use crate::sensors::{AutoRanging, Ranging};
+    use i2cdev::mock::MockI2CDevice;
+    use piweather_common::errors::PiWeatherError;
+
+    /// Registers are addressed along with the command bit
+    fn device(full: u16, infrared: u16) -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0xB2, &[0x50, 0x01]);
+        let [full_low, full_high] = full.to_le_bytes();
+        let [infrared_low, infrared_high] = infrared.to_le_bytes();
+        device
+            .regmap
+            .write_regs(0xB4, &[full_low, full_high, infrared_low, infrared_high]);
+        device
+    }
+
+    #[test]
+    fn convert_channels() {
+        type Sensor = Tsl2591<MockI2CDevice>;
+        let lux = Sensor::lux_from_channels(
+            1000,
+            200,
+            Tsl2591Gain::Medium,
+            Tsl2591IntegrationTime::Ms100,
+        );
+        assert!((lux - 104.448).abs() < 1e-3, "Unexpected {} lux", lux);
+
+        assert_eq!(
+            Sensor::lux_from_channels(0, 0, Tsl2591Gain::Max, Tsl2591IntegrationTime::Ms600),
+            0.0
+        );
+    }
+
+    #[tokio::test]
+    async fn tsl2591_read() {
+        let mut tsl2591 = Tsl2591::new(device(1000, 200));
+        match tsl2591.read().await.unwrap() {
+            Tsl2591Readout::Illuminance(lux) => assert!((lux - 104.448).abs() < 1e-3),
+        }
+
+        // Powered on, with a x25 gain and 100ms of integration
+        assert_eq!(
+            tsl2591.read_registers::<2>(0x00).await.unwrap(),
+            [0x03, 0x10]
+        );
+    }
+
+    #[tokio::test]
+    async fn tsl2591_saturated() {
+        let mut tsl2591 = Tsl2591::new(device(37000, 20000));
+        tsl2591.read().await.unwrap();
+        assert_eq!(
+            tsl2591.range(),
+            (Tsl2591Gain::Low, Tsl2591IntegrationTime::Ms100)
+        );
+        assert_eq!(tsl2591.read_registers::<1>(0x01).await.unwrap(), [0x00]);
+    }
+
+    #[tokio::test]
+    async fn tsl2591_fixed_range() {
+        let mut tsl2591 = Tsl2591::with_settings(
+            device(50, 10),
+            Tsl2591Settings {
+                ranging: Ranging::Fixed(Tsl2591Gain::High, Tsl2591IntegrationTime::Ms200),
+            },
+        );
+        tsl2591.read().await.unwrap();
+        assert_eq!(tsl2591.read_registers::<1>(0x01).await.unwrap(), [0x21]);
+    }
+
+    #[tokio::test]
+    async fn tsl2591_unknown_device() {
+        let mut device = device(1000, 200);
+        device.regmap.write_regs(0xB2, &[0x00]);
+
+        let mut tsl2591 = Tsl2591::new(device);
+        assert!(matches!(
+            tsl2591.read().await,
+            Err(PiWeatherError::I2CError(_))
+        ));
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Tsl2591Settings =
+            toml::from_str("ranging = { fixed = [\"max\", \"ms600\"] }").unwrap();
+        assert_eq!(
+            settings.ranging,
+            Ranging::Fixed(Tsl2591Gain::Max, Tsl2591IntegrationTime::Ms600)
+        );
+
+        assert_eq!(
+            toml::from_str::<Tsl2591Settings>("").unwrap(),
+            Tsl2591Settings::default()
+        );
+        assert!(toml::from_str::<Tsl2591Settings>("ranging = { fixed = [\"max\"] }").is_err());
+    }
+}
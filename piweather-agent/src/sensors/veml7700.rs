@@ -0,0 +1,374 @@
+use crate::i2c::{AsyncI2CDevice, I2CDeviceFactory};
+use crate::sensors::ranging::settle;
+use crate::sensors::registers::{read_registers, write_registers};
+use crate::sensors::{AutoRanging, I2CSensor, Ranging, Sensor};
+use async_trait::async_trait;
+use i2cdev::core::I2CDevice;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{Modality, Payload};
+use serde::Deserialize;
+use std::time::Duration;
+
+const VEML7700_DRIVER: &str = "veml7700";
+const VEML7700_NAME: &str = "VEML7700";
+const VEML7700_I2C_SLAVE_ADDRESS: u16 = 0x10;
+
+const VEML7700_REGISTER_ALS_CONF: u8 = 0x00;
+const VEML7700_REGISTER_ALS: u8 = 0x04;
+
+/// Lux per count with a x2 gain and 800ms of integration
+const VEML7700_MAX_RESOLUTION: f32 = 0.0042;
+
+/// Counts between which the readouts are accurate, as recommended by Vishay
+const VEML7700_MIN_COUNT: u32 = 100;
+const VEML7700_MAX_COUNT: u32 = 10000;
+
+/// Above this, the response of the sensor is no longer linear
+const VEML7700_LINEARITY_LIMIT: f32 = 1000.0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Veml7700Gain {
+    X1_8,
+    X1_4,
+    X1,
+    X2,
+}
+
+impl Veml7700Gain {
+    fn bits(&self) -> u16 {
+        match self {
+            Veml7700Gain::X1 => 0b00,
+            Veml7700Gain::X2 => 0b01,
+            Veml7700Gain::X1_8 => 0b10,
+            Veml7700Gain::X1_4 => 0b11,
+        }
+    }
+
+    fn factor(&self) -> f32 {
+        match self {
+            Veml7700Gain::X1_8 => 0.125,
+            Veml7700Gain::X1_4 => 0.25,
+            Veml7700Gain::X1 => 1.0,
+            Veml7700Gain::X2 => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Veml7700IntegrationTime {
+    Ms25,
+    Ms50,
+    Ms100,
+    Ms200,
+    Ms400,
+    Ms800,
+}
+
+impl Veml7700IntegrationTime {
+    fn bits(&self) -> u16 {
+        match self {
+            Veml7700IntegrationTime::Ms25 => 0b1100,
+            Veml7700IntegrationTime::Ms50 => 0b1000,
+            Veml7700IntegrationTime::Ms100 => 0b0000,
+            Veml7700IntegrationTime::Ms200 => 0b0001,
+            Veml7700IntegrationTime::Ms400 => 0b0010,
+            Veml7700IntegrationTime::Ms800 => 0b0011,
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_millis(match self {
+            Veml7700IntegrationTime::Ms25 => 25,
+            Veml7700IntegrationTime::Ms50 => 50,
+            Veml7700IntegrationTime::Ms100 => 100,
+            Veml7700IntegrationTime::Ms200 => 200,
+            Veml7700IntegrationTime::Ms400 => 400,
+            Veml7700IntegrationTime::Ms800 => 800,
+        })
+    }
+}
+
+/// Settings by increasing sensitivity: the gain is raised before lengthening the integration,
+/// as recommended by Vishay
+const VEML7700_RANGES: [(Veml7700Gain, Veml7700IntegrationTime); 9] = [
+    (Veml7700Gain::X1_8, Veml7700IntegrationTime::Ms25),
+    (Veml7700Gain::X1_8, Veml7700IntegrationTime::Ms50),
+    (Veml7700Gain::X1_8, Veml7700IntegrationTime::Ms100),
+    (Veml7700Gain::X1_4, Veml7700IntegrationTime::Ms100),
+    (Veml7700Gain::X1, Veml7700IntegrationTime::Ms100),
+    (Veml7700Gain::X2, Veml7700IntegrationTime::Ms100),
+    (Veml7700Gain::X2, Veml7700IntegrationTime::Ms200),
+    (Veml7700Gain::X2, Veml7700IntegrationTime::Ms400),
+    (Veml7700Gain::X2, Veml7700IntegrationTime::Ms800),
+];
+
+/// Range the automatic ranging starts from
+const VEML7700_INITIAL_RANGE: usize = 2;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Veml7700Settings {
+    pub ranging: Ranging<Veml7700Gain, Veml7700IntegrationTime>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Veml7700Readout {
+    /// Expressed in lux
+    Illuminance(f32),
+}
+
+impl From<Veml7700Readout> for Modality {
+    fn from(value: Veml7700Readout) -> Self {
+        match value {
+            Veml7700Readout::Illuminance(lux) => Modality::Illuminance(lux),
+        }
+    }
+}
+
+pub struct Veml7700<T: I2CDevice + Sized> {
+    settings: Veml7700Settings,
+    range: usize,
+    started: bool,
+    device: AsyncI2CDevice<T>,
+}
+
+impl<T> Veml7700<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    pub fn new(device: T) -> Self {
+        Self::with_settings(device, Veml7700Settings::default())
+    }
+
+    pub fn with_settings(device: T, settings: Veml7700Settings) -> Self {
+        Self {
+            settings,
+            range: VEML7700_INITIAL_RANGE,
+            started: false,
+            device: AsyncI2CDevice::new(device),
+        }
+    }
+
+    /// Registers hold 16 bits little-endian words
+    async fn read_register(&self, register: u8) -> Result<u16, PiWeatherError> {
+        let data = read_registers(&self.device, VEML7700_NAME, register).await?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    fn lux_from_count(
+        count: u16,
+        gain: Veml7700Gain,
+        integration_time: Veml7700IntegrationTime,
+    ) -> f32 {
+        let resolution = VEML7700_MAX_RESOLUTION
+            * (Veml7700IntegrationTime::Ms800.duration().as_secs_f32()
+                / integration_time.duration().as_secs_f32())
+            * (Veml7700Gain::X2.factor() / gain.factor());
+        let lux = count as f32 * resolution;
+
+        // Correction of the non-linearity provided by Vishay
+        if lux > VEML7700_LINEARITY_LIMIT {
+            (((6.0135e-13 * lux - 9.3924e-9) * lux + 8.1488e-5) * lux + 1.0023) * lux
+        } else {
+            lux
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<(), PiWeatherError> {
+        self.configure().await?;
+        self.started = true;
+        Ok(())
+    }
+
+    pub async fn read(&mut self) -> Result<Veml7700Readout, PiWeatherError> {
+        if !self.started {
+            self.start().await?;
+        }
+
+        let count = self.measure_in_range().await?;
+        let (gain, integration_time) = self.range();
+        Ok(Veml7700Readout::Illuminance(Self::lux_from_count(
+            count,
+            gain,
+            integration_time,
+        )))
+    }
+}
+
+#[async_trait]
+impl<T> AutoRanging for Veml7700<T>
+where
+    T: I2CDevice + Send + 'static,
+{
+    type Gain = Veml7700Gain;
+    type IntegrationTime = Veml7700IntegrationTime;
+    type Counts = u16;
+
+    const NAME: &'static str = VEML7700_NAME;
+    const RANGES: &'static [(Veml7700Gain, Veml7700IntegrationTime)] = &VEML7700_RANGES;
+
+    fn ranging(&self) -> Ranging<Veml7700Gain, Veml7700IntegrationTime> {
+        self.settings.ranging
+    }
+
+    fn range_index(&self) -> usize {
+        self.range
+    }
+
+    fn set_range_index(&mut self, index: usize) {
+        self.range = index;
+    }
+
+    fn count(count: &u16) -> u32 {
+        *count as u32
+    }
+
+    fn count_limits(&self) -> (u32, u32) {
+        (VEML7700_MIN_COUNT, VEML7700_MAX_COUNT)
+    }
+
+    /// Apply the current range, powering the sensor on, and wait for a measurement
+    async fn configure(&self) -> Result<(), PiWeatherError> {
+        let (gain, integration_time) = self.range();
+        let configuration = (gain.bits() << 11) | (integration_time.bits() << 6);
+        write_registers(
+            &self.device,
+            VEML7700_NAME,
+            VEML7700_REGISTER_ALS_CONF,
+            &configuration.to_le_bytes(),
+        )
+        .await?;
+
+        settle(integration_time.duration()).await;
+        Ok(())
+    }
+
+    async fn measure(&self) -> Result<u16, PiWeatherError> {
+        self.read_register(VEML7700_REGISTER_ALS).await
+    }
+}
+
+impl<D> I2CSensor<D> for Veml7700<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    const DRIVER: &'static str = VEML7700_DRIVER;
+    const DEFAULT_ADDRESS: u16 = VEML7700_I2C_SLAVE_ADDRESS;
+    type Settings = Veml7700Settings;
+
+    fn with_i2c_settings<F>(
+        factory: F,
+        address: u16,
+        settings: Self::Settings,
+    ) -> Result<Self, PiWeatherError>
+    where
+        F: I2CDeviceFactory<Device = D>,
+    {
+        let device = factory.open(address)?;
+        Ok(Veml7700::with_settings(device, settings))
+    }
+}
+
+#[async_trait]
+impl<D> Sensor for Veml7700<D>
+where
+    D: I2CDevice + Send + 'static,
+{
+    fn driver(&self) -> &'static str {
+        VEML7700_DRIVER
+    }
+
+    async fn payload(&mut self) -> Result<Option<Payload>, PiWeatherError> {
+        let readout = self.read().await?;
+        Ok(Some(Payload::now([readout.into()])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sensors::veml7700::{
+        Veml7700, Veml7700Gain, Veml7700IntegrationTime, Veml7700Readout, Veml7700Settings,
+    };
+    use crate::sensors::{AutoRanging, Ranging};
+    use i2cdev::mock::MockI2CDevice;
+
+    fn device(count: u16) -> MockI2CDevice {
+        let mut device = MockI2CDevice::new();
+        device.regmap.write_regs(0x04, &count.to_le_bytes());
+        device
+    }
+
+    #[test]
+    fn convert_counts() {
+        type Sensor = Veml7700<MockI2CDevice>;
+        assert_eq!(
+            Sensor::lux_from_count(1000, Veml7700Gain::X2, Veml7700IntegrationTime::Ms800),
+            4.2
+        );
+        assert_eq!(
+            Sensor::lux_from_count(1000, Veml7700Gain::X1_8, Veml7700IntegrationTime::Ms100),
+            537.6
+        );
+
+        // Non-linearity correction of the bright readouts
+        let lux = Sensor::lux_from_count(10000, Veml7700Gain::X1_8, Veml7700IntegrationTime::Ms100);
+        assert!((lux - 6786.4).abs() < 0.1, "Unexpected {} lux", lux);
+    }
+
+    #[tokio::test]
+    async fn veml7700_read() {
+        let mut veml7700 = Veml7700::new(device(1000));
+        assert_eq!(
+            veml7700.read().await.unwrap(),
+            Veml7700Readout::Illuminance(537.6)
+        );
+        assert_eq!(
+            veml7700.range(),
+            (Veml7700Gain::X1_8, Veml7700IntegrationTime::Ms100)
+        );
+        assert_eq!(veml7700.read_register(0x00).await.unwrap(), 0x1000);
+    }
+
+    #[tokio::test]
+    async fn veml7700_saturated() {
+        let mut veml7700 = Veml7700::new(device(20000));
+
+        // Down to the least sensitive range, which can't do better
+        match veml7700.read().await.unwrap() {
+            Veml7700Readout::Illuminance(lux) => assert!(lux > 43008.0),
+        }
+        assert_eq!(veml7700.range, 0);
+        assert_eq!(veml7700.read_register(0x00).await.unwrap(), 0x1300);
+    }
+
+    #[tokio::test]
+    async fn veml7700_fixed_range() {
+        let mut veml7700 = Veml7700::with_settings(
+            device(20),
+            Veml7700Settings {
+                ranging: Ranging::Fixed(Veml7700Gain::X2, Veml7700IntegrationTime::Ms25),
+            },
+        );
+
+        match veml7700.read().await.unwrap() {
+            Veml7700Readout::Illuminance(lux) => assert!((lux - 2.688).abs() < 1e-4),
+        }
+        assert_eq!(veml7700.read_register(0x00).await.unwrap(), 0x0B00);
+    }
+
+    #[test]
+    fn settings_from_config() {
+        let settings: Veml7700Settings =
+            toml::from_str("ranging = { fixed = [\"x1_4\", \"ms200\"] }").unwrap();
+        assert_eq!(
+            settings.ranging,
+            Ranging::Fixed(Veml7700Gain::X1_4, Veml7700IntegrationTime::Ms200)
+        );
+
+        let settings: Veml7700Settings = toml::from_str("ranging = \"auto\"").unwrap();
+        assert_eq!(settings, Veml7700Settings::default());
+        assert!(toml::from_str::<Veml7700Settings>("gain = \"x2\"").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use crate::queue::DiskQueue;
+use crate::sinks::Sink;
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use std::collections::VecDeque;
+use tracing::{debug, info, warn};
+
+/// Sink decorator persisting the payloads the underlying sink fails to forward.
+///
+/// Batching sinks keep their own schedule: the payloads they hold back are remembered until
+/// they report them delivered, and written to disk as soon as the sink starts failing, so that
+/// readouts survive an outage of the destination. Buffered payloads are replayed in order before
+/// any new one, so the destination receives readouts in the order they were acquired.
+pub struct Buffered {
+    sink: Box<dyn Sink>,
+    queue: DiskQueue,
+
+    /// Payloads accepted by the sink which weren't delivered yet, oldest first
+    held: VecDeque<Payload>,
+}
+
+impl Buffered {
+    pub fn wrap(sink: Box<dyn Sink>, queue: DiskQueue) -> Box<dyn Sink> {
+        Box::new(Self {
+            sink,
+            queue,
+            held: VecDeque::new(),
+        })
+    }
+
+    /// Forget the payloads the sink delivered, persisting the others if it's failing
+    async fn settle(&mut self) -> Result<(), PiWeatherError> {
+        // Sinks deliver the payloads they hold in order
+        let delivered = self.held.len().saturating_sub(self.sink.held());
+        self.held.drain(..delivered);
+
+        if self.sink.is_failing() {
+            self.spill().await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Give the sink a last chance to deliver the payloads it holds, persisting them otherwise
+    async fn spill(&mut self) -> Result<(), PiWeatherError> {
+        if self.held.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(err) = self.sink.flush().await {
+            warn!("{}, buffering payloads on disk", err);
+            for payload in self.held.iter() {
+                self.queue.push(payload).await?;
+            }
+        }
+
+        self.held.clear();
+        Ok(())
+    }
+
+    /// Forward the buffered payloads, returning whether the queue was drained
+    async fn replay(&mut self) -> bool {
+        match self.queue.replay(self.sink.as_mut()).await {
+            Ok(0) => true,
+            Ok(forwarded) => {
+                info!("Replayed {} buffered payloads", forwarded);
+                true
+            }
+            Err(err) => {
+                debug!("Destination still unavailable: {}", err);
+                false
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for Buffered {
+    async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        if self.queue.is_empty() || self.replay().await {
+            match self.sink.send(payload).await {
+                Ok(()) => {
+                    self.held.push_back(payload.clone());
+                    return self.settle().await;
+                }
+                Err(err) => {
+                    warn!("{}, buffering payloads on disk", err);
+                    self.spill().await?;
+                }
+            }
+        }
+
+        self.queue.push(payload).await
+    }
+
+    async fn tick(&mut self) -> Result<(), PiWeatherError> {
+        let result = self.sink.tick().await;
+        self.settle().await?;
+        result
+    }
+
+    async fn close(&mut self) -> Result<(), PiWeatherError> {
+        if !self.queue.is_empty() && !self.replay().await {
+            warn!(
+                "{} bytes of payloads remain buffered until next start",
+                self.queue.pending_size()
+            );
+        }
+
+        let result = self.sink.close().await;
+        if result.is_err() {
+            // Keep whatever the sink couldn't deliver for the next start
+            let delivered = self.held.len().saturating_sub(self.sink.held());
+            for payload in self.held.drain(delivered..) {
+                self.queue.push(&payload).await?;
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::queue::{DiskQueue, Retention};
+    use crate::sinks::{Buffered, Sink};
+    use async_trait::async_trait;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Humidity, Modality, Payload};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// Sink recording the sequence of the payloads, unless it's offline
+    struct FlakySink {
+        online: Arc<Mutex<bool>>,
+        sequences: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl Sink for FlakySink {
+        async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+            if !*self.online.lock().unwrap() {
+                return Err(PiWeatherError::SinkError("offline".into()));
+            }
+
+            self.sequences
+                .lock()
+                .unwrap()
+                .push(payload.sequence().unwrap());
+            Ok(())
+        }
+    }
+
+    /// Sink holding the payloads until a batch of 2 is complete, like the batching sinks do
+    struct BatchingSink {
+        online: Arc<Mutex<bool>>,
+        held: Vec<u64>,
+        failing: bool,
+        sequences: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl BatchingSink {
+        fn deliver(&mut self) -> Result<(), PiWeatherError> {
+            self.failing = !*self.online.lock().unwrap();
+            if self.failing {
+                return Err(PiWeatherError::SinkError("offline".into()));
+            }
+
+            self.sequences.lock().unwrap().append(&mut self.held);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Sink for BatchingSink {
+        async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+            self.held.push(payload.sequence().unwrap());
+            if self.held.len() >= 2 {
+                let _ = self.deliver();
+            }
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<(), PiWeatherError> {
+            let result = self.deliver();
+            self.held.clear();
+            result
+        }
+
+        async fn close(&mut self) -> Result<(), PiWeatherError> {
+            self.deliver()
+        }
+
+        fn held(&self) -> usize {
+            self.held.len()
+        }
+
+        fn is_failing(&self) -> bool {
+            self.failing
+        }
+    }
+
+    fn payload(sequence: u64) -> Payload {
+        Payload::now([Modality::Humidity(Humidity::Relative(40.0))]).with_sequence(sequence)
+    }
+
+    async fn queue(directory: &tempfile::TempDir) -> DiskQueue {
+        let retention = Retention {
+            max_size: 1024 * 1024,
+            max_age: None,
+        };
+        DiskQueue::open(directory.path(), retention).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn buffer_while_offline() {
+        let directory = tempfile::tempdir().unwrap();
+        let queue = queue(&directory).await;
+
+        let online = Arc::new(Mutex::new(true));
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = Buffered::wrap(
+            Box::new(FlakySink {
+                online: Arc::clone(&online),
+                sequences: Arc::clone(&sequences),
+            }),
+            queue,
+        );
+
+        sink.send(&payload(0)).await.unwrap();
+
+        // Readouts acquired while the destination is down are not lost
+        *online.lock().unwrap() = false;
+        for sequence in 1..4 {
+            sink.send(&payload(sequence)).await.unwrap();
+        }
+        assert_eq!(*sequences.lock().unwrap(), [0]);
+
+        // And are forwarded first once it's back
+        *online.lock().unwrap() = true;
+        sink.send(&payload(4)).await.unwrap();
+        assert_eq!(*sequences.lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn buffer_held_payloads() {
+        let directory = tempfile::tempdir().unwrap();
+        let queue = queue(&directory).await;
+
+        let online = Arc::new(Mutex::new(true));
+        let sequences = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = Buffered {
+            sink: Box::new(BatchingSink {
+                online: Arc::clone(&online),
+                held: Vec::new(),
+                failing: false,
+                sequences: Arc::clone(&sequences),
+            }),
+            queue,
+            held: VecDeque::new(),
+        };
+
+        // Payloads are delivered in batches rather than one by one
+        sink.send(&payload(0)).await.unwrap();
+        assert!(sequences.lock().unwrap().is_empty());
+        sink.send(&payload(1)).await.unwrap();
+        assert_eq!(*sequences.lock().unwrap(), [0, 1]);
+
+        // Payloads held back by a failing sink are persisted, not only accepted
+        *online.lock().unwrap() = false;
+        for sequence in 2..5 {
+            sink.send(&payload(sequence)).await.unwrap();
+        }
+        assert_eq!(*sequences.lock().unwrap(), [0, 1]);
+        assert!(sink.held.is_empty() && !sink.queue.is_empty());
+
+        // Every payload is delivered once, in order
+        *online.lock().unwrap() = true;
+        sink.send(&payload(5)).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(*sequences.lock().unwrap(), [0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::sinks::{encode, Sink};
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use std::path::PathBuf;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// Append every payload as a JSON line to a local file
+pub struct FileSink {
+    path: PathBuf,
+    file: File,
+}
+
+impl FileSink {
+    pub async fn open(uri: &Url) -> Result<Self, PiWeatherError> {
+        let path = uri.to_file_path().map_err(|_| {
+            PiWeatherError::InvalidDestination(format!("{} is not a valid file path", uri))
+        })?;
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|err| {
+                PiWeatherError::SinkError(format!("Failed to open {}: {}", path.display(), err))
+            })?;
+
+        Ok(Self { path, file })
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        let mut line = encode(payload)?;
+        line.push(b'\n');
+
+        self.file.write_all(&line).await.map_err(|err| {
+            PiWeatherError::SinkError(format!(
+                "Failed to write to {}: {}",
+                self.path.display(),
+                err
+            ))
+        })
+    }
+
+    async fn close(&mut self) -> Result<(), PiWeatherError> {
+        self.file.flush().await.map_err(|err| {
+            PiWeatherError::SinkError(format!("Failed to flush {}: {}", self.path.display(), err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sinks::open;
+    use chrono::{TimeZone, Utc};
+    use piweather_common::{Humidity, Modality, Payload, Temperature};
+
+    #[tokio::test]
+    async fn file_sink_appends_lines() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("readouts.jsonl");
+        let uri = format!("file://{}", path.display());
+
+        let mut sink = open(&uri, "test").await.expect("Failed to open file sink");
+        let when = Utc.with_ymd_and_hms(2024, 7, 14, 12, 30, 0).unwrap();
+        let payload = Payload::new(
+            when,
+            [
+                Modality::Temperature(Temperature::Celsius(21.5)),
+                Modality::Humidity(Humidity::Relative(40.0)),
+            ],
+        );
+
+        sink.send(&payload).await.unwrap();
+        sink.send(&payload).await.unwrap();
+        sink.close().await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"{"when":"2024-07-14T12:30:00Z","readouts":[{"Temperature":{"value":21.5,"unit":"°C"}},{"Humidity":{"value":40.0,"unit":"%"}}]}"#
+        );
+    }
+}
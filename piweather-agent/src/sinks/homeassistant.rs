@@ -0,0 +1,264 @@
+use piweather_common::{
+    AirQuality, ConcentrationUnit, Gas, Lightning, Modality, Particle, Quantity,
+};
+use serde::Serialize;
+
+/// Default prefix Home Assistant listens to for discovery messages
+pub const HOMEASSISTANT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+const HOMEASSISTANT_MANUFACTURER: &str = "PiWeather";
+const HOMEASSISTANT_MODEL: &str = "piweather-agent";
+
+#[derive(Debug, Serialize)]
+struct Device<'a> {
+    identifiers: [&'a str; 1],
+    name: &'a str,
+    manufacturer: &'static str,
+    model: &'static str,
+    sw_version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct SensorConfig<'a> {
+    name: String,
+    unique_id: &'a str,
+    object_id: &'a str,
+    state_topic: &'a str,
+    availability_topic: &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'static str>,
+    state_class: &'static str,
+    device: Device<'a>,
+}
+
+fn particle_name(particle: Particle) -> &'static str {
+    match particle {
+        Particle::PM0_3 => "PM0.3",
+        Particle::PM0_5 => "PM0.5",
+        Particle::PM1_0 => "PM1.0",
+        Particle::PM2_5 => "PM2.5",
+        Particle::PM5_0 => "PM5.0",
+        Particle::PM10_0 => "PM10",
+    }
+}
+
+/// Human-readable name, device class and unit of the entity exposing `modality`
+fn describe(modality: &Modality) -> (String, Option<&'static str>, Option<&'static str>) {
+    match modality {
+        Modality::Temperature(t) => (
+            "Temperature".into(),
+            Some("temperature"),
+            Some(t.unit().symbol()),
+        ),
+        Modality::Humidity(h) => ("Humidity".into(), Some("humidity"), Some(h.unit().symbol())),
+        Modality::Pressure(p) => (
+            "Pressure".into(),
+            Some("atmospheric_pressure"),
+            Some(p.unit().symbol()),
+        ),
+        Modality::Wind(w) => (
+            "Wind speed".into(),
+            Some("wind_speed"),
+            Some(w.unit().symbol()),
+        ),
+        Modality::WindGust(w) => (
+            "Wind gust".into(),
+            Some("wind_speed"),
+            Some(w.unit().symbol()),
+        ),
+        Modality::WindDirection(d) => ("Wind direction".into(), None, Some(d.unit().symbol())),
+        Modality::AirQuality(AirQuality::Concentration(particle, unit, _)) => {
+            let device_class = match particle {
+                Particle::PM1_0 => Some("pm1"),
+                Particle::PM2_5 => Some("pm25"),
+                Particle::PM10_0 => Some("pm10"),
+                _ => None,
+            };
+
+            let name = match unit {
+                ConcentrationUnit::Standard => format!("{} (standard)", particle_name(*particle)),
+                ConcentrationUnit::Environmental => particle_name(*particle).to_string(),
+            };
+
+            (name, device_class, Some("µg/m³"))
+        }
+        Modality::AirQuality(AirQuality::Count(particle, _)) => (
+            format!("{} particles", particle_name(*particle)),
+            None,
+            Some("particles/0.1L"),
+        ),
+        Modality::Rain(r) => (
+            "Rain".into(),
+            Some("precipitation"),
+            Some(r.unit().symbol()),
+        ),
+        Modality::RainRate(r) => (
+            "Rain rate".into(),
+            Some("precipitation_intensity"),
+            Some(r.unit().symbol()),
+        ),
+        Modality::Illuminance(_) => ("Illuminance".into(), Some("illuminance"), Some("lx")),
+        Modality::UvIndex(_) => ("UV index".into(), None, Some("UV index")),
+        Modality::CarbonDioxide(_) => ("CO₂".into(), Some("carbon_dioxide"), Some("ppm")),
+        Modality::GasIndex(Gas::Voc, _) => ("VOC index".into(), None, None),
+        Modality::GasIndex(Gas::Nox, _) => ("NOx index".into(), None, None),
+        Modality::Lightning(Lightning::Distance(_)) => {
+            ("Lightning distance".into(), Some("distance"), Some("km"))
+        }
+        Modality::Lightning(Lightning::Strikes(_)) => ("Lightning strikes".into(), None, None),
+    }
+}
+
+/// Counters only ever grow until the sensor restarts, everything else is a measurement
+fn state_class(modality: &Modality) -> &'static str {
+    match modality {
+        Modality::Rain(_) | Modality::Lightning(Lightning::Strikes(_)) => "total_increasing",
+        _ => "measurement",
+    }
+}
+
+/// Discovery topic and retained configuration announcing the entity publishing `modality`
+/// on `state_topic`. `object_id` has to be unique across the Home Assistant instance.
+pub(crate) fn discovery(
+    prefix: &str,
+    station: &str,
+    object_id: &str,
+    modality: &Modality,
+    state_topic: &str,
+    availability_topic: &str,
+) -> Result<(String, Vec<u8>), serde_json::Error> {
+    let (name, device_class, unit_of_measurement) = describe(modality);
+    let config = SensorConfig {
+        name,
+        unique_id: object_id,
+        object_id,
+        state_topic,
+        availability_topic,
+        device_class,
+        unit_of_measurement,
+        state_class: state_class(modality),
+        device: Device {
+            identifiers: [station],
+            name: station,
+            manufacturer: HOMEASSISTANT_MANUFACTURER,
+            model: HOMEASSISTANT_MODEL,
+            sw_version: env!("CARGO_PKG_VERSION"),
+        },
+    };
+
+    let topic = format!("{}/sensor/{}/config", prefix, object_id);
+    Ok((topic, serde_json::to_vec(&config)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sinks::homeassistant::discovery;
+    use piweather_common::{
+        AirQuality, ConcentrationUnit, Gas, Modality, Particle, Rain, Temperature,
+    };
+    use serde_json::Value;
+
+    #[test]
+    fn temperature_discovery() {
+        let (topic, config) = discovery(
+            "homeassistant",
+            "garden",
+            "garden_outdoor_temperature",
+            &Modality::Temperature(Temperature::Celsius(21.5)),
+            "piweather/garden/outdoor/temperature",
+            "piweather/garden/status",
+        )
+        .unwrap();
+
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/garden_outdoor_temperature/config"
+        );
+
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["unique_id"], "garden_outdoor_temperature");
+        assert_eq!(config["device_class"], "temperature");
+        assert_eq!(config["unit_of_measurement"], "°C");
+        assert_eq!(config["state_class"], "measurement");
+        assert_eq!(
+            config["state_topic"],
+            "piweather/garden/outdoor/temperature"
+        );
+        assert_eq!(config["availability_topic"], "piweather/garden/status");
+        assert_eq!(config["device"]["identifiers"][0], "garden");
+    }
+
+    #[test]
+    fn particulate_matter_discovery() {
+        let (_, config) = discovery(
+            "homeassistant",
+            "garden",
+            "garden_pmsa003_pm2_5_environmental",
+            &Modality::AirQuality(AirQuality::Concentration(
+                Particle::PM2_5,
+                ConcentrationUnit::Environmental,
+                12,
+            )),
+            "piweather/garden/pmsa003/pm2_5_environmental",
+            "piweather/garden/status",
+        )
+        .unwrap();
+
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["name"], "PM2.5");
+        assert_eq!(config["device_class"], "pm25");
+        assert_eq!(config["unit_of_measurement"], "µg/m³");
+
+        let (_, config) = discovery(
+            "homeassistant",
+            "garden",
+            "garden_pmsa003_pm0_3_count",
+            &Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 1032)),
+            "piweather/garden/pmsa003/pm0_3_count",
+            "piweather/garden/status",
+        )
+        .unwrap();
+
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["name"], "PM0.3 particles");
+        assert!(config.get("device_class").is_none());
+    }
+
+    #[test]
+    fn counters_discovery() {
+        let (_, config) = discovery(
+            "homeassistant",
+            "garden",
+            "garden_rain_gauge_rain",
+            &Modality::Rain(Rain::Millimeters(2.4)),
+            "piweather/garden/rain_gauge/rain",
+            "piweather/garden/status",
+        )
+        .unwrap();
+
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["device_class"], "precipitation");
+        assert_eq!(config["unit_of_measurement"], "mm");
+        assert_eq!(config["state_class"], "total_increasing");
+
+        // Indices are unitless
+        let (_, config) = discovery(
+            "homeassistant",
+            "garden",
+            "garden_sgp41_voc_index",
+            &Modality::GasIndex(Gas::Voc, 104),
+            "piweather/garden/sgp41/voc_index",
+            "piweather/garden/status",
+        )
+        .unwrap();
+
+        let config: Value = serde_json::from_slice(&config).unwrap();
+        assert_eq!(config["name"], "VOC index");
+        assert!(config.get("unit_of_measurement").is_none());
+        assert_eq!(config["state_class"], "measurement");
+    }
+}
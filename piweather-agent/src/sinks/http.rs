@@ -0,0 +1,506 @@
+use crate::sinks::{encode, redact, Sink};
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE,
+};
+use reqwest::Client;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use url::Url;
+
+const HTTP_DEFAULT_BATCH_SIZE: usize = 16;
+const HTTP_DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const HTTP_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const HTTP_MAX_BACKOFF: Duration = Duration::from_secs(300);
+const HTTP_DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Payloads are rejected once this many batches are waiting for the destination to recover
+const HTTP_MAX_PENDING_BATCHES: usize = 8;
+
+/// Options of the HTTP sink, provided as query parameters of the destination URI and
+/// removed from the URL the payloads are posted to:
+///
+/// - `batch_size`: number of payloads per request, 16 by default
+/// - `flush_interval`: maximum number of seconds a payload waits for its batch to fill up, 60 by default
+/// - `gzip`: compress the requests if `true`
+/// - `timeout`: maximum number of seconds a request may take, 10 by default
+/// - `token`: bearer token sent in the `Authorization` header
+/// - `header`: additional `Name: value` header, can be repeated
+#[derive(Debug, Clone)]
+pub struct HttpSinkOptions {
+    pub endpoint: Url,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub gzip: bool,
+    pub timeout: Duration,
+    pub headers: HeaderMap,
+}
+
+impl HttpSinkOptions {
+    pub fn from_uri(uri: &Url) -> Result<Self, PiWeatherError> {
+        let invalid = |reason: String| {
+            PiWeatherError::InvalidDestination(format!("{}: {}", redact(uri.as_str()), reason))
+        };
+
+        let mut options = Self {
+            endpoint: uri.clone(),
+            batch_size: HTTP_DEFAULT_BATCH_SIZE,
+            flush_interval: HTTP_DEFAULT_FLUSH_INTERVAL,
+            gzip: false,
+            timeout: HTTP_DEFAULT_TIMEOUT,
+            headers: HeaderMap::new(),
+        };
+
+        let mut forwarded = Vec::new();
+        for (key, value) in uri.query_pairs() {
+            match key.as_ref() {
+                "batch_size" => {
+                    options.batch_size =
+                        value.parse().ok().filter(|size| *size > 0).ok_or_else(|| {
+                            invalid(format!(
+                                "batch_size must be a positive number, got {}",
+                                value
+                            ))
+                        })?
+                }
+                "flush_interval" => {
+                    let secs = value.parse().map_err(|_| {
+                        invalid(format!(
+                            "flush_interval must be a number of seconds, got {}",
+                            value
+                        ))
+                    })?;
+                    options.flush_interval = Duration::from_secs(secs);
+                }
+                "gzip" => {
+                    options.gzip = value
+                        .parse()
+                        .map_err(|_| invalid(format!("gzip must be a boolean, got {}", value)))?
+                }
+                "timeout" => {
+                    let secs = value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                        invalid(format!(
+                            "timeout must be a positive number of seconds, got {}",
+                            value
+                        ))
+                    })?;
+                    options.timeout = Duration::from_secs(secs);
+                }
+                "token" => {
+                    let token = HeaderValue::try_from(format!("Bearer {}", value))
+                        .map_err(|_| invalid("token is not a valid header value".into()))?;
+                    options.headers.insert(AUTHORIZATION, token);
+                }
+                "header" => {
+                    let (name, content) = value.split_once(':').ok_or_else(|| {
+                        invalid(format!("header {} is not \"Name: value\"", value))
+                    })?;
+                    let name = HeaderName::try_from(name.trim())
+                        .map_err(|_| invalid(format!("{} is not a valid header name", name)))?;
+                    let content = HeaderValue::try_from(content.trim())
+                        .map_err(|_| invalid(format!("{} is not a valid header value", content)))?;
+                    options.headers.append(name, content);
+                }
+                _ => forwarded.push((key, value)),
+            }
+        }
+
+        // Unknown parameters belong to the destination
+        options.endpoint.set_query(None);
+        if !forwarded.is_empty() {
+            options.endpoint.query_pairs_mut().extend_pairs(forwarded);
+        }
+
+        Ok(options)
+    }
+}
+
+/// POST batches of payloads as JSON arrays to an HTTP or HTTPS endpoint.
+///
+/// A batch is sent once `batch_size` payloads are pending or the oldest of them waited for
+/// `flush_interval`, which the periodic ticks check even when no payload comes. Failed requests
+/// are retried with an exponential backoff, new payloads being accepted until
+/// `HTTP_MAX_PENDING_BATCHES` batches are pending.
+pub struct HttpSink {
+    client: Client,
+    options: HttpSinkOptions,
+
+    /// Endpoint without its credentials, as written in the logs
+    destination: String,
+
+    /// Encoded payloads waiting to be sent, oldest first
+    pending: VecDeque<Vec<u8>>,
+    oldest: Option<Instant>,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl HttpSink {
+    pub fn open(uri: &Url) -> Result<Self, PiWeatherError> {
+        let options = HttpSinkOptions::from_uri(uri)?;
+        let client = Client::builder()
+            .default_headers(options.headers.clone())
+            .timeout(options.timeout)
+            .connect_timeout(options.timeout.min(HTTP_CONNECT_TIMEOUT))
+            .build()
+            .map_err(|err| {
+                PiWeatherError::SinkError(format!("Failed to create HTTP client: {}", err))
+            })?;
+
+        Ok(Self {
+            client,
+            destination: redact(options.endpoint.as_str()),
+            options,
+            pending: VecDeque::new(),
+            oldest: None,
+            backoff: HTTP_INITIAL_BACKOFF,
+            retry_at: None,
+        })
+    }
+
+    #[inline]
+    fn max_pending(&self) -> usize {
+        self.options.batch_size * HTTP_MAX_PENDING_BATCHES
+    }
+
+    /// Whether a batch is full, the oldest payload waited long enough or a failed one should be retried
+    fn is_due(&self) -> bool {
+        self.pending.len() >= self.options.batch_size
+            || (self.retry_at.is_some() && !self.pending.is_empty())
+            || self
+                .oldest
+                .is_some_and(|oldest| oldest.elapsed() >= self.options.flush_interval)
+    }
+
+    /// Concatenate the first `count` pending payloads into a JSON array
+    fn batch(&self, count: usize) -> Result<Vec<u8>, PiWeatherError> {
+        let mut body = Vec::with_capacity(count * 256);
+        body.push(b'[');
+        for (index, payload) in self.pending.iter().take(count).enumerate() {
+            if index > 0 {
+                body.push(b',');
+            }
+            body.extend_from_slice(payload);
+        }
+        body.push(b']');
+
+        if self.options.gzip {
+            let mut encoder =
+                GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::default());
+            encoder
+                .write_all(&body)
+                .and_then(|_| encoder.finish())
+                .map_err(|err| {
+                    PiWeatherError::SinkError(format!("Failed to compress batch: {}", err))
+                })
+        } else {
+            Ok(body)
+        }
+    }
+
+    async fn post(&self, body: Vec<u8>) -> Result<(), PiWeatherError> {
+        let mut request = self
+            .client
+            .post(self.options.endpoint.clone())
+            .header(CONTENT_TYPE, "application/json");
+
+        if self.options.gzip {
+            request = request.header(CONTENT_ENCODING, "gzip");
+        }
+
+        let response = request.body(body).send().await.map_err(|err| {
+            PiWeatherError::SinkError(format!("Failed to POST to {}: {}", self.destination, err))
+        })?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(PiWeatherError::SinkError(format!(
+                "{} answered with status {}",
+                self.destination,
+                response.status()
+            )))
+        }
+    }
+
+    /// Send every pending payload in batches, stopping at the first failure
+    async fn deliver(&mut self) -> Result<(), PiWeatherError> {
+        while !self.pending.is_empty() {
+            let count = self.pending.len().min(self.options.batch_size);
+            self.post(self.batch(count)?).await?;
+
+            self.pending.drain(..count);
+            debug!("Sent a batch of {} payloads", count);
+        }
+
+        self.oldest = None;
+        Ok(())
+    }
+
+    /// Flush unless the destination is backing off, scheduling the next attempt on failure
+    async fn try_flush(&mut self) {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return;
+        }
+
+        match self.deliver().await {
+            Ok(()) => {
+                self.backoff = HTTP_INITIAL_BACKOFF;
+                self.retry_at = None;
+            }
+            Err(err) => {
+                warn!("{}, retrying in {:?}", err, self.backoff);
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(HTTP_MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for HttpSink {
+    async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        if self.pending.len() >= self.max_pending() {
+            self.try_flush().await;
+            if self.pending.len() >= self.max_pending() {
+                return Err(PiWeatherError::SinkError(format!(
+                    "{} payloads are already waiting for {}",
+                    self.pending.len(),
+                    self.destination
+                )));
+            }
+        }
+
+        self.pending.push_back(encode(payload)?);
+        self.oldest.get_or_insert_with(Instant::now);
+
+        if self.is_due() {
+            self.try_flush().await;
+        }
+
+        Ok(())
+    }
+
+    async fn tick(&mut self) -> Result<(), PiWeatherError> {
+        if self.is_due() {
+            self.try_flush().await;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), PiWeatherError> {
+        self.try_flush().await;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let dropped = self.pending.len();
+        self.pending.clear();
+        self.oldest = None;
+        Err(PiWeatherError::SinkError(format!(
+            "{} is unavailable, {} payloads were not sent",
+            self.destination, dropped
+        )))
+    }
+
+    fn held(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_failing(&self) -> bool {
+        self.retry_at.is_some()
+    }
+
+    async fn close(&mut self) -> Result<(), PiWeatherError> {
+        let pending = self.pending.len();
+        self.deliver().await.map_err(|err| {
+            PiWeatherError::SinkError(format!("{}, {} payloads were lost", err, pending))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sinks::http::{HttpSink, HttpSinkOptions};
+    use crate::sinks::{stub, Sink};
+    use flate2::read::GzDecoder;
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{Humidity, Modality, Payload};
+    use serde_json::Value;
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use url::Url;
+
+    fn payload(sequence: u64) -> Payload {
+        Payload::now([Modality::Humidity(Humidity::Relative(40.0))]).with_sequence(sequence)
+    }
+
+    fn sequences(body: &[u8]) -> Vec<u64> {
+        let batch: Value = serde_json::from_slice(body).unwrap();
+        batch
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|payload| payload["sequence"].as_u64().unwrap())
+            .collect()
+    }
+
+    async fn serve(statuses: &'static [&'static str]) -> (u16, mpsc::Receiver<stub::Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, requests) = mpsc::channel(16);
+        tokio::spawn(stub::serve(listener, statuses, sender));
+
+        (port, requests)
+    }
+
+    #[test]
+    fn options_from_query() {
+        let uri = Url::parse(
+            "http://collector.local/readouts?batch_size=4&flush_interval=5&gzip=true&timeout=3&token=secret&header=X-Station:%20garden&site=home",
+        )
+        .unwrap();
+        let options = HttpSinkOptions::from_uri(&uri).unwrap();
+
+        assert_eq!(
+            options.endpoint.as_str(),
+            "http://collector.local/readouts?site=home"
+        );
+        assert_eq!(options.batch_size, 4);
+        assert_eq!(options.flush_interval, Duration::from_secs(5));
+        assert!(options.gzip);
+        assert_eq!(options.timeout, Duration::from_secs(3));
+        assert_eq!(options.headers["authorization"], "Bearer secret");
+        assert_eq!(options.headers["x-station"], "garden");
+
+        let uri = Url::parse("https://collector.local:8443/readouts?gzip=true&site=home").unwrap();
+        let options = HttpSinkOptions::from_uri(&uri).unwrap();
+        assert_eq!(
+            options.endpoint.as_str(),
+            "https://collector.local:8443/readouts?site=home"
+        );
+        assert!(options.gzip);
+
+        for uri in [
+            "http://collector.local?batch_size=0",
+            "http://collector.local?gzip=yes",
+            "http://collector.local?timeout=0",
+            "http://collector.local?header=X-Station",
+        ] {
+            assert!(matches!(
+                HttpSinkOptions::from_uri(&Url::parse(uri).unwrap()),
+                Err(PiWeatherError::InvalidDestination(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn post_gzip_batches() {
+        let (port, mut requests) = serve(&["204 No Content"]).await;
+        let uri = Url::parse(&format!(
+            "http://127.0.0.1:{}/readouts?batch_size=2&gzip=true&token=secret",
+            port
+        ))
+        .unwrap();
+        let mut sink = HttpSink::open(&uri).unwrap();
+
+        for sequence in 0..3 {
+            sink.send(&payload(sequence)).await.unwrap();
+        }
+        sink.close().await.unwrap();
+
+        for expected in [vec![0, 1], vec![2]] {
+            let request = requests.recv().await.unwrap();
+            assert_eq!(request.headers[0], "POST /readouts HTTP/1.1");
+            assert_eq!(request.header("authorization"), Some("Bearer secret"));
+            assert_eq!(request.header("content-encoding"), Some("gzip"));
+
+            let mut body = Vec::new();
+            GzDecoder::new(request.body.as_slice())
+                .read_to_end(&mut body)
+                .unwrap();
+            assert_eq!(sequences(&body), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_failed_batches() {
+        let (port, mut requests) = serve(&["503 Service Unavailable", "204 No Content"]).await;
+        let uri = Url::parse(&format!("http://127.0.0.1:{}/?batch_size=1", port)).unwrap();
+        let mut sink = HttpSink::open(&uri).unwrap();
+
+        // The first attempt fails, the payload is kept for later
+        sink.send(&payload(0)).await.unwrap();
+        assert_eq!(sequences(&requests.recv().await.unwrap().body), [0]);
+
+        // Payloads are accepted while backing off, until too many of them are pending
+        for sequence in 1..8 {
+            sink.send(&payload(sequence)).await.unwrap();
+        }
+        assert!(matches!(
+            sink.send(&payload(8)).await,
+            Err(PiWeatherError::SinkError(_))
+        ));
+        assert!(requests.try_recv().is_err());
+
+        // Everything pending is delivered in order
+        sink.close().await.unwrap();
+        for expected in 0..8 {
+            assert_eq!(sequences(&requests.recv().await.unwrap().body), [expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn flush_on_tick() {
+        let (port, mut requests) = serve(&["204 No Content"]).await;
+        let uri = Url::parse(&format!("http://127.0.0.1:{}/?flush_interval=60", port)).unwrap();
+        let mut sink = HttpSink::open(&uri).unwrap();
+
+        sink.send(&payload(0)).await.unwrap();
+        sink.tick().await.unwrap();
+        assert!(requests.try_recv().is_err());
+
+        // The batch is sent once it waited long enough, even though no payload came
+        sink.oldest = Some(Instant::now() - Duration::from_secs(60));
+        sink.tick().await.unwrap();
+        assert_eq!(sequences(&requests.recv().await.unwrap().body), [0]);
+    }
+
+    #[tokio::test]
+    async fn flush_acknowledges_delivery() {
+        let (port, mut requests) = serve(&["503 Service Unavailable", "204 No Content"]).await;
+        let uri = Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap();
+        let mut sink = HttpSink::open(&uri).unwrap();
+
+        // Payloads that couldn't be delivered are handed back to the caller
+        sink.send(&payload(0)).await.unwrap();
+        assert!(matches!(
+            sink.flush().await,
+            Err(PiWeatherError::SinkError(_))
+        ));
+        assert_eq!(sequences(&requests.recv().await.unwrap().body), [0]);
+
+        // Nothing is sent while backing off
+        sink.send(&payload(1)).await.unwrap();
+        assert!(sink.flush().await.is_err());
+        assert!(requests.try_recv().is_err());
+
+        sink.retry_at = Some(Instant::now());
+        sink.send(&payload(2)).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(sequences(&requests.recv().await.unwrap().body), [2]);
+    }
+}
@@ -0,0 +1,607 @@
+use crate::sinks::{redact, Sink};
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::{AirQuality, Lightning, Modality, Payload, Quantity};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Client;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use url::Url;
+
+const INFLUXDB_DEFAULT_PORT: u16 = 8086;
+const INFLUXDB_DEFAULT_BATCH_SIZE: usize = 16;
+const INFLUXDB_DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const INFLUXDB_DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const INFLUXDB_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const INFLUXDB_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const INFLUXDB_MAX_BACKOFF: Duration = Duration::from_secs(300);
+const INFLUXDB_UNKNOWN_SENSOR: &str = "sensor";
+
+/// Failed batches are kept for the next write, new payloads being refused beyond this many batches
+const INFLUXDB_MAX_PENDING_BATCHES: usize = 8;
+
+/// Escape commas, equal signs and spaces in tag keys and values
+fn escape_tag(value: &str, line: &mut String) {
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            line.push('\\');
+        }
+        line.push(c);
+    }
+}
+
+/// Whether the fields of `modality` can be written, InfluxDB rejecting NaN and infinite floats
+fn is_finite(modality: &Modality) -> bool {
+    match modality {
+        Modality::Humidity(h) => h.value().is_finite(),
+        Modality::Pressure(p) => p.value().is_finite(),
+        Modality::Temperature(t) => t.value().is_finite(),
+        Modality::Wind(w) | Modality::WindGust(w) => w.value().is_finite(),
+        Modality::WindDirection(d) => d.degrees().is_finite(),
+        Modality::Rain(r) => r.value().is_finite(),
+        Modality::RainRate(r) => r.value().is_finite(),
+        Modality::Illuminance(value) | Modality::UvIndex(value) => value.is_finite(),
+        Modality::Lightning(Lightning::Distance(km)) => km.is_finite(),
+        Modality::AirQuality(_)
+        | Modality::CarbonDioxide(_)
+        | Modality::GasIndex(_, _)
+        | Modality::Lightning(Lightning::Strikes(_)) => true,
+    }
+}
+
+/// Encode every readout of `payload` as a line of InfluxDB line protocol, with millisecond timestamps.
+///
+/// Each modality is written to its own measurement, tagged with the station and the sensor name.
+/// Readouts which aren't finite numbers are skipped, as they would fail the whole batch:
+///
+/// ```text
+/// temperature,station=garden,sensor=outdoor,unit=celsius value=21.5 1700000000000
+/// air_quality,station=garden,sensor=pmsa003,particle=pm2_5,unit=environmental concentration=12i 1700000000000
+/// ```
+pub fn line_protocol(payload: &Payload, station: &str, lines: &mut String) {
+    let timestamp = payload.when().timestamp_millis();
+    let sensor = payload.sensor().unwrap_or(INFLUXDB_UNKNOWN_SENSOR);
+
+    for modality in payload
+        .readouts()
+        .iter()
+        .filter(|modality| is_finite(modality))
+    {
+        let measurement = match modality {
+            Modality::Humidity(_) => "humidity",
+            Modality::Pressure(_) => "pressure",
+            Modality::Temperature(_) => "temperature",
+            Modality::Wind(_) | Modality::WindGust(_) | Modality::WindDirection(_) => "wind",
+            Modality::AirQuality(_) => "air_quality",
+            Modality::Rain(_) | Modality::RainRate(_) => "rain",
+            Modality::Illuminance(_) | Modality::UvIndex(_) => "light",
+            Modality::CarbonDioxide(_) | Modality::GasIndex(_, _) => "gas",
+            Modality::Lightning(_) => "lightning",
+        };
+
+        lines.push_str(measurement);
+        lines.push_str(",station=");
+        escape_tag(station, lines);
+        lines.push_str(",sensor=");
+        escape_tag(sensor, lines);
+
+        // Writing into a String never fails
+        let _ = match modality {
+            Modality::Humidity(h) => {
+                write!(lines, ",unit={} value={}", h.unit().as_str(), h.value())
+            }
+            Modality::Pressure(p) => {
+                write!(lines, ",unit={} value={}", p.unit().as_str(), p.value())
+            }
+            Modality::Temperature(t) => {
+                write!(lines, ",unit={} value={}", t.unit().as_str(), t.value())
+            }
+            Modality::Wind(w) => write!(lines, ",unit={} speed={}", w.unit().as_str(), w.value()),
+            Modality::WindGust(w) => {
+                write!(lines, ",unit={} gust={}", w.unit().as_str(), w.value())
+            }
+            Modality::WindDirection(d) => {
+                write!(
+                    lines,
+                    ",unit={} direction={}",
+                    d.unit().as_str(),
+                    d.degrees()
+                )
+            }
+            Modality::AirQuality(AirQuality::Concentration(particle, unit, value)) => write!(
+                lines,
+                ",particle={},unit={} concentration={}i",
+                particle.as_str(),
+                unit.as_str(),
+                value
+            ),
+            Modality::AirQuality(AirQuality::Count(particle, value)) => {
+                write!(lines, ",particle={} count={}i", particle.as_str(), value)
+            }
+            Modality::Rain(r) => {
+                write!(
+                    lines,
+                    ",unit={} accumulation={}",
+                    r.unit().as_str(),
+                    r.value()
+                )
+            }
+            Modality::RainRate(r) => {
+                write!(lines, ",unit={} rate={}", r.unit().as_str(), r.value())
+            }
+            Modality::Illuminance(lux) => write!(lines, " illuminance={}", lux),
+            Modality::UvIndex(index) => write!(lines, " uv_index={}", index),
+            Modality::CarbonDioxide(ppm) => write!(lines, ",gas=co2 concentration={}i", ppm),
+            Modality::GasIndex(gas, index) => {
+                write!(lines, ",gas={} index={}i", gas.as_str(), index)
+            }
+            Modality::Lightning(Lightning::Distance(km)) => write!(lines, " distance={}", km),
+            Modality::Lightning(Lightning::Strikes(count)) => write!(lines, " strikes={}i", count),
+        };
+
+        let _ = writeln!(lines, " {}", timestamp);
+    }
+}
+
+/// Write payloads to an InfluxDB v2 bucket, described by the destination URI:
+/// `influxdb://host[:port]?bucket=weather[&org=home&token=...&batch_size=16&flush_interval=60&timeout=10]`,
+/// or `influxdbs://` to write over HTTPS.
+///
+/// Payloads are buffered and written to `/api/v2/write` once `batch_size` of them are pending
+/// or the oldest of them waited for more than `flush_interval` seconds, checked on every tick. Lines failing to be
+/// written are kept and retried with an exponential backoff, unless the sink is explicitly flushed.
+pub struct InfluxDbSink {
+    client: Client,
+    endpoint: Url,
+    token: Option<String>,
+    station: String,
+    batch_size: usize,
+    flush_interval: Duration,
+    lines: String,
+    pending: usize,
+    since: Option<Instant>,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl InfluxDbSink {
+    pub fn open(uri: &Url, station: &str) -> Result<Self, PiWeatherError> {
+        let invalid = |reason: String| {
+            PiWeatherError::InvalidDestination(format!("{}: {}", redact(uri.as_str()), reason))
+        };
+
+        let host = uri
+            .host_str()
+            .ok_or_else(|| invalid("doesn't specify a host".into()))?;
+
+        let scheme = if uri.scheme() == "influxdbs" {
+            "https"
+        } else {
+            "http"
+        };
+        let mut endpoint = Url::parse(&format!(
+            "{}://{}:{}/api/v2/write",
+            scheme,
+            host,
+            uri.port().unwrap_or(INFLUXDB_DEFAULT_PORT)
+        ))
+        .map_err(|err| invalid(err.to_string()))?;
+
+        let (mut bucket, mut org, mut token) = (None, None, None);
+        let mut batch_size = INFLUXDB_DEFAULT_BATCH_SIZE;
+        let mut flush_interval = INFLUXDB_DEFAULT_FLUSH_INTERVAL;
+        let mut timeout = INFLUXDB_DEFAULT_TIMEOUT;
+
+        for (key, value) in uri.query_pairs() {
+            match key.as_ref() {
+                "bucket" => bucket = Some(value.to_string()),
+                "org" => org = Some(value.to_string()),
+                "token" => token = Some(value.to_string()),
+                "batch_size" => {
+                    batch_size = value.parse().ok().filter(|size| *size > 0).ok_or_else(|| {
+                        invalid(format!(
+                            "batch_size must be a positive number, got {}",
+                            value
+                        ))
+                    })?
+                }
+                "flush_interval" => {
+                    let secs = value.parse().map_err(|_| {
+                        invalid(format!(
+                            "flush_interval must be a number of seconds, got {}",
+                            value
+                        ))
+                    })?;
+                    flush_interval = Duration::from_secs(secs);
+                }
+                "timeout" => {
+                    let secs = value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                        invalid(format!(
+                            "timeout must be a positive number of seconds, got {}",
+                            value
+                        ))
+                    })?;
+                    timeout = Duration::from_secs(secs);
+                }
+                key => return Err(invalid(format!("unknown parameter \"{}\"", key))),
+            }
+        }
+
+        let bucket = bucket.ok_or_else(|| invalid("missing bucket parameter".into()))?;
+        {
+            let mut query = endpoint.query_pairs_mut();
+            if let Some(org) = &org {
+                query.append_pair("org", org);
+            }
+            query.append_pair("bucket", &bucket);
+            query.append_pair("precision", "ms");
+        }
+
+        let client = Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout.min(INFLUXDB_CONNECT_TIMEOUT))
+            .build()
+            .map_err(|err| {
+                PiWeatherError::SinkError(format!("Failed to create HTTP client: {}", err))
+            })?;
+
+        Ok(Self {
+            client,
+            endpoint,
+            token,
+            station: station.to_string(),
+            batch_size,
+            flush_interval,
+            lines: String::new(),
+            pending: 0,
+            since: None,
+            backoff: INFLUXDB_INITIAL_BACKOFF,
+            retry_at: None,
+        })
+    }
+
+    #[inline]
+    fn max_pending(&self) -> usize {
+        self.batch_size * INFLUXDB_MAX_PENDING_BATCHES
+    }
+
+    /// Whether a batch is full, the oldest payload waited long enough or failed lines should be retried
+    fn is_due(&self) -> bool {
+        self.pending >= self.batch_size
+            || (self.retry_at.is_some() && self.pending > 0)
+            || self
+                .since
+                .is_some_and(|since| since.elapsed() >= self.flush_interval)
+    }
+
+    /// Write every buffered line, keeping them around for the next attempt on failure
+    async fn write(&mut self) -> Result<(), PiWeatherError> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut request = self
+            .client
+            .post(self.endpoint.clone())
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(self.lines.clone());
+
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Token {}", token));
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                self.clear();
+                Ok(())
+            }
+            Ok(response) => Err(PiWeatherError::SinkError(format!(
+                "{} answered with status {}",
+                self.endpoint,
+                response.status()
+            ))),
+            Err(err) => Err(PiWeatherError::SinkError(format!(
+                "Failed to write to {}: {}",
+                self.endpoint, err
+            ))),
+        }
+    }
+
+    /// Write unless the server is backing off, scheduling the next attempt on failure
+    async fn try_write(&mut self) {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return;
+        }
+
+        match self.write().await {
+            Ok(()) => {
+                self.backoff = INFLUXDB_INITIAL_BACKOFF;
+                self.retry_at = None;
+            }
+            Err(err) => {
+                warn!("{}, retrying in {:?}", err, self.backoff);
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(INFLUXDB_MAX_BACKOFF);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.lines.clear();
+        self.pending = 0;
+        self.since = None;
+    }
+}
+
+#[async_trait]
+impl Sink for InfluxDbSink {
+    async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        if self.pending >= self.max_pending() {
+            self.try_write().await;
+            if self.pending >= self.max_pending() {
+                return Err(PiWeatherError::SinkError(format!(
+                    "{} payloads are already waiting for {}",
+                    self.pending, self.endpoint
+                )));
+            }
+        }
+
+        line_protocol(payload, &self.station, &mut self.lines);
+        self.pending += 1;
+        self.since.get_or_insert_with(Instant::now);
+
+        if self.is_due() {
+            self.try_write().await;
+        }
+
+        Ok(())
+    }
+
+    async fn tick(&mut self) -> Result<(), PiWeatherError> {
+        if self.is_due() {
+            self.try_write().await;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), PiWeatherError> {
+        self.try_write().await;
+        if self.pending == 0 {
+            return Ok(());
+        }
+
+        let dropped = self.pending;
+        self.clear();
+        Err(PiWeatherError::SinkError(format!(
+            "{} is unavailable, {} payloads were not written",
+            self.endpoint, dropped
+        )))
+    }
+
+    fn held(&self) -> usize {
+        self.pending
+    }
+
+    fn is_failing(&self) -> bool {
+        self.retry_at.is_some()
+    }
+
+    async fn close(&mut self) -> Result<(), PiWeatherError> {
+        self.write().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sinks::influxdb::{line_protocol, InfluxDbSink};
+    use crate::sinks::stub;
+    use crate::sinks::Sink;
+    use chrono::{TimeZone, Utc};
+    use piweather_common::errors::PiWeatherError;
+    use piweather_common::{
+        AirQuality, ConcentrationUnit, Direction, Gas, Humidity, Lightning, Modality, Particle,
+        Payload, Pressure, Rain, RainRate, Temperature, Wind,
+    };
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use url::Url;
+
+    fn payload() -> Payload {
+        let when = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+        Payload::new(
+            when,
+            [
+                Modality::Temperature(Temperature::Celsius(21.5)),
+                Modality::Humidity(Humidity::Relative(45.2)),
+                Modality::Pressure(Pressure::Hectopascal(1013.0)),
+                Modality::Wind(Wind::Kph(12.0)),
+                Modality::WindGust(Wind::Kph(20.5)),
+                Modality::WindDirection(Direction::Degrees(225.0)),
+                Modality::AirQuality(AirQuality::Concentration(
+                    Particle::PM2_5,
+                    ConcentrationUnit::Environmental,
+                    12,
+                )),
+                Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 1032)),
+            ],
+        )
+        .with_sensor("outdoor")
+    }
+
+    #[test]
+    fn encode_line_protocol() {
+        let mut lines = String::new();
+        line_protocol(&payload(), "my garden", &mut lines);
+
+        assert_eq!(
+            lines,
+            "temperature,station=my\\ garden,sensor=outdoor,unit=celsius value=21.5 1700000000123\n\
+             humidity,station=my\\ garden,sensor=outdoor,unit=percent value=45.2 1700000000123\n\
+             pressure,station=my\\ garden,sensor=outdoor,unit=hpa value=1013 1700000000123\n\
+             wind,station=my\\ garden,sensor=outdoor,unit=kph speed=12 1700000000123\n\
+             wind,station=my\\ garden,sensor=outdoor,unit=kph gust=20.5 1700000000123\n\
+             wind,station=my\\ garden,sensor=outdoor,unit=degrees direction=225 1700000000123\n\
+             air_quality,station=my\\ garden,sensor=outdoor,particle=pm2_5,unit=environmental concentration=12i 1700000000123\n\
+             air_quality,station=my\\ garden,sensor=outdoor,particle=pm0_3 count=1032i 1700000000123\n"
+        );
+    }
+
+    #[test]
+    fn encode_environment_modalities() {
+        let when = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+        let payload = Payload::new(
+            when,
+            [
+                Modality::Rain(Rain::Millimeters(2.5)),
+                Modality::RainRate(RainRate::MillimetersPerHour(0.5)),
+                Modality::Illuminance(1250.5),
+                Modality::UvIndex(3.0),
+                Modality::CarbonDioxide(612),
+                Modality::GasIndex(Gas::Voc, 104),
+                Modality::Lightning(Lightning::Distance(12.0)),
+                Modality::Lightning(Lightning::Strikes(3)),
+            ],
+        )
+        .with_sensor("roof");
+
+        let mut lines = String::new();
+        line_protocol(&payload, "garden", &mut lines);
+
+        assert_eq!(
+            lines,
+            "rain,station=garden,sensor=roof,unit=mm accumulation=2.5 1700000000123\n\
+             rain,station=garden,sensor=roof,unit=mm_h rate=0.5 1700000000123\n\
+             light,station=garden,sensor=roof illuminance=1250.5 1700000000123\n\
+             light,station=garden,sensor=roof uv_index=3 1700000000123\n\
+             gas,station=garden,sensor=roof,gas=co2 concentration=612i 1700000000123\n\
+             gas,station=garden,sensor=roof,gas=voc index=104i 1700000000123\n\
+             lightning,station=garden,sensor=roof distance=12 1700000000123\n\
+             lightning,station=garden,sensor=roof strikes=3i 1700000000123\n"
+        );
+    }
+
+    #[test]
+    fn skip_non_finite_readouts() {
+        let when = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+        let payload = Payload::new(
+            when,
+            [
+                Modality::Temperature(Temperature::Celsius(f32::NAN)),
+                Modality::Humidity(Humidity::Relative(45.2)),
+                Modality::Illuminance(f32::INFINITY),
+            ],
+        )
+        .with_sensor("outdoor");
+
+        let mut lines = String::new();
+        line_protocol(&payload, "garden", &mut lines);
+
+        assert_eq!(
+            lines,
+            "humidity,station=garden,sensor=outdoor,unit=percent value=45.2 1700000000123\n"
+        );
+    }
+
+    #[test]
+    fn open_over_https() {
+        let uri = Url::parse("influxdbs://influx.local?bucket=weather").unwrap();
+        let sink = InfluxDbSink::open(&uri, "garden").unwrap();
+        assert_eq!(
+            sink.endpoint.as_str(),
+            "https://influx.local:8086/api/v2/write?bucket=weather&precision=ms"
+        );
+    }
+
+    #[test]
+    fn open_invalid_options() {
+        for uri in [
+            "influxdb://localhost",
+            "influxdb://localhost?bucket=weather&batch_size=0",
+            "influxdb://localhost?bucket=weather&timeout=0",
+            "influxdb://localhost?bucket=weather&precision=s",
+        ] {
+            let uri = Url::parse(uri).unwrap();
+            assert!(matches!(
+                InfluxDbSink::open(&uri, "garden"),
+                Err(PiWeatherError::InvalidDestination(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn write_batches() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, mut requests) = mpsc::channel(4);
+        tokio::spawn(stub::serve(listener, &["204 No Content"], sender));
+
+        let uri = Url::parse(&format!(
+            "influxdb://127.0.0.1:{}?org=home&bucket=weather&token=secret&batch_size=2",
+            port
+        ))
+        .unwrap();
+        let mut sink = InfluxDbSink::open(&uri, "garden").unwrap();
+
+        // The first payload is only buffered
+        sink.send(&payload()).await.unwrap();
+        assert!(requests.try_recv().is_err());
+
+        sink.send(&payload()).await.unwrap();
+        let request = requests.recv().await.unwrap();
+        assert_eq!(
+            request.headers[0],
+            "POST /api/v2/write?org=home&bucket=weather&precision=ms HTTP/1.1"
+        );
+        assert_eq!(request.header("authorization"), Some("Token secret"));
+        assert_eq!(String::from_utf8(request.body).unwrap().lines().count(), 16);
+
+        // Closing the sink writes whatever remains
+        sink.send(&payload()).await.unwrap();
+        sink.close().await.unwrap();
+        let request = requests.recv().await.unwrap();
+        assert_eq!(String::from_utf8(request.body).unwrap().lines().count(), 8);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_writes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, mut requests) = mpsc::channel(4);
+        tokio::spawn(stub::serve(
+            listener,
+            &["503 Service Unavailable", "204 No Content"],
+            sender,
+        ));
+
+        let uri = Url::parse(&format!(
+            "influxdb://127.0.0.1:{}?bucket=weather&batch_size=1",
+            port
+        ))
+        .unwrap();
+        let mut sink = InfluxDbSink::open(&uri, "garden").unwrap();
+
+        // The first write fails, the lines are kept without reporting the payload as lost
+        sink.send(&payload()).await.unwrap();
+        assert_eq!(requests.recv().await.unwrap().body.len(), sink.lines.len());
+
+        // Payloads are accepted while backing off, until too many of them are pending
+        for _ in 1..8 {
+            sink.send(&payload()).await.unwrap();
+        }
+        assert!(matches!(
+            sink.send(&payload()).await,
+            Err(PiWeatherError::SinkError(_))
+        ));
+        assert!(requests.try_recv().is_err());
+
+        // Everything pending is written at once
+        sink.close().await.unwrap();
+        let request = requests.recv().await.unwrap();
+        assert_eq!(String::from_utf8(request.body).unwrap().lines().count(), 64);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::sinks::{encode, Sink};
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use tokio::io::{stdout, AsyncWriteExt, Stdout};
+
+/// Print every payload as a JSON line on the standard output
+pub struct StdoutSink {
+    stdout: Stdout,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self { stdout: stdout() }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        let mut line = encode(payload)?;
+        line.push(b'\n');
+
+        self.stdout
+            .write_all(&line)
+            .await
+            .map_err(|err| PiWeatherError::SinkError(format!("Failed to write to stdout: {}", err)))
+    }
+
+    async fn close(&mut self) -> Result<(), PiWeatherError> {
+        self.stdout
+            .flush()
+            .await
+            .map_err(|err| PiWeatherError::SinkError(format!("Failed to flush stdout: {}", err)))
+    }
+}
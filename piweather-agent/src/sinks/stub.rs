@@ -0,0 +1,67 @@
+//! Minimal HTTP server standing in for the destinations of the HTTP based sinks
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// Request received by the stub server
+pub(crate) struct Request {
+    /// Request line followed by the headers
+    pub headers: Vec<String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().skip(1).find_map(|header| {
+            let (key, value) = header.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// Answer the n-th request with the n-th of `statuses`, the last one being repeated,
+/// forwarding every request to `requests`
+pub(crate) async fn serve(
+    listener: TcpListener,
+    statuses: &'static [&'static str],
+    requests: mpsc::Sender<Request>,
+) {
+    for index in 0.. {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+
+        let mut headers = Vec::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+            headers.push(line);
+        }
+
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+
+        let status = statuses[index.min(statuses.len() - 1)];
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        requests.send(Request { headers, body }).await.unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use crate::sinks::{encode, Sink};
+use async_trait::async_trait;
+use piweather_common::errors::PiWeatherError;
+use piweather_common::Payload;
+use tokio::net::{lookup_host, UdpSocket};
+use url::Url;
+
+/// Send every payload as a single JSON datagram
+pub struct UdpSink {
+    socket: UdpSocket,
+}
+
+impl UdpSink {
+    pub async fn open(uri: &Url) -> Result<Self, PiWeatherError> {
+        let host = uri.host_str().ok_or_else(|| {
+            PiWeatherError::InvalidDestination(format!("{} doesn't specify a host", uri))
+        })?;
+        let port = uri.port().ok_or_else(|| {
+            PiWeatherError::InvalidDestination(format!("{} doesn't specify a port", uri))
+        })?;
+
+        // IPv6 literals keep their brackets, which the resolver expects along with the port
+        let destination = lookup_host(format!("{}:{}", host, port))
+            .await
+            .map_err(|err| {
+                PiWeatherError::SinkError(format!("Failed to resolve {}: {}", uri, err))
+            })?
+            .next()
+            .ok_or_else(|| {
+                PiWeatherError::SinkError(format!("{} doesn't resolve to any address", uri))
+            })?;
+
+        // Bind the unspecified address of the same family as the destination
+        let local = if destination.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local).await.map_err(|err| {
+            PiWeatherError::SinkError(format!("Failed to bind UDP socket: {}", err))
+        })?;
+
+        socket.connect(destination).await.map_err(|err| {
+            PiWeatherError::SinkError(format!("Failed to connect to {}: {}", uri, err))
+        })?;
+
+        Ok(Self { socket })
+    }
+}
+
+#[async_trait]
+impl Sink for UdpSink {
+    async fn send(&mut self, payload: &Payload) -> Result<(), PiWeatherError> {
+        let datagram = encode(payload)?;
+        self.socket
+            .send(&datagram)
+            .await
+            .map(|_| ())
+            .map_err(|err| PiWeatherError::SinkError(format!("Failed to send datagram: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sinks::open;
+    use piweather_common::{Modality, Payload, Pressure};
+    use tokio::net::UdpSocket;
+
+    #[tokio::test]
+    async fn udp_sink_sends_datagram() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("udp://{}", server.local_addr().unwrap());
+
+        let mut sink = open(&uri, "test").await.expect("Failed to open UDP sink");
+        let payload =
+            Payload::now([Modality::Pressure(Pressure::Hectopascal(1013.0))]).with_sequence(1);
+        sink.send(&payload).await.unwrap();
+
+        let mut buffer = [0u8; 256];
+        let size = server.recv(&mut buffer).await.unwrap();
+        let received: Payload = serde_json::from_slice(&buffer[..size]).unwrap();
+        assert_eq!(received, payload);
+    }
+
+    #[tokio::test]
+    async fn udp_sink_sends_ipv6_datagram() {
+        let server = UdpSocket::bind("[::1]:0").await.unwrap();
+        let uri = format!("udp://{}", server.local_addr().unwrap());
+
+        let mut sink = open(&uri, "test").await.expect("Failed to open UDP sink");
+        let payload = Payload::now([Modality::Pressure(Pressure::Hectopascal(1013.0))]);
+        sink.send(&payload).await.unwrap();
+
+        let mut buffer = [0u8; 256];
+        let size = server.recv(&mut buffer).await.unwrap();
+        let received: Payload = serde_json::from_slice(&buffer[..size]).unwrap();
+        assert_eq!(received, payload);
+    }
+}
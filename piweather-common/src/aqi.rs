@@ -0,0 +1,418 @@
+//! Air quality indices computed from particulate matter concentrations.
+//!
+//! Only `ConcentrationUnit::Environmental` concentrations of PM2.5 and PM10 are taken into
+//! account, averaged over the window each index is defined for.
+
+use crate::{AirQuality, ConcentrationUnit, Modality, Particle, Payload};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::VecDeque;
+
+/// Concentration range, in μg/m³, and the matching index range
+struct Breakpoint {
+    low: f32,
+    high: f32,
+    index_low: f32,
+    index_high: f32,
+    category: &'static str,
+}
+
+const fn breakpoint(
+    low: f32,
+    high: f32,
+    index_low: f32,
+    index_high: f32,
+    category: &'static str,
+) -> Breakpoint {
+    Breakpoint {
+        low,
+        high,
+        index_low,
+        index_high,
+        category,
+    }
+}
+
+/// US EPA breakpoints for the 24h PM2.5 average, as revised in 2024
+const EPA_PM2_5: [Breakpoint; 6] = [
+    breakpoint(0.0, 9.0, 0.0, 50.0, "Good"),
+    breakpoint(9.1, 35.4, 51.0, 100.0, "Moderate"),
+    breakpoint(35.5, 55.4, 101.0, 150.0, "Unhealthy for Sensitive Groups"),
+    breakpoint(55.5, 125.4, 151.0, 200.0, "Unhealthy"),
+    breakpoint(125.5, 225.4, 201.0, 300.0, "Very Unhealthy"),
+    breakpoint(225.5, 325.4, 301.0, 500.0, "Hazardous"),
+];
+
+/// US EPA breakpoints for the 24h PM10 average
+const EPA_PM10: [Breakpoint; 6] = [
+    breakpoint(0.0, 54.0, 0.0, 50.0, "Good"),
+    breakpoint(55.0, 154.0, 51.0, 100.0, "Moderate"),
+    breakpoint(155.0, 254.0, 101.0, 150.0, "Unhealthy for Sensitive Groups"),
+    breakpoint(255.0, 354.0, 151.0, 200.0, "Unhealthy"),
+    breakpoint(355.0, 424.0, 201.0, 300.0, "Very Unhealthy"),
+    breakpoint(425.0, 604.0, 301.0, 500.0, "Hazardous"),
+];
+
+/// CAQI background grid for the hourly PM2.5 average
+const CAQI_PM2_5: [Breakpoint; 5] = [
+    breakpoint(0.0, 15.0, 0.0, 25.0, "Very low"),
+    breakpoint(15.0, 30.0, 25.0, 50.0, "Low"),
+    breakpoint(30.0, 55.0, 50.0, 75.0, "Medium"),
+    breakpoint(55.0, 110.0, 75.0, 100.0, "High"),
+    // Open-ended, extrapolated with the slope of the previous class
+    breakpoint(110.0, 330.0, 100.0, 200.0, "Very high"),
+];
+
+/// CAQI background grid for the hourly PM10 average
+const CAQI_PM10: [Breakpoint; 5] = [
+    breakpoint(0.0, 25.0, 0.0, 25.0, "Very low"),
+    breakpoint(25.0, 50.0, 25.0, 50.0, "Low"),
+    breakpoint(50.0, 90.0, 50.0, 75.0, "Medium"),
+    breakpoint(90.0, 180.0, 75.0, 100.0, "High"),
+    breakpoint(180.0, 540.0, 100.0, 200.0, "Very high"),
+];
+
+/// Upper concentration of every UK DAQI band for the 24h PM2.5 mean, band 10 being open-ended
+const DAQI_PM2_5: [f32; 9] = [11.0, 23.0, 35.0, 41.0, 47.0, 53.0, 58.0, 64.0, 70.0];
+
+/// Upper concentration of every UK DAQI band for the 24h PM10 mean, band 10 being open-ended
+const DAQI_PM10: [f32; 9] = [16.0, 33.0, 50.0, 58.0, 66.0, 75.0, 83.0, 91.0, 100.0];
+
+/// Value of an air quality index, driven by its dominant pollutant
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AirQualityIndex {
+    pub scale: Scale,
+    pub value: u16,
+    pub category: &'static str,
+    pub dominant: Particle,
+}
+
+/// Air quality index standards
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+pub enum Scale {
+    /// US EPA AQI, from 0 to 500, over 24h averages
+    UsEpa,
+
+    /// European Common Air Quality Index, from 0 to over 100, over hourly averages
+    Caqi,
+
+    /// UK Daily Air Quality Index, from 1 to 10, over 24h running means
+    Daqi,
+}
+
+impl Scale {
+    /// Period concentrations are averaged over
+    pub fn window(&self) -> TimeDelta {
+        match self {
+            Scale::UsEpa | Scale::Daqi => TimeDelta::hours(24),
+            Scale::Caqi => TimeDelta::hours(1),
+        }
+    }
+
+    /// Index of a single pollutant, `concentration` being averaged over `window()`.
+    /// Returns `None` for pollutants the scale doesn't cover.
+    pub fn sub_index(&self, particle: Particle, concentration: f32) -> Option<(u16, &'static str)> {
+        let concentration = concentration.max(0.0);
+        match (self, particle) {
+            // Concentrations are truncated to the precision of the breakpoints,
+            // the ones beyond the EPA scale being reported at its maximum
+            (Scale::UsEpa, Particle::PM2_5) => Some(interpolate(
+                &EPA_PM2_5,
+                ((concentration * 10.0).floor() / 10.0).min(325.4),
+            )),
+            (Scale::UsEpa, Particle::PM10_0) => {
+                Some(interpolate(&EPA_PM10, concentration.floor().min(604.0)))
+            }
+            (Scale::Caqi, Particle::PM2_5) => Some(interpolate(&CAQI_PM2_5, concentration)),
+            (Scale::Caqi, Particle::PM10_0) => Some(interpolate(&CAQI_PM10, concentration)),
+            (Scale::Daqi, Particle::PM2_5) => Some(band(&DAQI_PM2_5, concentration.round())),
+            (Scale::Daqi, Particle::PM10_0) => Some(band(&DAQI_PM10, concentration.round())),
+            _ => None,
+        }
+    }
+
+    /// Index over the averaged PM2.5 and PM10 concentrations, the highest sub-index wins
+    pub fn index(&self, pm2_5: Option<f32>, pm10: Option<f32>) -> Option<AirQualityIndex> {
+        [(Particle::PM2_5, pm2_5), (Particle::PM10_0, pm10)]
+            .into_iter()
+            .filter_map(|(particle, concentration)| {
+                let (value, category) = self.sub_index(particle, concentration?)?;
+                Some(AirQualityIndex {
+                    scale: *self,
+                    value,
+                    category,
+                    dominant: particle,
+                })
+            })
+            .reduce(|dominant, index| {
+                if index.value > dominant.value {
+                    index
+                } else {
+                    dominant
+                }
+            })
+    }
+}
+
+/// Linear interpolation of the index within the range `concentration` falls in,
+/// extrapolated from the highest range beyond it
+fn interpolate(breakpoints: &[Breakpoint], concentration: f32) -> (u16, &'static str) {
+    let (last, ranges) = breakpoints.split_last().expect("Empty breakpoints");
+    let range = ranges
+        .iter()
+        .find(|range| concentration <= range.high)
+        .unwrap_or(last);
+
+    let index = (range.index_high - range.index_low) / (range.high - range.low)
+        * (concentration - range.low)
+        + range.index_low;
+    (index.round() as u16, range.category)
+}
+
+/// UK DAQI band `concentration` falls in, with its banding
+fn band(upper_bounds: &[f32], concentration: f32) -> (u16, &'static str) {
+    let band = upper_bounds
+        .iter()
+        .position(|upper| concentration <= *upper)
+        .unwrap_or(upper_bounds.len())
+        + 1;
+
+    let category = match band {
+        1..=3 => "Low",
+        4..=6 => "Moderate",
+        7..=9 => "High",
+        _ => "Very High",
+    };
+
+    (band as u16, category)
+}
+
+/// Rolling history of the PM2.5 and PM10 concentrations, over the longest averaging window
+#[derive(Debug, Default, Clone)]
+pub struct AirQualityHistory {
+    samples: VecDeque<(DateTime<Utc>, Particle, f32)>,
+}
+
+impl AirQualityHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a readout acquired at `when`, ignoring the ones no index is computed from
+    pub fn push(&mut self, when: DateTime<Utc>, readout: &AirQuality) {
+        if let AirQuality::Concentration(
+            particle @ (Particle::PM2_5 | Particle::PM10_0),
+            ConcentrationUnit::Environmental,
+            concentration,
+        ) = *readout
+        {
+            self.samples
+                .push_back((when, particle, f32::from(concentration)));
+        }
+
+        // Keep what the longest window needs, relative to the most recent sample. Samples
+        // are recorded in order, so it's the last one
+        if let Some(&(latest, _, _)) = self.samples.back() {
+            let horizon = latest - Scale::UsEpa.window().max(Scale::Daqi.window());
+            while self
+                .samples
+                .front()
+                .is_some_and(|(when, _, _)| *when <= horizon)
+            {
+                self.samples.pop_front();
+            }
+        }
+    }
+
+    /// Record the air quality readouts of `payload`
+    pub fn record(&mut self, payload: &Payload) {
+        for readout in payload.readouts() {
+            if let Modality::AirQuality(air_quality) = readout {
+                self.push(payload.when(), air_quality);
+            }
+        }
+    }
+
+    /// Mean concentration of `particle` over the `window` preceding `now`
+    pub fn average(
+        &self,
+        particle: Particle,
+        window: TimeDelta,
+        now: DateTime<Utc>,
+    ) -> Option<f32> {
+        let (sum, count) = self
+            .samples
+            .iter()
+            .filter(|(when, p, _)| *p == particle && *when > now - window && *when <= now)
+            .fold((0.0, 0), |(sum, count), (_, _, value)| {
+                (sum + value, count + 1)
+            });
+
+        (count > 0).then(|| sum / count as f32)
+    }
+
+    /// Index on `scale` at time `now`, `None` without any recent PM readout
+    pub fn index(&self, scale: Scale, now: DateTime<Utc>) -> Option<AirQualityIndex> {
+        let window = scale.window();
+        scale.index(
+            self.average(Particle::PM2_5, window, now),
+            self.average(Particle::PM10_0, window, now),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aqi::{AirQualityHistory, Scale};
+    use crate::{AirQuality, ConcentrationUnit, Modality, Particle, Payload};
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    #[test]
+    fn us_epa_breakpoints() {
+        // Concentration (μg/m³), AQI, category
+        for (concentration, aqi, category) in [
+            (0.0, 0, "Good"),
+            (9.0, 50, "Good"),
+            (9.05, 50, "Good"),
+            (12.0, 56, "Moderate"),
+            (35.4, 100, "Moderate"),
+            (35.5, 101, "Unhealthy for Sensitive Groups"),
+            (55.5, 151, "Unhealthy"),
+            (150.0, 225, "Very Unhealthy"),
+            (325.4, 500, "Hazardous"),
+            (800.0, 500, "Hazardous"),
+        ] {
+            assert_eq!(
+                Scale::UsEpa.sub_index(Particle::PM2_5, concentration),
+                Some((aqi, category)),
+                "PM2.5 at {}",
+                concentration
+            );
+        }
+
+        for (concentration, aqi) in [(54.0, 50), (154.0, 100), (200.0, 123), (424.0, 300)] {
+            assert_eq!(
+                Scale::UsEpa
+                    .sub_index(Particle::PM10_0, concentration)
+                    .map(|(aqi, _)| aqi),
+                Some(aqi),
+                "PM10 at {}",
+                concentration
+            );
+        }
+
+        assert_eq!(Scale::UsEpa.sub_index(Particle::PM1_0, 10.0), None);
+    }
+
+    #[test]
+    fn caqi_grid() {
+        for (particle, concentration, caqi, category) in [
+            (Particle::PM2_5, 15.0, 25, "Very low"),
+            (Particle::PM2_5, 40.0, 60, "Medium"),
+            (Particle::PM2_5, 110.0, 100, "High"),
+            (Particle::PM2_5, 165.0, 125, "Very high"),
+            (Particle::PM10_0, 40.0, 40, "Low"),
+            (Particle::PM10_0, 135.0, 88, "High"),
+        ] {
+            assert_eq!(
+                Scale::Caqi.sub_index(particle, concentration),
+                Some((caqi, category)),
+                "{:?} at {}",
+                particle,
+                concentration
+            );
+        }
+    }
+
+    #[test]
+    fn daqi_bands() {
+        for (particle, concentration, daqi, category) in [
+            (Particle::PM2_5, 0.0, 1, "Low"),
+            (Particle::PM2_5, 11.4, 1, "Low"),
+            (Particle::PM2_5, 36.0, 4, "Moderate"),
+            (Particle::PM2_5, 58.0, 7, "High"),
+            (Particle::PM2_5, 71.0, 10, "Very High"),
+            (Particle::PM10_0, 50.0, 3, "Low"),
+            (Particle::PM10_0, 101.0, 10, "Very High"),
+        ] {
+            assert_eq!(
+                Scale::Daqi.sub_index(particle, concentration),
+                Some((daqi, category)),
+                "{:?} at {}",
+                particle,
+                concentration
+            );
+        }
+    }
+
+    #[test]
+    fn dominant_pollutant() {
+        let index = Scale::UsEpa.index(Some(12.0), Some(200.0)).unwrap();
+        assert_eq!(index.value, 123);
+        assert_eq!(index.dominant, Particle::PM10_0);
+
+        let index = Scale::UsEpa.index(Some(40.0), None).unwrap();
+        assert_eq!(index.dominant, Particle::PM2_5);
+
+        assert_eq!(Scale::Caqi.index(None, None), None);
+    }
+
+    #[test]
+    fn averaging_windows() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        let mut history = AirQualityHistory::new();
+
+        // A clean day, then a polluted hour
+        for hour in 0..24 {
+            history.record(&Payload::new(
+                start + TimeDelta::hours(hour),
+                [Modality::AirQuality(AirQuality::Concentration(
+                    Particle::PM2_5,
+                    ConcentrationUnit::Environmental,
+                    5,
+                ))],
+            ));
+        }
+
+        let now = start + TimeDelta::hours(24);
+        history.record(&Payload::new(
+            now,
+            [
+                Modality::AirQuality(AirQuality::Concentration(
+                    Particle::PM2_5,
+                    ConcentrationUnit::Environmental,
+                    120,
+                )),
+                // Only environmental concentrations are averaged
+                Modality::AirQuality(AirQuality::Concentration(
+                    Particle::PM2_5,
+                    ConcentrationUnit::Standard,
+                    500,
+                )),
+            ],
+        ));
+
+        // The hourly index reacts to the spike, the daily ones smooth it
+        assert_eq!(
+            history.average(Particle::PM2_5, TimeDelta::hours(1), now),
+            Some(120.0)
+        );
+        assert_eq!(
+            history.average(Particle::PM2_5, TimeDelta::hours(24), now),
+            Some((23.0 * 5.0 + 120.0) / 24.0)
+        );
+
+        assert_eq!(
+            history.index(Scale::Caqi, now).unwrap().category,
+            "Very high"
+        );
+        assert_eq!(
+            history.index(Scale::UsEpa, now).unwrap().category,
+            "Moderate"
+        );
+        assert_eq!(history.index(Scale::Daqi, now).unwrap().value, 1);
+        assert_eq!(
+            history.index(Scale::UsEpa, now).unwrap().dominant,
+            Particle::PM2_5
+        );
+    }
+}
@@ -0,0 +1,400 @@
+//! Meteorological quantities derived from the temperature, relative humidity and wind readouts.
+//!
+//! Temperatures can be provided in either unit, derived temperatures are expressed in the
+//! unit of the provided one. Relative humidity is expressed in percent.
+
+use crate::{Humidity, Modality, Quantity, Temperature, Wind};
+use std::ops::RangeInclusive;
+
+/// Coefficients of the Magnus formula (Sonntag, 1990) over water, valid in [-45, 60]°C
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+
+/// The Magnus formula diverges in perfectly dry air, which sensors do report when clamping
+const MAGNUS_MIN_HUMIDITY: f32 = 0.1;
+
+/// The heat index is only meaningful in hot weather
+const HEAT_INDEX_MIN_FAHRENHEIT: f32 = 80.0;
+
+/// Humidex is only reported for warm weather by Environment Canada
+const HUMIDEX_MIN_CELSIUS: f32 = 20.0;
+
+/// Validity range of the wet-bulb approximation
+const WET_BULB_CELSIUS: RangeInclusive<f32> = -20.0..=50.0;
+const WET_BULB_HUMIDITY: RangeInclusive<f32> = 5.0..=99.0;
+
+/// Wind chill is only defined for cold temperatures and noticeable wind
+const WIND_CHILL_MAX_CELSIUS: f32 = 10.0;
+const WIND_CHILL_MIN_KPH: f32 = 4.8;
+
+#[inline]
+fn celsius(temperature: Temperature) -> f32 {
+    match temperature.to_celsius() {
+        Temperature::Celsius(t) | Temperature::Fahrenheit(t) => t,
+    }
+}
+
+#[inline]
+fn fahrenheit(temperature: Temperature) -> f32 {
+    match temperature.to_fahrenheit() {
+        Temperature::Celsius(t) | Temperature::Fahrenheit(t) => t,
+    }
+}
+
+/// Express `celsius` in the unit of `reference`
+#[inline]
+fn like(celsius: f32, reference: Temperature) -> Temperature {
+    match reference {
+        Temperature::Celsius(_) => Temperature::Celsius(celsius),
+        Temperature::Fahrenheit(_) => Temperature::Celsius(celsius).to_fahrenheit(),
+    }
+}
+
+/// Temperature at which the air would be saturated with its current water vapour content.
+/// Humidity is raised to 0.1% in dry air, where the dew point is undefined.
+///
+/// ```
+/// use piweather_common::derived::dew_point;
+/// use piweather_common::Temperature;
+///
+/// match dew_point(Temperature::Celsius(20.0), 50.0) {
+///     Temperature::Celsius(t) => assert!((t - 9.26).abs() < 0.01),
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn dew_point(temperature: Temperature, humidity: f32) -> Temperature {
+    let t = celsius(temperature);
+    let gamma = (humidity.max(MAGNUS_MIN_HUMIDITY) / 100.0).ln() + MAGNUS_B * t / (MAGNUS_C + t);
+    like(MAGNUS_C * gamma / (MAGNUS_B - gamma), temperature)
+}
+
+/// Apparent temperature combining heat and humidity, as computed by the US National Weather
+/// Service (Rothfusz regression with its low and high humidity adjustments).
+/// Returns `None` below 80°F, where it isn't meaningful.
+pub fn heat_index(temperature: Temperature, humidity: f32) -> Option<Temperature> {
+    if fahrenheit(temperature) < HEAT_INDEX_MIN_FAHRENHEIT {
+        return None;
+    }
+
+    // The regression coefficients are published with more digits than f32 holds
+    let (t, rh) = (f64::from(fahrenheit(temperature)), f64::from(humidity));
+
+    // Steadman's simple formula is accurate enough below 80°F
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let index = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let regression = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh
+            - 0.224_755_41 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            regression - (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt()
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            regression + (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0)
+        } else {
+            regression
+        }
+    };
+
+    Some(match temperature {
+        Temperature::Celsius(_) => Temperature::Fahrenheit(index as f32).to_celsius(),
+        Temperature::Fahrenheit(_) => Temperature::Fahrenheit(index as f32),
+    })
+}
+
+/// Canadian humidex, a dimensionless index of the felt temperature in Celsius.
+/// Returns `None` below 20°C, where it isn't reported.
+pub fn humidex(temperature: Temperature, humidity: f32) -> Option<f32> {
+    let t = celsius(temperature);
+    if t < HUMIDEX_MIN_CELSIUS {
+        return None;
+    }
+
+    let dew_point = celsius(dew_point(temperature, humidity));
+    let vapour_pressure = 6.11 * (5417.753 * (1.0 / 273.16 - 1.0 / (273.15 + dew_point))).exp();
+    Some(t + 0.5555 * (vapour_pressure - 10.0))
+}
+
+/// Mass of water vapour in a volume of air, in g/m³
+pub fn absolute_humidity(temperature: Temperature, humidity: f32) -> f32 {
+    let t = celsius(temperature);
+    let saturation = 6.112 * (17.67 * t / (t + 243.5)).exp();
+    saturation * humidity * 2.1674 / (273.15 + t)
+}
+
+/// Temperature the air would cool down to by evaporating water, following Stull (2011).
+/// Returns `None` outside of its validity range: relative humidity in [5, 99]% and
+/// temperature in [-20, 50]°C.
+pub fn wet_bulb(temperature: Temperature, humidity: f32) -> Option<Temperature> {
+    let (t, rh) = (celsius(temperature), humidity);
+    if !WET_BULB_CELSIUS.contains(&t) || !WET_BULB_HUMIDITY.contains(&rh) {
+        return None;
+    }
+
+    let wet_bulb = t * (0.151_977 * (rh + 8.313_659).sqrt()).atan() + (t + rh).atan()
+        - (rh - 1.676_331).atan()
+        + 0.003_918_38 * rh.powf(1.5) * (0.023_101 * rh).atan()
+        - 4.686_035;
+
+    Some(like(wet_bulb, temperature))
+}
+
+/// Felt temperature due to the wind, following the index used in North America since 2001.
+/// Returns `None` above 10°C or for winds below 4.8 km/h, where it isn't defined.
+pub fn wind_chill(temperature: Temperature, wind: Wind) -> Option<Temperature> {
+    let t = celsius(temperature);
+    let speed = wind.to_kph().value();
+
+    if t > WIND_CHILL_MAX_CELSIUS || speed < WIND_CHILL_MIN_KPH {
+        return None;
+    }
+
+    let factor = speed.powf(0.16);
+    Some(like(
+        13.12 + 0.6215 * t - 11.37 * factor + 0.3965 * t * factor,
+        temperature,
+    ))
+}
+
+/// Every quantity which can be derived from a set of readouts
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Derived {
+    pub dew_point: Option<Temperature>,
+    pub heat_index: Option<Temperature>,
+    pub humidex: Option<f32>,
+    pub absolute_humidity: Option<f32>,
+    pub wet_bulb: Option<Temperature>,
+    pub wind_chill: Option<Temperature>,
+}
+
+impl Derived {
+    /// Derive quantities from the first temperature, humidity and wind of `readouts`
+    ///
+    /// ```
+    /// use piweather_common::derived::Derived;
+    /// use piweather_common::{Humidity, Modality, Payload, Temperature};
+    ///
+    /// let payload = Payload::now([
+    ///     Modality::Temperature(Temperature::Celsius(25.0)),
+    ///     Modality::Humidity(Humidity::Relative(60.0)),
+    /// ]);
+    ///
+    /// let derived = Derived::from_readouts(payload.readouts());
+    /// assert!(derived.dew_point.is_some());
+    /// assert!(derived.wind_chill.is_none());
+    /// ```
+    pub fn from_readouts(readouts: &[Modality]) -> Self {
+        let mut temperature = None;
+        let mut humidity = None;
+        let mut wind = None;
+
+        for readout in readouts {
+            match *readout {
+                Modality::Temperature(t) => temperature = temperature.or(Some(t)),
+                Modality::Humidity(Humidity::Relative(h)) => humidity = humidity.or(Some(h)),
+                Modality::Wind(w) => wind = wind.or(Some(w)),
+                _ => {}
+            }
+        }
+
+        let Some(temperature) = temperature else {
+            return Self::default();
+        };
+
+        let wind_chill = wind.and_then(|wind| wind_chill(temperature, wind));
+        match humidity {
+            Some(humidity) => Self {
+                dew_point: Some(dew_point(temperature, humidity)),
+                heat_index: heat_index(temperature, humidity),
+                humidex: humidex(temperature, humidity),
+                absolute_humidity: Some(absolute_humidity(temperature, humidity)),
+                wet_bulb: wet_bulb(temperature, humidity),
+                wind_chill,
+            },
+            None => Self {
+                wind_chill,
+                ..Self::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::derived::{
+        absolute_humidity, dew_point, heat_index, humidex, wet_bulb, wind_chill, Derived,
+    };
+    use crate::{Humidity, Modality, Pressure, Temperature, Wind};
+    use approx::assert_abs_diff_eq;
+
+    fn value(temperature: Temperature) -> f32 {
+        match temperature {
+            Temperature::Celsius(t) | Temperature::Fahrenheit(t) => t,
+        }
+    }
+
+    #[test]
+    fn dew_point_table() {
+        // Temperature (°C), relative humidity (%), dew point (°C)
+        for (t, rh, expected) in [
+            (20.0, 50.0, 9.3),
+            (25.0, 60.0, 16.7),
+            (30.0, 80.0, 26.2),
+            (10.0, 90.0, 8.4),
+            (0.0, 75.0, -3.9),
+        ] {
+            let dew_point = dew_point(Temperature::Celsius(t), rh);
+            assert!(matches!(dew_point, Temperature::Celsius(_)));
+            assert_abs_diff_eq!(value(dew_point), expected, epsilon = 0.05);
+        }
+
+        // Saturated air condensates at its own temperature
+        assert_abs_diff_eq!(
+            value(dew_point(Temperature::Celsius(15.0), 100.0)),
+            15.0,
+            epsilon = 1e-4
+        );
+
+        // Expressed in the unit of the temperature
+        assert_abs_diff_eq!(
+            value(dew_point(Temperature::Fahrenheit(68.0), 50.0)),
+            48.7,
+            epsilon = 0.05
+        );
+
+        // Perfectly dry air, as reported by clamping sensors
+        let dry = value(dew_point(Temperature::Celsius(20.0), 0.0));
+        assert!(dry.is_finite());
+        assert!(dry < -50.0);
+    }
+
+    #[test]
+    fn heat_index_table() {
+        // NWS heat index chart: temperature (°F), relative humidity (%), heat index (°F)
+        for (t, rh, expected) in [
+            (80.0, 40.0, 80.0),
+            (86.0, 90.0, 105.0),
+            (90.0, 70.0, 106.0),
+            (96.0, 65.0, 121.0),
+            (100.0, 50.0, 118.0),
+        ] {
+            let index = heat_index(Temperature::Fahrenheit(t), rh).unwrap();
+            assert_abs_diff_eq!(value(index), expected, epsilon = 0.5);
+        }
+
+        let index = heat_index(Temperature::Celsius(32.22), 70.0).unwrap();
+        assert!(matches!(index, Temperature::Celsius(_)));
+        assert_abs_diff_eq!(value(index), 41.1, epsilon = 0.1);
+
+        assert_eq!(heat_index(Temperature::Celsius(-10.0), 80.0), None);
+        assert_eq!(heat_index(Temperature::Fahrenheit(79.0), 50.0), None);
+    }
+
+    #[test]
+    fn humidex_table() {
+        // Temperature (°C), relative humidity (%) for the given dew point, humidex
+        for (t, rh, expected) in [(30.0, 40.2, 34.0), (30.0, 74.7, 42.0), (35.0, 43.0, 43.0)] {
+            assert_abs_diff_eq!(
+                humidex(Temperature::Celsius(t), rh).unwrap(),
+                expected,
+                epsilon = 0.5
+            );
+        }
+
+        // Dry air doesn't turn the index into NaN
+        let dry = humidex(Temperature::Celsius(30.0), 0.0).unwrap();
+        assert!(dry.is_finite());
+        assert_abs_diff_eq!(dry, 24.4, epsilon = 0.5);
+
+        assert_eq!(humidex(Temperature::Celsius(-5.0), 60.0), None);
+    }
+
+    #[test]
+    fn absolute_humidity_table() {
+        // Temperature (°C), relative humidity (%), absolute humidity (g/m³)
+        for (t, rh, expected) in [(20.0, 50.0, 8.64), (30.0, 80.0, 24.28), (0.0, 100.0, 4.85)] {
+            assert_abs_diff_eq!(
+                absolute_humidity(Temperature::Celsius(t), rh),
+                expected,
+                epsilon = 0.02
+            );
+        }
+    }
+
+    #[test]
+    fn wet_bulb_table() {
+        // Stull (2011): temperature (°C), relative humidity (%), wet-bulb temperature (°C)
+        for (t, rh, expected) in [(20.0, 50.0, 13.7), (30.0, 80.0, 27.1), (10.0, 30.0, 3.3)] {
+            assert_abs_diff_eq!(
+                value(wet_bulb(Temperature::Celsius(t), rh).unwrap()),
+                expected,
+                epsilon = 0.05
+            );
+        }
+
+        // Outside of the range the approximation was fitted on
+        assert_eq!(wet_bulb(Temperature::Celsius(20.0), 0.0), None);
+        assert_eq!(wet_bulb(Temperature::Celsius(-30.0), 50.0), None);
+    }
+
+    #[test]
+    fn wind_chill_table() {
+        // Environment Canada chart: temperature (°C), wind (km/h), wind chill index
+        for (t, wind, expected) in [
+            (0.0, 10.0, -3.0),
+            (-10.0, 20.0, -18.0),
+            (-20.0, 30.0, -33.0),
+            (-30.0, 50.0, -49.0),
+        ] {
+            let chill = wind_chill(Temperature::Celsius(t), Wind::Kph(wind)).unwrap();
+            assert_abs_diff_eq!(value(chill), expected, epsilon = 0.5);
+        }
+
+        // NWS chart: 10°F with a 15 mph wind feels like -7°F
+        let chill = wind_chill(Temperature::Fahrenheit(10.0), Wind::Mph(15.0)).unwrap();
+        assert!(matches!(chill, Temperature::Fahrenheit(_)));
+        assert_abs_diff_eq!(value(chill), -7.0, epsilon = 0.5);
+
+        assert_eq!(
+            wind_chill(Temperature::Celsius(15.0), Wind::Kph(30.0)),
+            None
+        );
+        assert_eq!(wind_chill(Temperature::Celsius(-5.0), Wind::Kph(3.0)), None);
+    }
+
+    #[test]
+    fn derive_from_readouts() {
+        let derived = Derived::from_readouts(&[
+            Modality::Temperature(Temperature::Celsius(-10.0)),
+            Modality::Humidity(Humidity::Relative(80.0)),
+            Modality::Wind(Wind::Kph(20.0)),
+            Modality::Pressure(Pressure::Hectopascal(1013.0)),
+        ]);
+
+        // Heat indices aren't defined below freezing
+        assert!(derived.dew_point.is_some());
+        assert!(derived.heat_index.is_none());
+        assert!(derived.humidex.is_none());
+        assert!(derived.absolute_humidity.is_some());
+        assert!(derived.wet_bulb.is_some());
+        assert!(derived.wind_chill.is_some());
+
+        let derived = Derived::from_readouts(&[
+            Modality::Temperature(Temperature::Celsius(30.0)),
+            Modality::Humidity(Humidity::Relative(0.0)),
+        ]);
+        assert!(derived.dew_point.is_some_and(|t| value(t).is_finite()));
+        assert!(derived.heat_index.is_some());
+        assert!(derived.humidex.is_some_and(f32::is_finite));
+        assert!(derived.wet_bulb.is_none());
+        assert!(derived.wind_chill.is_none());
+
+        let derived = Derived::from_readouts(&[Modality::Humidity(Humidity::Relative(80.0))]);
+        assert_eq!(derived, Derived::default());
+    }
+}
@@ -7,6 +7,29 @@ pub enum PiWeatherError {
 
     #[error("IO error: {0}")]
     Io(String),
+
+    #[error("Checksum mismatch on data received from {sensor}: expected {expected:#06x}, computed {computed:#06x}")]
+    ChecksumMismatch {
+        sensor: &'static str,
+        expected: u16,
+        computed: u16,
+    },
+
+    /// The sensor answered, but failed to carry out the operation
+    #[error("Sensor error: {0}")]
+    SensorError(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfiguration(String),
+
+    #[error("Unknown sensor driver: {0}")]
+    UnknownSensorDriver(String),
+
+    #[error("Invalid destination: {0}")]
+    InvalidDestination(String),
+
+    #[error("Sink error: {0}")]
+    SinkError(String),
 }
 //
 // #[cfg(target_os = "linux")]
@@ -1,6 +1,14 @@
+pub mod aqi;
+pub mod derived;
 pub mod errors;
 mod modality;
 mod payload;
+pub mod units;
+pub mod wind;
 
-pub use modality::{AirQuality, Modality, Particle, Temperature, Wind};
-pub use payload::Payload;
+pub use modality::{
+    AirQuality, Compass, ConcentrationUnit, Direction, Gas, Humidity, Lightning, Modality,
+    Particle, Pressure, Rain, RainRate, Temperature, Wind,
+};
+pub use payload::{Payload, Readouts, PAYLOAD_INLINE_READOUTS};
+pub use units::{Quantity, Unit, UnitSystem};
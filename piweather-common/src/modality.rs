@@ -1,13 +1,461 @@
+use crate::units::{quantity_serde, Quantity, Unit};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+/// Hectopascals in one inch of mercury (at 0°C)
+const HPA_PER_INHG: f64 = 33.863_886_666_667;
+
+/// Hectopascals in one millimeter of mercury (at 0°C)
+const HPA_PER_MMHG: f64 = 1.333_223_874_15;
+
+/// Meters per second in one knot
+const MS_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+/// Meters per second in one kilometer per hour
+const MS_PER_KPH: f64 = 1.0 / 3.6;
+
+/// Meters per second in one mile per hour
+const MS_PER_MPH: f64 = 0.44704;
+
+/// Millimeters in one inch
+const MM_PER_INCH: f64 = 25.4;
+
+/// Represent wind speed reading
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Wind {
-    Kph(u16),
-    Mph(u16),
+    MetersPerSecond(f32),
+    Knots(f32),
+    Kph(f32),
+    Mph(f32),
+}
+
+impl Wind {
+    /// Convert the wind speed to `Wind::Kph`
+    ///
+    /// ```
+    /// use piweather_common::Wind;
+    ///
+    /// assert_eq!(Wind::MetersPerSecond(10.0).to_kph(), Wind::Kph(36.0))
+    /// ```
+    pub fn to_kph(&self) -> Self {
+        Wind::Kph((self.meters_per_second() / MS_PER_KPH) as f32)
+    }
+
+    /// Convert the wind speed to `Wind::MetersPerSecond`
+    pub fn to_meters_per_second(&self) -> Self {
+        Wind::MetersPerSecond(self.meters_per_second() as f32)
+    }
+
+    fn meters_per_second(&self) -> f64 {
+        match *self {
+            Wind::MetersPerSecond(speed) => speed as f64,
+            Wind::Knots(speed) => speed as f64 * MS_PER_KNOT,
+            Wind::Kph(speed) => speed as f64 * MS_PER_KPH,
+            Wind::Mph(speed) => speed as f64 * MS_PER_MPH,
+        }
+    }
+}
+
+impl Quantity for Wind {
+    fn value(&self) -> f32 {
+        match *self {
+            Wind::MetersPerSecond(speed)
+            | Wind::Knots(speed)
+            | Wind::Kph(speed)
+            | Wind::Mph(speed) => speed,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        match self {
+            Wind::MetersPerSecond(_) => Unit::MetersPerSecond,
+            Wind::Knots(_) => Unit::Knots,
+            Wind::Kph(_) => Unit::Kph,
+            Wind::Mph(_) => Unit::Mph,
+        }
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::MetersPerSecond => Some(Wind::MetersPerSecond(value)),
+            Unit::Knots => Some(Wind::Knots(value)),
+            Unit::Kph => Some(Wind::Kph(value)),
+            Unit::Mph => Some(Wind::Mph(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        if unit == self.unit() {
+            return Some(*self);
+        }
+
+        let speed = self.meters_per_second();
+        let value = match unit {
+            Unit::MetersPerSecond => speed,
+            Unit::Knots => speed / MS_PER_KNOT,
+            Unit::Kph => speed / MS_PER_KPH,
+            Unit::Mph => speed / MS_PER_MPH,
+            _ => return None,
+        };
+        Self::with_unit(value as f32, unit)
+    }
+}
+
+quantity_serde!(Wind, "wind speed");
+
+/// Point of the 16-point compass rose
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub enum Compass {
+    N,
+    NNE,
+    NE,
+    ENE,
+    E,
+    ESE,
+    SE,
+    SSE,
+    S,
+    SSW,
+    SW,
+    WSW,
+    W,
+    WNW,
+    NW,
+    NNW,
+}
+
+impl Compass {
+    /// Points of the compass rose, clockwise from the north
+    pub const POINTS: [Compass; 16] = [
+        Compass::N,
+        Compass::NNE,
+        Compass::NE,
+        Compass::ENE,
+        Compass::E,
+        Compass::ESE,
+        Compass::SE,
+        Compass::SSE,
+        Compass::S,
+        Compass::SSW,
+        Compass::SW,
+        Compass::WSW,
+        Compass::W,
+        Compass::WNW,
+        Compass::NW,
+        Compass::NNW,
+    ];
+
+    /// Point covering the `degrees` bearing, each point spanning 22.5°
+    ///
+    /// ```
+    /// use piweather_common::Compass;
+    ///
+    /// assert_eq!(Compass::from_degrees(350.0), Compass::N);
+    /// assert_eq!(Compass::from_degrees(230.0), Compass::SW);
+    /// ```
+    pub fn from_degrees(degrees: f32) -> Self {
+        let sector = (degrees.rem_euclid(360.0) / 22.5).round() as usize;
+        Self::POINTS[sector % Self::POINTS.len()]
+    }
+
+    /// Bearing at the center of the point
+    pub fn degrees(&self) -> f32 {
+        *self as usize as f32 * 22.5
+    }
+
+    /// Abbreviation of the point, as printed on a compass rose
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compass::N => "N",
+            Compass::NNE => "NNE",
+            Compass::NE => "NE",
+            Compass::ENE => "ENE",
+            Compass::E => "E",
+            Compass::ESE => "ESE",
+            Compass::SE => "SE",
+            Compass::SSE => "SSE",
+            Compass::S => "S",
+            Compass::SSW => "SSW",
+            Compass::SW => "SW",
+            Compass::WSW => "WSW",
+            Compass::W => "W",
+            Compass::WNW => "WNW",
+            Compass::NW => "NW",
+            Compass::NNW => "NNW",
+        }
+    }
+}
+
+/// Represent the direction the wind blows from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    /// Clockwise from the true north, 90° being a wind blowing from the east
+    Degrees(f32),
+}
+
+impl Direction {
+    /// Bearing normalized within [0, 360)
+    ///
+    /// ```
+    /// use piweather_common::Direction;
+    ///
+    /// assert_eq!(Direction::Degrees(-90.0).degrees(), 270.0);
+    /// ```
+    pub fn degrees(&self) -> f32 {
+        match *self {
+            Direction::Degrees(degrees) => degrees.rem_euclid(360.0),
+        }
+    }
+
+    /// Point of the 16-point compass rose the wind blows from
+    pub fn compass(&self) -> Compass {
+        Compass::from_degrees(self.degrees())
+    }
+}
+
+impl Quantity for Direction {
+    fn value(&self) -> f32 {
+        match *self {
+            Direction::Degrees(degrees) => degrees,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Degrees
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::Degrees => Some(Direction::Degrees(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        Self::with_unit(self.value(), unit)
+    }
+}
+
+quantity_serde!(Direction, "wind direction");
+
+/// Represent atmospheric pressure reading
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pressure {
+    Hectopascal(f32),
+    InchesOfMercury(f32),
+    MillimetersOfMercury(f32),
+}
+
+impl Pressure {
+    /// Convert the pressure to `Pressure::Hectopascal`
+    ///
+    /// ```
+    /// use piweather_common::Pressure;
+    ///
+    /// let Pressure::Hectopascal(hpa) = Pressure::InchesOfMercury(29.92).to_hectopascal() else {
+    ///     unreachable!()
+    /// };
+    /// assert_eq!(hpa.round(), 1013.0)
+    /// ```
+    pub fn to_hectopascal(&self) -> Self {
+        Pressure::Hectopascal(self.hectopascal() as f32)
+    }
+
+    fn hectopascal(&self) -> f64 {
+        match *self {
+            Pressure::Hectopascal(pressure) => pressure as f64,
+            Pressure::InchesOfMercury(pressure) => pressure as f64 * HPA_PER_INHG,
+            Pressure::MillimetersOfMercury(pressure) => pressure as f64 * HPA_PER_MMHG,
+        }
+    }
+}
+
+impl Quantity for Pressure {
+    fn value(&self) -> f32 {
+        match *self {
+            Pressure::Hectopascal(pressure)
+            | Pressure::InchesOfMercury(pressure)
+            | Pressure::MillimetersOfMercury(pressure) => pressure,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        match self {
+            Pressure::Hectopascal(_) => Unit::Hectopascal,
+            Pressure::InchesOfMercury(_) => Unit::InchesOfMercury,
+            Pressure::MillimetersOfMercury(_) => Unit::MillimetersOfMercury,
+        }
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::Hectopascal => Some(Pressure::Hectopascal(value)),
+            Unit::InchesOfMercury => Some(Pressure::InchesOfMercury(value)),
+            Unit::MillimetersOfMercury => Some(Pressure::MillimetersOfMercury(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        if unit == self.unit() {
+            return Some(*self);
+        }
+
+        let pressure = self.hectopascal();
+        let value = match unit {
+            Unit::Hectopascal => pressure,
+            Unit::InchesOfMercury => pressure / HPA_PER_INHG,
+            Unit::MillimetersOfMercury => pressure / HPA_PER_MMHG,
+            _ => return None,
+        };
+        Self::with_unit(value as f32, unit)
+    }
+}
+
+quantity_serde!(Pressure, "pressure");
+
+/// Represent humidity reading
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Humidity {
+    /// Relative humidity, in percent
+    Relative(f32),
+}
+
+impl Quantity for Humidity {
+    fn value(&self) -> f32 {
+        match *self {
+            Humidity::Relative(humidity) => humidity,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Percent
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::Percent => Some(Humidity::Relative(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        Self::with_unit(self.value(), unit)
+    }
+}
+
+quantity_serde!(Humidity, "humidity");
+
+/// Represent rain accumulated since the sensor started counting
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rain {
+    Millimeters(f32),
+    Inches(f32),
+}
+
+impl Rain {
+    fn millimeters(&self) -> f64 {
+        match *self {
+            Rain::Millimeters(rain) => rain as f64,
+            Rain::Inches(rain) => rain as f64 * MM_PER_INCH,
+        }
+    }
+}
+
+impl Quantity for Rain {
+    fn value(&self) -> f32 {
+        match *self {
+            Rain::Millimeters(rain) | Rain::Inches(rain) => rain,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        match self {
+            Rain::Millimeters(_) => Unit::Millimeters,
+            Rain::Inches(_) => Unit::Inches,
+        }
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::Millimeters => Some(Rain::Millimeters(value)),
+            Unit::Inches => Some(Rain::Inches(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        if unit == self.unit() {
+            return Some(*self);
+        }
+
+        let value = match unit {
+            Unit::Millimeters => self.millimeters(),
+            Unit::Inches => self.millimeters() / MM_PER_INCH,
+            _ => return None,
+        };
+        Self::with_unit(value as f32, unit)
+    }
+}
+
+quantity_serde!(Rain, "rain");
+
+/// Represent rain intensity reading
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RainRate {
+    MillimetersPerHour(f32),
+    InchesPerHour(f32),
+}
+
+impl RainRate {
+    fn millimeters_per_hour(&self) -> f64 {
+        match *self {
+            RainRate::MillimetersPerHour(rate) => rate as f64,
+            RainRate::InchesPerHour(rate) => rate as f64 * MM_PER_INCH,
+        }
+    }
 }
 
+impl Quantity for RainRate {
+    fn value(&self) -> f32 {
+        match *self {
+            RainRate::MillimetersPerHour(rate) | RainRate::InchesPerHour(rate) => rate,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        match self {
+            RainRate::MillimetersPerHour(_) => Unit::MillimetersPerHour,
+            RainRate::InchesPerHour(_) => Unit::InchesPerHour,
+        }
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::MillimetersPerHour => Some(RainRate::MillimetersPerHour(value)),
+            Unit::InchesPerHour => Some(RainRate::InchesPerHour(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        if unit == self.unit() {
+            return Some(*self);
+        }
+
+        let value = match unit {
+            Unit::MillimetersPerHour => self.millimeters_per_hour(),
+            Unit::InchesPerHour => self.millimeters_per_hour() / MM_PER_INCH,
+            _ => return None,
+        };
+        Self::with_unit(value as f32, unit)
+    }
+}
+
+quantity_serde!(RainRate, "rain rate");
+
 /// Represent temperature reading
-#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Temperature {
     Celsius(f32),
     Fahrenheit(f32),
@@ -49,7 +497,40 @@ impl Temperature {
     }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+impl Quantity for Temperature {
+    fn value(&self) -> f32 {
+        match *self {
+            Temperature::Celsius(temp) | Temperature::Fahrenheit(temp) => temp,
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        match self {
+            Temperature::Celsius(_) => Unit::Celsius,
+            Temperature::Fahrenheit(_) => Unit::Fahrenheit,
+        }
+    }
+
+    fn with_unit(value: f32, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::Celsius => Some(Temperature::Celsius(value)),
+            Unit::Fahrenheit => Some(Temperature::Fahrenheit(value)),
+            _ => None,
+        }
+    }
+
+    fn to_unit(&self, unit: Unit) -> Option<Self> {
+        match unit {
+            Unit::Celsius => Some(self.to_celsius()),
+            Unit::Fahrenheit => Some(self.to_fahrenheit()),
+            _ => None,
+        }
+    }
+}
+
+quantity_serde!(Temperature, "temperature");
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Deserialize, Serialize)]
 pub enum Particle {
     PM0_3,
     PM0_5,
@@ -59,27 +540,113 @@ pub enum Particle {
     PM10_0,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+impl Particle {
+    /// Identifier of the particle size, suitable for topics, labels or field names
+    ///
+    /// ```
+    /// use piweather_common::Particle;
+    ///
+    /// assert_eq!(Particle::PM2_5.as_str(), "pm2_5");
+    /// ```
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Particle::PM0_3 => "pm0_3",
+            Particle::PM0_5 => "pm0_5",
+            Particle::PM1_0 => "pm1_0",
+            Particle::PM2_5 => "pm2_5",
+            Particle::PM5_0 => "pm5_0",
+            Particle::PM10_0 => "pm10_0",
+        }
+    }
+}
+
+/// Reference environment a particulate matter concentration is reported for
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub enum ConcentrationUnit {
+    /// Standard particle (CF=1), as calibrated in the factory environment
+    Standard,
+
+    /// Atmospheric environment, the value to use for outdoor air quality
+    Environmental,
+}
+
+impl ConcentrationUnit {
+    /// Identifier of the unit, suitable for topics, labels or field names
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConcentrationUnit::Standard => "standard",
+            ConcentrationUnit::Environmental => "environmental",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
 pub enum AirQuality {
     // Expressed in μg/m3
-    Concentration(Particle, u16),
+    Concentration(Particle, ConcentrationUnit, u16),
 
     // Expressed in number of particles in 0.1L of air
     Count(Particle, u16),
 }
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+/// Gas reported through a relative index rather than a concentration
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub enum Gas {
+    /// Volatile organic compounds, 100 being the average of the past 24 hours
+    Voc,
+
+    /// Nitrogen oxides, 1 being the average of the past 24 hours
+    Nox,
+}
+
+impl Gas {
+    /// Identifier of the gas, suitable for topics, labels or field names
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Gas::Voc => "voc",
+            Gas::Nox => "nox",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Lightning {
+    // Estimated distance to the storm front, in km
+    Distance(f32),
+
+    // Number of strikes detected since the sensor started counting
+    Strikes(u32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
 pub enum Modality {
-    Humidity(f32),
-    Pressure(u16),
+    Humidity(Humidity),
+    Pressure(Pressure),
     Temperature(Temperature),
+
+    /// Sustained wind speed
     Wind(Wind),
+
+    /// Peak wind speed
+    WindGust(Wind),
+    WindDirection(Direction),
     AirQuality(AirQuality),
+    Rain(Rain),
+    RainRate(RainRate),
+
+    /// Expressed in lux
+    Illuminance(f32),
+    UvIndex(f32),
+
+    /// Carbon dioxide, expressed in ppm
+    CarbonDioxide(u16),
+    GasIndex(Gas, u16),
+    Lightning(Lightning),
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::modality::Temperature;
+    use crate::modality::{Compass, Direction, Temperature};
 
     #[test]
     fn temperature_celsius_to_celsius() {
@@ -138,4 +705,25 @@ mod tests {
             Temperature::Fahrenheit(49.82)
         );
     }
+
+    #[test]
+    fn direction_to_compass() {
+        for (degrees, point) in [
+            (0.0, Compass::N),
+            (11.24, Compass::N),
+            (11.26, Compass::NNE),
+            (90.0, Compass::E),
+            (200.0, Compass::SSW),
+            (348.7, Compass::NNW),
+            (348.8, Compass::N),
+            (720.0, Compass::N),
+            (-22.5, Compass::NNW),
+        ] {
+            assert_eq!(Direction::Degrees(degrees).compass(), point, "{}°", degrees);
+        }
+
+        assert!(Compass::POINTS
+            .iter()
+            .all(|point| Compass::from_degrees(point.degrees()) == *point));
+    }
 }
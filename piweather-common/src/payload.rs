@@ -1,21 +1,185 @@
 use crate::Modality;
-use std::time::Instant;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::sync::Arc;
 
-#[derive(Debug)]
-pub struct Payload<const N: usize> {
-    when: Instant,
-    readouts: [Modality; N],
-}
+/// Number of readouts stored inline within a `Payload` before spilling to the heap.
+/// It covers the common temperature / humidity / pressure sensors without any allocation.
+pub const PAYLOAD_INLINE_READOUTS: usize = 4;
 
-impl<const N: usize> Payload<N> {
-    pub fn new(when: Instant, readouts: [Modality; N]) -> Self {
-        Self { when, readouts }
-    }
+pub type Readouts = SmallVec<[Modality; PAYLOAD_INLINE_READOUTS]>;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Payload {
+    when: DateTime<Utc>,
 
-    pub fn now(readouts: [Modality; N]) -> Self {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sensor: Option<Arc<str>>,
+
+    readouts: Readouts,
+}
+
+impl Payload {
+    pub fn new<R>(when: DateTime<Utc>, readouts: R) -> Self
+    where
+        R: IntoIterator<Item = Modality>,
+    {
         Self {
-            when: Instant::now(),
-            readouts,
+            when,
+            sequence: None,
+            sensor: None,
+            readouts: readouts.into_iter().collect(),
         }
     }
+
+    pub fn now<R>(readouts: R) -> Self
+    where
+        R: IntoIterator<Item = Modality>,
+    {
+        Self::new(Utc::now(), readouts)
+    }
+
+    /// Attach a monotonic sequence number to the payload, allowing the receiver to detect gaps
+    ///
+    /// ```
+    /// use piweather_common::{Humidity, Modality, Payload};
+    ///
+    /// let payload = Payload::now([Modality::Humidity(Humidity::Relative(45.0))]).with_sequence(7);
+    /// assert_eq!(payload.sequence(), Some(7));
+    /// ```
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    /// Identify the sensor which acquired the readouts
+    ///
+    /// ```
+    /// use piweather_common::{Humidity, Modality, Payload};
+    ///
+    /// let payload = Payload::now([Modality::Humidity(Humidity::Relative(45.0))]).with_sensor("am2315");
+    /// assert_eq!(payload.sensor(), Some("am2315"));
+    /// ```
+    pub fn with_sensor<S: Into<Arc<str>>>(mut self, sensor: S) -> Self {
+        self.sensor = Some(sensor.into());
+        self
+    }
+
+    /// UTC time at which the readouts were acquired
+    #[inline]
+    pub fn when(&self) -> DateTime<Utc> {
+        self.when
+    }
+
+    /// Sequence number of the payload, if any
+    #[inline]
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    /// Name of the sensor which acquired the readouts, if any
+    #[inline]
+    pub fn sensor(&self) -> Option<&str> {
+        self.sensor.as_deref()
+    }
+
+    /// Modalities captured by the sensor
+    #[inline]
+    pub fn readouts(&self) -> &[Modality] {
+        &self.readouts
+    }
+
+    /// Mutable access to the modalities, i.e. to apply corrections
+    #[inline]
+    pub fn readouts_mut(&mut self) -> &mut [Modality] {
+        &mut self.readouts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AirQuality, Humidity, Modality, Particle, Payload, Pressure, Temperature};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn payload_serialize() {
+        let when = Utc.with_ymd_and_hms(2024, 7, 14, 12, 30, 0).unwrap();
+        let payload = Payload::new(
+            when,
+            [
+                Modality::Temperature(Temperature::Celsius(27.7)),
+                Modality::Humidity(Humidity::Relative(82.5)),
+            ],
+        );
+
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            r#"{"when":"2024-07-14T12:30:00Z","readouts":[{"Temperature":{"value":27.7,"unit":"°C"}},{"Humidity":{"value":82.5,"unit":"%"}}]}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&payload.with_sequence(3).with_sensor("am2315")).unwrap(),
+            r#"{"when":"2024-07-14T12:30:00Z","sequence":3,"sensor":"am2315","readouts":[{"Temperature":{"value":27.7,"unit":"°C"}},{"Humidity":{"value":82.5,"unit":"%"}}]}"#
+        );
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let payload = Payload::now([
+            Modality::Temperature(Temperature::Fahrenheit(-4.0)),
+            Modality::Pressure(Pressure::Hectopascal(1013.0)),
+            Modality::AirQuality(AirQuality::Count(Particle::PM0_3, 1200)),
+        ])
+        .with_sequence(u64::MAX)
+        .with_sensor("bme280");
+
+        let encoded = serde_json::to_string(&payload).unwrap();
+        let decoded: Payload = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, payload);
+    }
+
+    #[test]
+    fn payload_round_trip_without_sequence() {
+        let payload = Payload::now([Modality::Humidity(Humidity::Relative(45.0))]);
+
+        let encoded = serde_json::to_string(&payload).unwrap();
+        let decoded: Payload = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded.sequence(), None);
+        assert_eq!(decoded, payload);
+    }
+
+    #[test]
+    fn payload_inline_readouts() {
+        let payload = Payload::now([
+            Modality::Temperature(Temperature::Celsius(27.7)),
+            Modality::Humidity(Humidity::Relative(82.5)),
+        ]);
+        assert!(!payload.readouts.spilled());
+        assert_eq!(payload.readouts().len(), 2);
+    }
+
+    #[test]
+    fn payload_spilled_readouts() {
+        let counts = [
+            Particle::PM0_3,
+            Particle::PM0_5,
+            Particle::PM1_0,
+            Particle::PM2_5,
+            Particle::PM5_0,
+            Particle::PM10_0,
+        ]
+        .map(|particle| Modality::AirQuality(AirQuality::Count(particle, 10)));
+
+        let payload = Payload::now(counts);
+        assert!(payload.readouts.spilled());
+        assert_eq!(payload.readouts(), &counts);
+
+        let encoded = serde_json::to_string(&payload).unwrap();
+        let decoded: Payload = serde_json::from_str(&encoded).unwrap();
+        assert_eq!(decoded, payload);
+    }
 }
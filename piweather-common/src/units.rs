@@ -0,0 +1,368 @@
+use crate::{Humidity, Modality};
+use serde::{Deserialize, Serialize};
+
+/// Unit of measure a quantity is expressed in, serialized as its symbol
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq, Deserialize, Serialize)]
+pub enum Unit {
+    #[serde(rename = "°C")]
+    Celsius,
+
+    #[serde(rename = "°F")]
+    Fahrenheit,
+
+    #[serde(rename = "hPa")]
+    Hectopascal,
+
+    #[serde(rename = "inHg")]
+    InchesOfMercury,
+
+    #[serde(rename = "mmHg")]
+    MillimetersOfMercury,
+
+    #[serde(rename = "m/s")]
+    MetersPerSecond,
+
+    #[serde(rename = "kn")]
+    Knots,
+
+    #[serde(rename = "km/h")]
+    Kph,
+
+    #[serde(rename = "mph")]
+    Mph,
+
+    /// Relative humidity
+    #[serde(rename = "%")]
+    Percent,
+
+    /// Angle, clockwise from the true north
+    #[serde(rename = "°")]
+    Degrees,
+
+    #[serde(rename = "mm")]
+    Millimeters,
+
+    #[serde(rename = "in")]
+    Inches,
+
+    #[serde(rename = "mm/h")]
+    MillimetersPerHour,
+
+    #[serde(rename = "in/h")]
+    InchesPerHour,
+}
+
+impl Unit {
+    /// Symbol of the unit, as displayed next to a value
+    ///
+    /// ```
+    /// use piweather_common::Unit;
+    ///
+    /// assert_eq!(Unit::InchesOfMercury.symbol(), "inHg");
+    /// ```
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Hectopascal => "hPa",
+            Unit::InchesOfMercury => "inHg",
+            Unit::MillimetersOfMercury => "mmHg",
+            Unit::MetersPerSecond => "m/s",
+            Unit::Knots => "kn",
+            Unit::Kph => "km/h",
+            Unit::Mph => "mph",
+            Unit::Percent => "%",
+            Unit::Degrees => "°",
+            Unit::Millimeters => "mm",
+            Unit::Inches => "in",
+            Unit::MillimetersPerHour => "mm/h",
+            Unit::InchesPerHour => "in/h",
+        }
+    }
+
+    /// Identifier of the unit, suitable for topics, labels or field names
+    ///
+    /// ```
+    /// use piweather_common::Unit;
+    ///
+    /// assert_eq!(Unit::MetersPerSecond.as_str(), "ms");
+    /// ```
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Unit::Celsius => "celsius",
+            Unit::Fahrenheit => "fahrenheit",
+            Unit::Hectopascal => "hpa",
+            Unit::InchesOfMercury => "inhg",
+            Unit::MillimetersOfMercury => "mmhg",
+            Unit::MetersPerSecond => "ms",
+            Unit::Knots => "knots",
+            Unit::Kph => "kph",
+            Unit::Mph => "mph",
+            Unit::Percent => "percent",
+            Unit::Degrees => "degrees",
+            Unit::Millimeters => "mm",
+            Unit::Inches => "in",
+            Unit::MillimetersPerHour => "mm_h",
+            Unit::InchesPerHour => "in_h",
+        }
+    }
+}
+
+/// Physical quantity expressed in one of the units measuring it
+pub trait Quantity: Copy + Sized {
+    /// Value, expressed in `unit()`
+    fn value(&self) -> f32;
+
+    fn unit(&self) -> Unit;
+
+    /// Quantity of `value` expressed in `unit`, `None` if `unit` doesn't measure this quantity
+    fn with_unit(value: f32, unit: Unit) -> Option<Self>;
+
+    /// Same quantity expressed in `unit`, `None` if `unit` doesn't measure this quantity
+    fn to_unit(&self, unit: Unit) -> Option<Self>;
+}
+
+/// Serialized form of a quantity, recording the unit along the value
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Measure {
+    pub value: f32,
+    pub unit: Unit,
+}
+
+/// Implement `Serialize` and `Deserialize` for a `Quantity` as `{"value": 21.5, "unit": "°C"}`
+macro_rules! quantity_serde {
+    ($quantity:ty, $name:literal) => {
+        impl serde::Serialize for $quantity {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use $crate::units::{Measure, Quantity};
+
+                Measure {
+                    value: self.value(),
+                    unit: self.unit(),
+                }
+                .serialize(serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $quantity {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                use $crate::units::{Measure, Quantity};
+
+                let measure = Measure::deserialize(deserializer)?;
+                Self::with_unit(measure.value, measure.unit).ok_or_else(|| {
+                    serde::de::Error::custom(format!(
+                        "{} is not a unit of {}",
+                        measure.unit.symbol(),
+                        $name
+                    ))
+                })
+            }
+        }
+    };
+}
+
+pub(crate) use quantity_serde;
+
+/// Set of units readouts are presented in
+#[derive(Debug, Copy, Clone, Default, Eq, Hash, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    /// °C, hPa, km/h and mm
+    #[default]
+    Metric,
+
+    /// °F, inHg, mph and in
+    Imperial,
+}
+
+impl UnitSystem {
+    pub fn temperature(&self) -> Unit {
+        match self {
+            UnitSystem::Metric => Unit::Celsius,
+            UnitSystem::Imperial => Unit::Fahrenheit,
+        }
+    }
+
+    pub fn pressure(&self) -> Unit {
+        match self {
+            UnitSystem::Metric => Unit::Hectopascal,
+            UnitSystem::Imperial => Unit::InchesOfMercury,
+        }
+    }
+
+    pub fn wind_speed(&self) -> Unit {
+        match self {
+            UnitSystem::Metric => Unit::Kph,
+            UnitSystem::Imperial => Unit::Mph,
+        }
+    }
+
+    pub fn rain(&self) -> Unit {
+        match self {
+            UnitSystem::Metric => Unit::Millimeters,
+            UnitSystem::Imperial => Unit::Inches,
+        }
+    }
+
+    pub fn rain_rate(&self) -> Unit {
+        match self {
+            UnitSystem::Metric => Unit::MillimetersPerHour,
+            UnitSystem::Imperial => Unit::InchesPerHour,
+        }
+    }
+
+    /// Express `modality` in the units of the system, unitless modalities being left untouched
+    ///
+    /// ```
+    /// use piweather_common::{Modality, Temperature, UnitSystem};
+    ///
+    /// assert_eq!(
+    ///     UnitSystem::Imperial.present(Modality::Temperature(Temperature::Celsius(0.0))),
+    ///     Modality::Temperature(Temperature::Fahrenheit(32.0))
+    /// );
+    /// ```
+    pub fn present(&self, modality: Modality) -> Modality {
+        // Every unit below measures the matching quantity
+        match modality {
+            Modality::Temperature(t) => {
+                Modality::Temperature(t.to_unit(self.temperature()).unwrap_or(t))
+            }
+            Modality::Pressure(p) => Modality::Pressure(p.to_unit(self.pressure()).unwrap_or(p)),
+            Modality::Wind(w) => Modality::Wind(w.to_unit(self.wind_speed()).unwrap_or(w)),
+            Modality::WindGust(w) => Modality::WindGust(w.to_unit(self.wind_speed()).unwrap_or(w)),
+            Modality::Rain(r) => Modality::Rain(r.to_unit(self.rain()).unwrap_or(r)),
+            Modality::RainRate(r) => Modality::RainRate(r.to_unit(self.rain_rate()).unwrap_or(r)),
+            Modality::Humidity(Humidity::Relative(_))
+            | Modality::WindDirection(_)
+            | Modality::AirQuality(_)
+            | Modality::Illuminance(_)
+            | Modality::UvIndex(_)
+            | Modality::CarbonDioxide(_)
+            | Modality::GasIndex(_, _)
+            | Modality::Lightning(_) => modality,
+        }
+    }
+}
+
+impl std::str::FromStr for UnitSystem {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "metric" => Ok(UnitSystem::Metric),
+            "imperial" => Ok(UnitSystem::Imperial),
+            _ => Err(format!("unknown unit system \"{}\"", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::units::{Quantity, Unit, UnitSystem};
+    use crate::{Humidity, Modality, Pressure, Rain, RainRate, Temperature, Wind};
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn serialize_unit() {
+        assert_eq!(
+            serde_json::to_string(&Temperature::Celsius(21.5)).unwrap(),
+            r#"{"value":21.5,"unit":"°C"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Pressure::InchesOfMercury(29.92)).unwrap(),
+            r#"{"value":29.92,"unit":"inHg"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Humidity::Relative(45.0)).unwrap(),
+            r#"{"value":45.0,"unit":"%"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Wind>(r#"{"value":3.5,"unit":"m/s"}"#).unwrap(),
+            Wind::MetersPerSecond(3.5)
+        );
+
+        // Units have to match the quantity
+        assert!(serde_json::from_str::<Wind>(r#"{"value":3.5,"unit":"hPa"}"#).is_err());
+        assert!(serde_json::from_str::<Wind>(r#"{"value":3.5,"unit":"furlong"}"#).is_err());
+    }
+
+    #[test]
+    fn lossless_conversions() {
+        let pressure = Pressure::Hectopascal(1013.25);
+        for unit in [Unit::InchesOfMercury, Unit::MillimetersOfMercury] {
+            let converted = pressure.to_unit(unit).unwrap();
+            assert_eq!(converted.unit(), unit);
+            assert_relative_eq!(
+                converted.to_unit(Unit::Hectopascal).unwrap().value(),
+                1013.25,
+                max_relative = 1e-6
+            );
+        }
+
+        let wind = Wind::Kph(36.0);
+        for unit in [Unit::MetersPerSecond, Unit::Knots, Unit::Mph] {
+            let converted = wind.to_unit(unit).unwrap();
+            assert_relative_eq!(
+                converted.to_unit(Unit::Kph).unwrap().value(),
+                36.0,
+                max_relative = 1e-6
+            );
+        }
+
+        assert_eq!(
+            RainRate::InchesPerHour(0.5).to_unit(Unit::MillimetersPerHour),
+            Some(RainRate::MillimetersPerHour(12.7))
+        );
+        assert_eq!(Temperature::Celsius(20.0).to_unit(Unit::Hectopascal), None);
+    }
+
+    #[test]
+    fn present_in_unit_system() {
+        let readouts = [
+            Modality::Temperature(Temperature::Fahrenheit(50.0)),
+            Modality::Pressure(Pressure::InchesOfMercury(29.92)),
+            Modality::Wind(Wind::Knots(10.0)),
+            Modality::Humidity(Humidity::Relative(45.0)),
+            Modality::Rain(Rain::Millimeters(12.7)),
+        ];
+
+        let units = |system: UnitSystem| {
+            readouts.map(|readout| match system.present(readout) {
+                Modality::Temperature(t) => t.unit(),
+                Modality::Pressure(p) => p.unit(),
+                Modality::Wind(w) => w.unit(),
+                Modality::Humidity(h) => h.unit(),
+                Modality::Rain(r) => r.unit(),
+                _ => unreachable!(),
+            })
+        };
+
+        assert_eq!(
+            units(UnitSystem::Metric),
+            [
+                Unit::Celsius,
+                Unit::Hectopascal,
+                Unit::Kph,
+                Unit::Percent,
+                Unit::Millimeters
+            ]
+        );
+        assert_eq!(
+            units(UnitSystem::Imperial),
+            [
+                Unit::Fahrenheit,
+                Unit::InchesOfMercury,
+                Unit::Mph,
+                Unit::Percent,
+                Unit::Inches
+            ]
+        );
+
+        match UnitSystem::Metric.present(readouts[1]) {
+            Modality::Pressure(Pressure::Hectopascal(p)) => {
+                assert_relative_eq!(p, 1013.21, max_relative = 1e-4)
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
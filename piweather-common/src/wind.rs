@@ -0,0 +1,261 @@
+//! Sustained wind computed from a stream of instantaneous samples.
+//!
+//! Speeds are averaged as scalars while directions are vector-averaged, each sample weighted by
+//! its speed, so that 350° and 10° average to the north rather than the south.
+
+use crate::{Direction, Modality, Payload, Quantity, Unit, Wind};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::VecDeque;
+
+/// Below this resultant speed, in m/s, directions cancel out and the wind has no prevailing one
+const CALM_RESULTANT_MS: f32 = 0.01;
+
+/// Averaging periods used to report the sustained wind
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
+pub enum Window {
+    /// Used by surface observations in North America
+    TwoMinutes,
+
+    /// Recommended by the World Meteorological Organization
+    TenMinutes,
+}
+
+impl Window {
+    pub fn duration(&self) -> TimeDelta {
+        match self {
+            Window::TwoMinutes => TimeDelta::minutes(2),
+            Window::TenMinutes => TimeDelta::minutes(10),
+        }
+    }
+}
+
+/// Wind averaged over a window, speeds being expressed in the unit of the latest sample
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SustainedWind {
+    pub speed: Wind,
+
+    /// `None` when no sample carried a direction or when the wind was calm
+    pub direction: Option<Direction>,
+
+    /// Highest gust, or instantaneous speed if none was reported, over the window
+    pub gust: Wind,
+}
+
+impl SustainedWind {
+    /// Modalities reporting the sustained wind
+    pub fn readouts(&self) -> impl Iterator<Item = Modality> {
+        [
+            Some(Modality::Wind(self.speed)),
+            Some(Modality::WindGust(self.gust)),
+            self.direction.map(Modality::WindDirection),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Sample {
+    when: DateTime<Utc>,
+    speed: f32,
+    gust: f32,
+    direction: Option<f32>,
+}
+
+/// Rolling history of the wind samples, over the longest averaging window
+#[derive(Debug, Default, Clone)]
+pub struct WindHistory {
+    samples: VecDeque<Sample>,
+    unit: Option<Unit>,
+}
+
+impl WindHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the wind measured at `when`
+    pub fn push(
+        &mut self,
+        when: DateTime<Utc>,
+        speed: Wind,
+        direction: Option<Direction>,
+        gust: Option<Wind>,
+    ) {
+        let meters_per_second = |wind: Wind| wind.to_meters_per_second().value();
+
+        self.unit = Some(speed.unit());
+        self.samples.push_back(Sample {
+            when,
+            speed: meters_per_second(speed),
+            gust: meters_per_second(gust.unwrap_or(speed)),
+            direction: direction.map(|direction| direction.degrees()),
+        });
+
+        // Keep what the longest window needs, relative to the most recent sample
+        if let Some(latest) = self.samples.iter().map(|sample| sample.when).max() {
+            let horizon = latest - Window::TenMinutes.duration();
+            while self
+                .samples
+                .front()
+                .is_some_and(|sample| sample.when <= horizon)
+            {
+                self.samples.pop_front();
+            }
+        }
+    }
+
+    /// Record the wind readouts of `payload`, ignored if it doesn't report the wind speed
+    pub fn record(&mut self, payload: &Payload) {
+        let mut speed = None;
+        let mut direction = None;
+        let mut gust = None;
+
+        for readout in payload.readouts() {
+            match *readout {
+                Modality::Wind(w) => speed = speed.or(Some(w)),
+                Modality::WindGust(w) => gust = gust.or(Some(w)),
+                Modality::WindDirection(d) => direction = direction.or(Some(d)),
+                _ => {}
+            }
+        }
+
+        if let Some(speed) = speed {
+            self.push(payload.when(), speed, direction, gust);
+        }
+    }
+
+    /// Wind over the `window` preceding `now`, `None` without any sample in it
+    pub fn sustained(&self, window: Window, now: DateTime<Utc>) -> Option<SustainedWind> {
+        let since = now - window.duration();
+        let samples = self
+            .samples
+            .iter()
+            .filter(|sample| sample.when > since && sample.when <= now);
+
+        let (mut speed, mut gust, mut count) = (0.0, 0.0f32, 0);
+        let (mut east, mut north) = (0.0, 0.0);
+        for sample in samples {
+            speed += sample.speed;
+            gust = gust.max(sample.gust);
+            count += 1;
+
+            if let Some(direction) = sample.direction {
+                let (sin, cos) = direction.to_radians().sin_cos();
+                east += sample.speed * sin;
+                north += sample.speed * cos;
+            }
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        let resultant = (east * east + north * north).sqrt() / count as f32;
+        let direction = (resultant >= CALM_RESULTANT_MS)
+            .then(|| Direction::Degrees(f32::atan2(east, north).to_degrees().rem_euclid(360.0)));
+
+        // Speeds are stored in m/s, express them back in the unit of the sensor
+        let unit = self.unit.unwrap_or(Unit::MetersPerSecond);
+        let express = |value: f32| {
+            let wind = Wind::MetersPerSecond(value);
+            wind.to_unit(unit).unwrap_or(wind)
+        };
+
+        Some(SustainedWind {
+            speed: express(speed / count as f32),
+            direction,
+            gust: express(gust),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wind::{WindHistory, Window};
+    use crate::{Compass, Direction, Modality, Payload, Quantity, Wind};
+    use approx::assert_abs_diff_eq;
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    #[test]
+    fn vector_average_direction() {
+        let start = Utc.with_ymd_and_hms(2024, 7, 14, 12, 0, 0).unwrap();
+        let mut history = WindHistory::new();
+
+        // Oscillating around the north, a scalar mean would point south
+        for (seconds, degrees) in [(0, 350.0), (10, 10.0), (20, 340.0), (30, 20.0)] {
+            history.push(
+                start + TimeDelta::seconds(seconds),
+                Wind::MetersPerSecond(5.0),
+                Some(Direction::Degrees(degrees)),
+                None,
+            );
+        }
+
+        let sustained = history
+            .sustained(Window::TwoMinutes, start + TimeDelta::seconds(30))
+            .unwrap();
+        let direction = sustained.direction.unwrap();
+        assert!(direction.degrees() < 0.01 || direction.degrees() > 359.99);
+        assert_eq!(direction.compass(), Compass::N);
+        assert_abs_diff_eq!(sustained.speed.value(), 5.0, epsilon = 1e-4);
+
+        // Opposite winds of the same strength cancel out
+        let mut history = WindHistory::new();
+        history.push(start, Wind::Kph(10.0), Some(Direction::Degrees(90.0)), None);
+        history.push(
+            start,
+            Wind::Kph(10.0),
+            Some(Direction::Degrees(270.0)),
+            None,
+        );
+        let sustained = history.sustained(Window::TenMinutes, start).unwrap();
+        assert_eq!(sustained.direction, None);
+    }
+
+    #[test]
+    fn sustained_windows() {
+        let start = Utc.with_ymd_and_hms(2024, 7, 14, 12, 0, 0).unwrap();
+        let mut history = WindHistory::new();
+
+        // Ten minutes at 10 km/h, then two minutes at 30 km/h gusting to 45 km/h
+        for minute in 0..12 {
+            let (speed, gust) = if minute < 10 {
+                (10.0, None)
+            } else {
+                (30.0, Some(Wind::Kph(45.0)))
+            };
+
+            history.record(&Payload::new(
+                start + TimeDelta::minutes(minute + 1),
+                [
+                    Modality::Wind(Wind::Kph(speed)),
+                    Modality::WindDirection(Direction::Degrees(225.0)),
+                ]
+                .into_iter()
+                .chain(gust.map(Modality::WindGust)),
+            ));
+        }
+
+        let now = start + TimeDelta::minutes(12);
+        let two = history.sustained(Window::TwoMinutes, now).unwrap();
+        assert!(matches!(two.speed, Wind::Kph(_)));
+        assert_abs_diff_eq!(two.speed.value(), 30.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(two.gust.value(), 45.0, epsilon = 1e-3);
+        assert_eq!(two.direction.unwrap().compass(), Compass::SW);
+
+        let ten = history.sustained(Window::TenMinutes, now).unwrap();
+        assert_abs_diff_eq!(ten.speed.value(), 14.0, epsilon = 1e-3);
+        assert_eq!(ten.readouts().count(), 3);
+
+        // Samples older than the longest window are evicted
+        assert_eq!(
+            history.sustained(Window::TenMinutes, start + TimeDelta::minutes(2)),
+            None
+        );
+        assert_eq!(
+            history.sustained(Window::TwoMinutes, now + TimeDelta::hours(1)),
+            None
+        );
+    }
+}